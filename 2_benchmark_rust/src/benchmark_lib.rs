@@ -2,8 +2,13 @@
 use std::error::Error;
 use std::fs::{create_dir, OpenOptions};
 use std::path::Path;
-use raster::Float;
-use raster::Point;
+use raster::ClusterParams;
+use raster::{Point, Tile};
+use raster::par::PhaseTimes;
+use raster::par::blocks::Block;
+use raster::{KeyOrder, Projection};
+use raster::Tiling;
+use raster::pyramid::Pyramid;
 
 
 macro_rules! timeit {
@@ -31,6 +36,9 @@ pub struct CsvFormat {
     pub nr_clusters: usize,
     pub nr_clusters_percent: f32,
     pub nr_cores: usize,
+    pub projection: String,
+    pub merge_mean: f64,
+    pub merge_times: Vec<f64>,
 }
 
 impl CsvFormat {
@@ -47,6 +55,9 @@ impl CsvFormat {
             self.clust_std_dev.to_string(),
             format!("{:?}", self.proj_times).replace("\"", ""),
             format!("{:?}", self.clust_times).replace("\"", ""),
+            self.projection.clone(),
+            self.merge_mean.to_string(),
+            format!("{:?}", self.merge_times).replace("\"", ""),
         ]
     }
 
@@ -63,6 +74,52 @@ impl CsvFormat {
             "sample std_dev clustering [s]",
             "times projection [s]",
             "times clustering [s]",
+            "projection",
+            "mean merging [s]",
+            "times merging [s]",
+        ]
+    }
+}
+
+
+/// One row of a parameter sweep, see [`sweep_iter`](fn.sweep_iter.html).
+pub struct SweepFormat {
+    pub nr_clusters: usize,
+    pub threshold: usize,
+    pub min_size: usize,
+    pub nr_clusters_ident: usize,
+    pub nr_significant_tiles: usize,
+    pub nr_clustered_tiles: usize,
+    pub proj_secs: f64,
+    pub clust_secs: f64,
+}
+
+impl SweepFormat {
+    pub fn mk_record(&self) -> Vec<String> {
+        vec![
+            self.nr_clusters.to_string(),
+            self.threshold.to_string(),
+            self.min_size.to_string(),
+            self.nr_clusters_ident.to_string(),
+            (self.nr_clusters_ident as f32 / self.nr_clusters as f32).to_string(),
+            self.nr_significant_tiles.to_string(),
+            self.nr_clustered_tiles.to_string(),
+            self.proj_secs.to_string(),
+            self.clust_secs.to_string(),
+        ]
+    }
+
+    pub fn header() -> Vec<&'static str> {
+        vec![
+            "#clusters",
+            "threshold",
+            "min size",
+            "#clusters identified",
+            "#clusters identified [ratio]",
+            "#significant tiles",
+            "#clustered tiles",
+            "projection [s]",
+            "clustering [s]",
         ]
     }
 }
@@ -77,36 +134,166 @@ pub enum Raster {
 }
 
 
+/// How the concurrent versions cut the tiles into slices of columns.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Split {
+    /// Slices of equal width over -180..180 degrees.
+    EqualWidth,
+    /// Slices of equal width over the columns of the tiles, see `raster::par::split_by_extent`.
+    Extent,
+    /// Slices with about as many tiles, see `raster::par::split_balanced`.
+    Quantiles,
+    /// A grid of blocks with about as many tiles per column and per row of blocks,
+    /// see `raster::par::blocks::split_into_blocks`.
+    Blocks,
+}
+
+
+/// The tiles of the concurrent versions, cut by a `Split`.
+enum Parts<S> {
+    Slices(Vec<(i32, S, i32)>),
+    Blocks(Vec<Block<S>>),
+}
+
+impl<S> Parts<S> {
+    fn sizes(&self, len: impl Fn(&S) -> usize) -> Vec<usize> {
+        match self {
+            Parts::Slices(slices) => slices.iter().map(|s| len(&s.1)).collect(),
+            Parts::Blocks(blocks) => blocks.iter().map(|b| len(&b.tiles)).collect(),
+        }
+    }
+}
+
+
+/// The number of columns and rows of the most square grid of `nr_blocks` blocks.
+fn block_grid(nr_blocks: usize) -> (usize, usize) {
+    let nr_rows = (1..=nr_blocks).take_while(|r| r * r <= nr_blocks).filter(|r| nr_blocks % r == 0).last().unwrap_or(1);
+    (nr_blocks / nr_rows, nr_rows)
+}
+
+
 /// Run multiple iterations of RASTER and return a benchmark summary.
+#[allow(clippy::too_many_arguments)]
 pub fn cluster_iter(
     algorithm_choice: &Raster,
-    points: &Vec<Point>,
+    points: &[Point],
     nr_clusters: usize,
-    precision: Float,
+    tiling: &Tiling,
     threshold: usize,
     nr_cores: usize,
-    min_size: usize,
+    params: &ClusterParams,
+    projection: Projection,
+    split: Split,
     iterations: usize,
 ) -> CsvFormat {
-    println!("------------------------------\nRunning: {:?}, p={}, #cores={}\n",
-        algorithm_choice, precision, nr_cores);
+    println!("------------------------------\nRunning: {:?}, {:?}, {:?}, {:?}, {:?}, {:?}, #cores={}\n",
+        algorithm_choice, tiling, projection, params.engine, split, params.merge, nr_cores);
 
     let mut proj_secs = Vec::new();
     let mut clust_secs = Vec::new();
+    let mut merge_secs = Vec::new();
     let mut identifications = Vec::new();
+    // the concurrent versions run on a pool of exactly nr_cores threads, shared by all iterations
+    let pool = match algorithm_choice {
+        Raster::Par | Raster::ParPrime => Some(raster::par::ThreadPoolBuilder::new()
+            .num_threads(nr_cores)
+            .build()
+            .expect("could not build the thread pool")),
+        Raster::Seq | Raster::SeqPrime => None,
+    };
 
     for _ in 0..iterations {
-        let (proj_ms, clust_ms, n_clusters_ident) = match algorithm_choice {
-            Raster::Seq      => seq_raster(&points, precision, threshold, min_size),
-            Raster::SeqPrime => seq_raster_prime(&points, precision, threshold, min_size),
-            Raster::Par      => par_raster(points, precision, threshold, nr_cores, min_size),
-            Raster::ParPrime => par_raster_prime(points, precision, threshold, nr_cores, min_size),
+        let (proj_ms, clust_ms, merge_ms, n_clusters_ident) = match (algorithm_choice, &pool) {
+            (Raster::Seq, _)               => seq_raster(points, tiling, threshold, params, projection),
+            (Raster::SeqPrime, _)          => seq_raster_prime(points, tiling, threshold, params, projection),
+            (Raster::Par, Some(pool))      => pool.install(|| par_raster(points, tiling, threshold, nr_cores, params, projection, split)),
+            (Raster::ParPrime, Some(pool)) => pool.install(|| par_raster_prime(points, tiling, threshold, nr_cores, params, projection, split)),
+            (Raster::Par | Raster::ParPrime, None) => unreachable!("the concurrent versions have a pool"),
         };
         proj_secs.push(proj_ms as f64 / 1_000_000.);
         clust_secs.push(clust_ms as f64 / 1_000_000.);
+        merge_secs.push(merge_ms as f64 / 1_000_000.);
         identifications.push(n_clusters_ident);
     }
 
+    let row = summarize(proj_secs, clust_secs, merge_secs, identifications, nr_clusters, nr_cores, projection_name(projection));
+    if let Raster::Par | Raster::ParPrime = algorithm_choice {
+        println!("Average time of merging the seams: {:.4} s\n", row.merge_mean);
+    }
+    row
+}
+
+
+/// Run multiple iterations of the sequential RASTER on the levels of pyramids of tile counts, see
+/// `raster::pyramid::Pyramid`, and return a benchmark summary per tiling, in the order of `tilings`.
+/// The points are projected onto the finest tiling of each pyramid, and the counts of the coarser
+/// tilings that nest in it are derived from a finer level. A tiling that does not nest in any
+/// pyramid so far starts a new one.
+pub fn pyramid_iter(
+    points: &[Point],
+    nr_clusters: usize,
+    tilings: &[Tiling],
+    threshold: usize,
+    params: &ClusterParams,
+    iterations: usize,
+) -> Vec<CsvFormat> {
+    println!("------------------------------\nRunning: Seq, pyramid of {:?}, {:?}\n", tilings, params.engine);
+
+    // from the finest to the coarsest, so that every level can be derived from a finer one
+    let mut order: Vec<usize> = (0..tilings.len()).collect();
+    order.sort_by(|&a, &b| tilings[b].scalars[0].total_cmp(&tilings[a].scalars[0]));
+
+    let mut proj_secs = vec![Vec::new(); tilings.len()];
+    let mut clust_secs = vec![Vec::new(); tilings.len()];
+    let mut identifications = vec![Vec::new(); tilings.len()];
+    for _ in 0..iterations {
+        let mut pyramids: Vec<Pyramid> = Vec::new();
+        for &i in order.iter() {
+            println!("{:?}", tilings[i]);
+            let (tiles, proj_microsec) = timeit!("Projection: {} ms",
+                match pyramids.iter_mut().find_map(|p| p.insert(tilings[i]).ok().map(|l| l.tiles(threshold))) {
+                    Some(tiles) => tiles,
+                    None => {
+                        let pyramid = Pyramid::from_points(points, tilings[i]);
+                        let tiles = pyramid.levels[0].tiles(threshold);
+                        pyramids.push(pyramid);
+                        tiles
+                    },
+                }
+            );
+            let (clusters, clust_microsec) = timeit!("Clustering: {} ms",
+                raster::cluster_tiles_with(tiles, params)
+            );
+            proj_secs[i].push(proj_microsec as f64 / 1_000_000.);
+            clust_secs[i].push(clust_microsec as f64 / 1_000_000.);
+            identifications[i].push(clusters.len());
+        }
+    }
+
+    proj_secs.into_iter()
+        .zip(clust_secs)
+        .zip(identifications)
+        .zip(tilings)
+        .map(|(((proj_secs, clust_secs), identifications), tiling)| {
+            println!("\n{:?}", tiling);
+            let merge_secs = vec![0.; proj_secs.len()];
+            summarize(proj_secs, clust_secs, merge_secs, identifications, nr_clusters, 1, "pyramid")
+        })
+        .collect()
+}
+
+
+/// Prints the mean and standard deviation of the times of the iterations of a benchmark and
+/// returns them as a csv row.
+fn summarize(
+    proj_secs: Vec<f64>,
+    clust_secs: Vec<f64>,
+    merge_secs: Vec<f64>,
+    mut identifications: Vec<usize>,
+    nr_clusters: usize,
+    nr_cores: usize,
+    projection: &str,
+) -> CsvFormat {
     let proj_avg = mean(&proj_secs);
     let proj_std_dev = std_dev(&proj_secs, proj_avg);
     let clust_avg = mean(&clust_secs);
@@ -117,7 +304,7 @@ pub fn cluster_iter(
         proj_std_dev, clust_std_dev);
 
     let avg = proj_avg + clust_avg;
-    let secs = proj_secs.iter().zip(&clust_secs).map(|(x, y)| x+y).collect();
+    let secs: Vec<f64> = proj_secs.iter().zip(&clust_secs).map(|(x, y)| x+y).collect();
     let sd = std_dev(&secs, avg);
     println!("Total average time: {:.3} s\n", avg);
 
@@ -128,76 +315,229 @@ pub fn cluster_iter(
         mean:                avg,
         std_dev:             sd,
         proj_mean:           proj_avg,
-        proj_std_dev:        proj_std_dev,
+        proj_std_dev,
         proj_times:          proj_secs,
         clust_mean:          clust_avg,
-        clust_std_dev:       clust_std_dev,
+        clust_std_dev,
         clust_times:         clust_secs,
-        nr_clusters:         nr_clusters,
+        nr_clusters,
         nr_clusters_percent: n_clusters_ident as f32 / nr_clusters as f32,
-        nr_cores:            nr_cores,
+        nr_cores,
+        projection:          projection.to_string(),
+        merge_mean:          mean(&merge_secs),
+        merge_times:         merge_secs,
     }
 }
 
 
-fn seq_raster(points: &Vec<Point>, precision: Float, threshold: usize, min_cluster_size: usize) -> (u128, u128, usize) {
-    let ((tiles, _scalar), proj_microsec) = timeit!("Projection: {} ms",
-        raster::map_to_tiles(points, precision, threshold)
+/// The name of `projection` in the csv files and on the command line.
+pub fn projection_name(projection: Projection) -> &'static str {
+    match projection {
+        Projection::Hash                        => "hash",
+        Projection::Sort(KeyOrder::RowMajor)    => "row-major",
+        Projection::Sort(KeyOrder::Morton)      => "morton",
+    }
+}
+
+
+/// Project once and cluster every combination of `thresholds` and `min_sizes`.
+/// The clustering time of a threshold is shared by all its minimum sizes.
+pub fn sweep_iter(
+    points: &[Point],
+    nr_clusters: usize,
+    tiling: &Tiling,
+    thresholds: &[usize],
+    min_sizes: &[usize],
+) -> Vec<SweepFormat> {
+    println!("------------------------------\nSweeping: {:?}\n", tiling);
+
+    let (counts, proj_microsec) = timeit!("Projection: {} ms",
+        raster::tile_counts_with(points, tiling)
+    );
+
+    let mut rows = Vec::with_capacity(thresholds.len() * min_sizes.len());
+    for &threshold in thresholds {
+        let (results, clust_microsec) = timeit!("Clustering: {} ms",
+            raster::sweep::cluster_sweep(&counts, &[threshold], min_sizes)
+        );
+        let nr_significant_tiles = counts.values().filter(|c| **c >= threshold).count();
+        for result in results {
+            rows.push(SweepFormat {
+                nr_clusters,
+                threshold,
+                min_size: result.min_cluster_size,
+                nr_clusters_ident: result.clusters.len(),
+                nr_significant_tiles,
+                nr_clustered_tiles: result.clusters.iter().map(|c| c.len()).sum(),
+                proj_secs: proj_microsec as f64 / 1_000_000.,
+                clust_secs: clust_microsec as f64 / 1_000_000.,
+            });
+        }
+    }
+    rows
+}
+
+
+fn seq_raster(
+    points: &[Point],
+    tiling: &Tiling,
+    threshold: usize,
+    params: &ClusterParams,
+    projection: Projection,
+) -> (u128, u128, u128, usize) {
+    if let Projection::Sort(order) = projection {
+        // the sorted tiles are clustered by scanning them in order
+        let (tiles, proj_microsec) = timeit!("Projection: {} ms",
+            raster::sorted::map_to_sorted_tiles(points, tiling, threshold, order)
+        );
+        let (clusters, clust_microsec) = timeit!("Clustering: {} ms",
+            raster::sorted::cluster_sorted_tiles(&tiles, params)
+        );
+        return (proj_microsec, clust_microsec, 0, clusters.len());
+    }
+    let (tiles, proj_microsec) = timeit!("Projection: {} ms",
+        raster::map_to_tiles_with(points, tiling, threshold)
     );
     let (clusters, clust_microsec) = timeit!("Clustering: {} ms",
-        raster::cluster_tiles(tiles, min_cluster_size)
+        raster::cluster_tiles_with(tiles, params)
     );
-    (proj_microsec, clust_microsec, clusters.len())
+    (proj_microsec, clust_microsec, 0, clusters.len())
 }
 
 
-fn seq_raster_prime(points: &Vec<Point>, precision: Float, threshold: usize, min_cluster_size: usize) -> (u128, u128, usize) {
-    let ((tiles, _scalar), proj_microsec) = timeit!("Projection: {} ms",
-        raster::prime::map_to_tiles(points, precision, threshold as usize)
+fn seq_raster_prime(
+    points: &[Point],
+    tiling: &Tiling,
+    threshold: usize,
+    params: &ClusterParams,
+    projection: Projection,
+) -> (u128, u128, u128, usize) {
+    let (tiles, proj_microsec) = timeit!("Projection: {} ms",
+        raster::prime::map_to_tiles_using(points, tiling, threshold, projection)
     );
     let (clusters, clust_microsec) = timeit!("Clustering: {} ms",
-        raster::prime::cluster_tiles(tiles, min_cluster_size)
+        raster::prime::cluster_tiles_with(tiles, params)
     );
-    (proj_microsec, clust_microsec, clusters.len())
+    (proj_microsec, clust_microsec, 0, clusters.len())
 }
 
 
-fn par_raster(points: &Vec<Point>, precision: Float, threshold: usize, nr_cores:usize, min_cluster_size: usize) -> (u128, u128, usize) {
-    let ((slices, _scalar), proj_microsec) = timeit!("Projection: {} ms",{
-        let (tiles, scalar) = raster::par::map_to_tiles(points, precision, threshold, nr_cores);
-        let slices = raster::par::split_vertically(tiles, -180, 180, scalar, nr_cores);
-        (slices, scalar)
-    });
-    let (clusters, clust_microsec) = timeit!("Clustering: {} ms",
-        raster::par::cluster_tiles(slices, min_cluster_size)
+fn par_raster(
+    points: &[Point],
+    tiling: &Tiling,
+    threshold: usize,
+    nr_cores: usize,
+    params: &ClusterParams,
+    projection: Projection,
+    split: Split,
+) -> (u128, u128, u128, usize) {
+    let split_tiles = |tiles: Box<dyn Iterator<Item = Tile>>| match split {
+        Split::EqualWidth => Parts::Slices(raster::par::split_vertically(tiles, -180, 180, tiling.scalars[0], nr_cores)),
+        Split::Extent => Parts::Slices(raster::par::split_by_extent(tiles, nr_cores)),
+        Split::Quantiles => Parts::Slices(raster::par::split_balanced(tiles, nr_cores).0),
+        Split::Blocks => {
+            let (nr_columns, nr_rows) = block_grid(nr_cores);
+            Parts::Blocks(raster::par::blocks::split_into_blocks(tiles, nr_columns, nr_rows))
+        },
+    };
+    let (slices, proj_microsec) = timeit!("Projection: {} ms",
+        match projection {
+            Projection::Hash => {
+                split_tiles(Box::new(raster::par::map_to_tiles_with(points, tiling, threshold, nr_cores)))
+            },
+            Projection::Sort(order) => {
+                let counts = raster::par::sorted_tile_counts(points, tiling, order, nr_cores);
+                split_tiles(Box::new(counts.significant(threshold).tiles()))
+            },
+        }
     );
-    (proj_microsec, clust_microsec, clusters.len())
+    println!("Tiles per part: {:?}", slices.sizes(|tiles| tiles.len()));
+    let ((clusters, phases), clust_microsec) = timeit!("Clustering: {} ms",
+        match slices {
+            Parts::Slices(slices) => raster::par::cluster_tiles_timed(slices, params),
+            Parts::Blocks(blocks) => raster::par::blocks::cluster_blocks_timed(blocks, params),
+        }
+    );
+    print_phases(&phases);
+    (proj_microsec, clust_microsec, phases.merge.as_micros(), clusters.len())
 }
 
 
-fn par_raster_prime(points: &Vec<Point>, precision: Float, threshold: usize, nr_cores:usize, min_cluster_size: usize) -> (u128, u128, usize) {
-    let ((slices, _scalar), proj_microsec) = timeit!("Projection: {} ms",{
-        let (tiles, scalar) = raster::prime::par::map_to_tiles(points, precision, threshold, nr_cores);
-        let slices = raster::prime::par::split_vertically(tiles, -180, 180, scalar, nr_cores);
-        (slices, scalar)
-    });
-    let (clusters, clust_microsec) = timeit!("Clustering: {} ms",
-        raster::prime::par::cluster_tiles(slices, min_cluster_size)
+fn par_raster_prime(
+    points: &[Point],
+    tiling: &Tiling,
+    threshold: usize,
+    nr_cores: usize,
+    params: &ClusterParams,
+    projection: Projection,
+    split: Split,
+) -> (u128, u128, u128, usize) {
+    let split_tiles = |tiles: Box<dyn Iterator<Item = (Tile, Vec<Point>)>>| match split {
+        Split::EqualWidth => Parts::Slices(raster::prime::par::split_vertically(tiles, -180, 180, tiling.scalars[0], nr_cores)),
+        Split::Extent => Parts::Slices(raster::prime::par::split_by_extent(tiles, nr_cores)),
+        Split::Quantiles => Parts::Slices(raster::prime::par::split_balanced(tiles, nr_cores).0),
+        Split::Blocks => {
+            let (nr_columns, nr_rows) = block_grid(nr_cores);
+            Parts::Blocks(raster::prime::par::split_into_blocks(tiles, nr_columns, nr_rows))
+        },
+    };
+    let (slices, proj_microsec) = timeit!("Projection: {} ms",
+        match projection {
+            Projection::Hash => {
+                split_tiles(Box::new(raster::prime::par::map_to_tiles_with(points, tiling, threshold, nr_cores)))
+            },
+            Projection::Sort(order) => {
+                let tiles = raster::prime::par::map_to_sorted_tiles_with(points, tiling, threshold, order);
+                split_tiles(Box::new(tiles.into_iter()))
+            },
+        }
+    );
+    println!("Tiles per part: {:?}", slices.sizes(|tiles| tiles.len()));
+    let ((clusters, phases), clust_microsec) = timeit!("Clustering: {} ms",
+        match slices {
+            Parts::Slices(slices) => raster::prime::par::cluster_tiles_timed(slices, params),
+            Parts::Blocks(blocks) => raster::prime::par::cluster_blocks_timed(blocks, params),
+        }
     );
-    (proj_microsec, clust_microsec, clusters.len())
+    print_phases(&phases);
+    (proj_microsec, clust_microsec, phases.merge.as_micros(), clusters.len())
+}
+
+
+/// Prints the time of each phase of a concurrent clustering [ms].
+fn print_phases(phases: &PhaseTimes) {
+    println!("\tLocal clustering: {} ms\n\tMerging: {} ms",
+        phases.local.as_micros() as f64 / 1_000., phases.merge.as_micros() as f64 / 1_000.);
 }
 
 
 pub fn write_bench_times<P: AsRef<Path>>(
     csv_row: CsvFormat,
     path: P,
-) -> Result<(), Box<Error>> {
+) -> Result<(), Box<dyn Error>> {
+    append_records(path, CsvFormat::header(), vec![csv_row.mk_record()])
+}
+
+
+pub fn write_sweep_rows<P: AsRef<Path>>(
+    rows: Vec<SweepFormat>,
+    path: P,
+) -> Result<(), Box<dyn Error>> {
+    append_records(path, SweepFormat::header(), rows.iter().map(|r| r.mk_record()).collect())
+}
+
+
+/// Appends `records` to the csv file at `path`, which gets `header` if it is new.
+fn append_records<P: AsRef<Path>>(
+    path: P,
+    header: Vec<&str>,
+    records: Vec<Vec<String>>,
+) -> Result<(), Box<dyn Error>> {
     if let Some(dir) = path.as_ref().parent() {
         let _ = create_dir(dir);
     }
     let add_header = !path.as_ref().exists();
     let writer = OpenOptions::new()
-        .write(true)
         .append(true)
         .create(true)
         .open(path)?;
@@ -206,23 +546,25 @@ pub fn write_bench_times<P: AsRef<Path>>(
         .from_writer(writer);
 
     if add_header {
-        wtr.write_record(CsvFormat::header())?;
+        wtr.write_record(header)?;
+    }
+    for record in records {
+        wtr.write_record(record)?;
     }
-    wtr.write_record(csv_row.mk_record())?;
     wtr.flush()?;
     Ok(())
 }
 
 
-fn mean(numbers: &Vec<f64>) -> f64 {
+fn mean(numbers: &[f64]) -> f64 {
     let sum: f64 = numbers.iter().sum();
-    sum as f64 / numbers.len() as f64
+    sum / numbers.len() as f64
 }
 
 /// sample standard deviation:
 /// √(Σ(x-µ)²/(n-1))
-fn std_dev(numbers: &Vec<f64>, mean: f64) -> f64 {
-    let sum: f64 = numbers.iter().map(|x| (*x as f64 - mean).powi(2)).sum();
+fn std_dev(numbers: &[f64], mean: f64) -> f64 {
+    let sum: f64 = numbers.iter().map(|x| (*x - mean).powi(2)).sum();
     (sum / (numbers.len() - 1) as f64).sqrt()
 }
 
@@ -8,7 +8,7 @@ use std::path::Path;
 
 #[allow(dead_code)]
 /// Reads a CSV file without header and two real numbers per row (e.g. "10.42, 1080.360").
-pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<Point>, Box<Error>> {
+pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<Point>, Box<dyn Error>> {
     let mut rdr = csv::ReaderBuilder::new()
         .has_headers(false)
         .from_path(path)?;
@@ -24,7 +24,7 @@ pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<Point>, Box<Error>> {
 
 
 /// Reads a CSV file without header and two real numbers per row (e.g. "10.42, 1080.360").
-pub fn parallel_read<P: AsRef<Path>>(path: P) -> Result<Vec<Point>, Box<Error>> {
+pub fn parallel_read<P: AsRef<Path>>(path: P) -> Result<Vec<Point>, Box<dyn Error>> {
     let contents = fs::read_to_string(path)?;
 
     Ok(
@@ -45,17 +45,16 @@ pub fn parallel_read<P: AsRef<Path>>(path: P) -> Result<Vec<Point>, Box<Error>>
 
 #[allow(dead_code)]
 /// Write clusters to output/clustered.csv containing a cluster label for each tile.
-pub fn write_clusters(clusters: Vec<TileSet>, scalar: Float) -> Result<(), Box<Error>> {
+pub fn write_clusters(clusters: Vec<TileSet>, scalar: Float) -> Result<(), Box<dyn Error>> {
     let mut wtr = csv::Writer::from_path("output/clustered.csv")?;
 
-    let mut cluster_nr = 1;
-    for c in clusters {
+    for (i, c) in clusters.into_iter().enumerate() {
+        let cluster_nr = i + 1;
         for (x, y) in c {
             let x = (x as Float / scalar).to_string();
             let y = (y as Float / scalar).to_string();
             wtr.write_record(&[cluster_nr.to_string(), x, y])?;
         }
-        cluster_nr += 1;
     }
 
     wtr.flush()?;
@@ -79,6 +78,112 @@ mod tests {
         assert_eq!(tiles_seq, tiles_par);
     }
 
+    #[test]
+    fn par_eq_counts_test() {
+        let points = read("../0_data_generators/data_100_shuffled.csv").unwrap();
+
+        let (counts_seq, scalar) = raster::map_to_tile_counts(&points, 4., 5);
+        let (counts_par, _) = raster::par::map_to_tile_counts(&points, 4., 5, 4);
+        assert_eq!(counts_seq, counts_par);
+
+        let all_par = raster::par::tile_counts_with(&points, &raster::Tiling::from_scalars([scalar; 2]), 4);
+        assert_eq!(all_par, raster::tile_counts(&points, 4.).0);
+        assert_eq!(all_par.values().sum::<usize>(), points.len());
+    }
+
+    #[test]
+    fn significance_test() {
+        let points = read("../0_data_generators/data_100_shuffled.csv").unwrap();
+        let rules = [
+            raster::Significance::Count(5),
+            raster::Significance::Percentile(90.),
+            raster::Significance::Top(500),
+            raster::Significance::TimesMean(2.),
+        ];
+        for rule in rules.iter() {
+            let (tiles_seq, _, threshold) = raster::map_to_significant_tiles(&points, 4., rule);
+            let (tiles_par, _, threshold_par) = raster::par::map_to_significant_tiles(&points, 4., rule, 4);
+            assert_eq!(threshold, threshold_par);
+            assert_eq!(tiles_seq, tiles_par.collect::<IndexSet<_>>());
+            assert_eq!(tiles_seq, raster::map_to_tiles(&points, 4., threshold).0);
+        }
+    }
+
+    #[test]
+    fn weighted_test() {
+        let points = read("../0_data_generators/data_100_shuffled.csv").unwrap();
+        let weighted: Vec<_> = points
+            .iter()
+            .enumerate()
+            .map(|(i, p)| raster::WeightedPoint::from_point(*p, (i % 4) as f64 * 0.5))
+            .collect();
+
+        let (tiles_seq, _) = raster::map_to_weighted_tiles(&weighted, 4., 7.5);
+        let tiles_par: IndexSet<_> = raster::par::map_to_weighted_tiles(&weighted, 4., 7.5, 4).0.collect();
+        assert_eq!(tiles_seq, tiles_par);
+        let weights = raster::par::tile_weights(&weighted, 4., 4).0;
+        assert_eq!(weights.len(), raster::tile_weights(&weighted, 4.).0.len());
+
+        let (tile_map, _) = raster::prime::map_to_weighted_tiles(&weighted, 4., 7.5);
+        assert_eq!(tile_map.keys().cloned().collect::<IndexSet<_>>(), tiles_seq);
+        let clusters = raster::prime::cluster_weighted_tiles(tile_map, 4);
+        assert_eq!(clusters.len(), raster::cluster_tiles(tiles_seq, 4).len());
+        for cluster in clusters.iter() {
+            for (tile, points) in cluster {
+                let weight: f64 = points.iter().map(|p| p.weight()).sum();
+                assert!(weight >= 7.5);
+                assert!((weight - weights[tile]).abs() < 1e-9);
+            }
+        }
+
+        // unit weights are the same as counting the points
+        let ones: Vec<_> = points.iter().map(|p| raster::WeightedPoint::from_point(*p, 1.)).collect();
+        assert_eq!(raster::map_to_weighted_tiles(&ones, 4., 5.).0, raster::map_to_tiles(&points, 4., 5).0);
+    }
+
+    #[test]
+    fn sorted_projection_test() {
+        use raster::{KeyOrder, Projection, Tiling};
+
+        let points = read("../0_data_generators/data_100_shuffled.csv").unwrap();
+        let tiling = Tiling::new(4.);
+        for &order in [KeyOrder::RowMajor, KeyOrder::Morton].iter() {
+            let counts = raster::par::sorted_tile_counts(&points, &tiling, order, 4);
+            assert_eq!(counts, raster::sorted::sorted_tile_counts(&points, &tiling, order));
+            assert_eq!(counts.to_tile_counts(), raster::tile_counts_with(&points, &tiling));
+
+            let tiles = raster::map_to_tiles_using(&points, &tiling, 5, Projection::Sort(order));
+            assert_eq!(tiles, raster::map_to_tiles_with(&points, &tiling, 5));
+            let clusters = raster::sorted::cluster_sorted_tiles(&counts.significant(5), &raster::ClusterParams::new(4));
+            assert_eq!(clusters.len(), raster::cluster_tiles(tiles, 4).len());
+
+            let tile_map = raster::prime::par::map_to_sorted_tiles_with(&points, &tiling, 5, order);
+            assert_eq!(tile_map, raster::prime::map_to_tiles_with(&points, &tiling, 5));
+        }
+    }
+
+    #[test]
+    fn suggest_test() {
+        let points = read("../0_data_generators/data_100_shuffled.csv").unwrap();
+        let precisions = [2., 2.5, 3., 3.5, 4., 4.5, 5., 5.5];
+        let suggestion = raster::suggest::suggest(&points, &precisions).unwrap();
+
+        let precision = precisions[suggestion.candidate];
+        let (tiles, _) = raster::map_to_tiles(&points, precision, suggestion.threshold);
+        let clusters = raster::cluster_tiles(tiles, suggestion.min_cluster_size);
+        assert_eq!(clusters.len(), 100);
+    }
+
+    #[test]
+    fn pyramid_eq_projection_test() {
+        let points = read("../0_data_generators/data_100_shuffled.csv").unwrap();
+        let pyramid = raster::pyramid::Pyramid::from_precisions(&points, &[3., 5., 4.]).unwrap();
+
+        for level in pyramid.levels.iter() {
+            assert_eq!(level.counts, raster::tile_counts_with(&points, &level.tiling));
+        }
+    }
+
     #[test]
     fn test_two_core_map_to_tiles_slices(){
         let points = parallel_read("../0_data_generators/data_100_shuffled.csv").unwrap();
@@ -128,10 +233,264 @@ mod tests {
 
         assert_eq!(clusters_par.len(), clusters_seq.len());
         for c in clusters_par {
-            assert!(clusters_seq.iter().any(|set| *set == c));
+            assert!(clusters_seq.contains(&c));
         }
     }
 
+    #[test]
+    fn test_para_min_points(){
+        use raster::ClusterParams;
+
+        let points = parallel_read("../0_data_generators/data_100_shuffled.csv").unwrap();
+        let precision = 4.;
+        let nr_slices = 16;
+        let params = ClusterParams::new(2).with_min_points(40);
+
+        let (counts, scalar) = raster::par::map_to_tile_counts(&points, precision, 3, 4);
+        let slices = raster::prime::par::split_vertically(counts.clone().into_iter(), -180, 180, scalar, nr_slices);
+        let clusters_par = raster::par::cluster_tile_counts_with(slices, &params);
+        let clusters_seq = raster::cluster_tile_counts_with(counts, &params);
+        assert!(!clusters_seq.is_empty());
+        assert!(clusters_seq.len() < raster::cluster_tile_counts_with(
+            raster::map_to_tile_counts(&points, precision, 3).0, &ClusterParams::new(2)).len());
+        assert_eq!(clusters_par.len(), clusters_seq.len());
+        for c in clusters_par.iter() {
+            assert!(c.values().sum::<usize>() >= 40);
+            assert!(clusters_seq.contains(c));
+        }
+
+        let (tiles, scalar) = raster::prime::par::map_to_tiles(&points, precision, 3, 4);
+        let slices = raster::prime::par::split_vertically(tiles, -180, 180, scalar, nr_slices);
+        let clusters_prime = raster::prime::par::cluster_tiles_with(slices, &params);
+        assert_eq!(clusters_prime.len(), clusters_seq.len());
+        for c in clusters_prime {
+            let counts: raster::TileCounts = c.iter().map(|(tile, ps)| (*tile, ps.len())).collect();
+            assert!(clusters_seq.contains(&counts));
+        }
+    }
+
+    #[test]
+    fn test_para_union_find(){
+        use raster::{ClusterEngine, ClusterParams, Connectivity};
+
+        let points = parallel_read("../0_data_generators/data_100_shuffled.csv").unwrap();
+        let precision = 4.;
+        let threshold = 3;
+        for &connectivity in [Connectivity::Eight, Connectivity::Radius(3)].iter() {
+            let bfs = ClusterParams::new(4).with_connectivity(connectivity).with_engine(ClusterEngine::Bfs);
+            let params = bfs.with_engine(ClusterEngine::UnionFind);
+            let (tiles_seq, _) = raster::map_to_tiles(&points, precision, threshold);
+            let clusters_bfs = raster::cluster_tiles_with(tiles_seq.clone(), &bfs);
+            let clusters_seq = raster::cluster_tiles_with(tiles_seq, &params);
+            assert_eq!(clusters_seq.len(), clusters_bfs.len());
+            for c in clusters_seq.iter() {
+                assert!(clusters_bfs.contains(c));
+            }
+
+            for &nr_slices in [2, 16].iter() {
+                let (tiles, scalar) = raster::par::map_to_tiles(&points, precision, threshold, 4);
+                let slices = raster::par::split_vertically(tiles, -180, 180, scalar, nr_slices);
+                let clusters_par = raster::par::cluster_tiles_with(slices, &params);
+                assert_eq!(clusters_par.len(), clusters_bfs.len());
+                for c in clusters_par {
+                    assert!(clusters_bfs.contains(&c));
+                }
+
+                let (tiles, scalar) = raster::prime::par::map_to_tiles(&points, precision, threshold, 4);
+                let slices = raster::prime::par::split_vertically(tiles, -180, 180, scalar, nr_slices);
+                let clusters_prime = raster::prime::par::cluster_tiles_with(slices, &params);
+                assert_eq!(clusters_prime.len(), clusters_bfs.len());
+                for c in clusters_prime {
+                    let c: TileSet = c.keys().cloned().collect();
+                    assert!(clusters_bfs.contains(&c));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_para_balanced_split(){
+        let points = parallel_read("../0_data_generators/data_100_shuffled.csv").unwrap();
+        let precision = 3.5;
+        let threshold = 5;
+        let min_cluster_size = 4;
+        let (tiles_seq, _) = raster::map_to_tiles(&points, precision, threshold);
+        let clusters_seq = raster::cluster_tiles(tiles_seq.clone(), min_cluster_size);
+
+        for &nr_slices in [2, 4, 8].iter() {
+            let (tiles, _) = raster::par::map_to_tiles(&points, precision, threshold, 4);
+            let (slices, counts) = raster::par::split_balanced(tiles, nr_slices);
+            assert_eq!(counts.iter().sum::<usize>(), tiles_seq.len());
+            assert!(counts.len() <= nr_slices && counts.iter().all(|c| *c > 0));
+            let clusters_par = raster::par::cluster_tiles(slices, min_cluster_size);
+            assert_eq!(clusters_par.len(), clusters_seq.len());
+            for c in clusters_par {
+                assert!(clusters_seq.contains(&c));
+            }
+
+            let (tiles, _) = raster::prime::par::map_to_tiles(&points, precision, threshold, 4);
+            let (slices, counts_prime) = raster::prime::par::split_balanced(tiles, nr_slices);
+            assert_eq!(counts_prime, counts);
+            let clusters_prime = raster::prime::par::cluster_tiles(slices, min_cluster_size);
+            assert_eq!(clusters_prime.len(), clusters_seq.len());
+            for c in clusters_prime {
+                assert!(clusters_seq.contains(&c.keys().cloned().collect()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_para_blocks(){
+        let points = parallel_read("../0_data_generators/data_100_shuffled.csv").unwrap();
+        let precision = 3.5;
+        let threshold = 5;
+        let min_cluster_size = 4;
+        let (tiles_seq, _) = raster::map_to_tiles(&points, precision, threshold);
+        let clusters_seq = raster::cluster_tiles(tiles_seq, min_cluster_size);
+
+        for &(nr_columns, nr_rows) in [(1, 4), (2, 2), (4, 3)].iter() {
+            let (tiles, _) = raster::par::map_to_tiles(&points, precision, threshold, 4);
+            let blocks = raster::par::blocks::split_into_blocks(tiles, nr_columns, nr_rows);
+            assert_eq!(blocks.len(), nr_columns * nr_rows);
+            let clusters_par = raster::par::blocks::cluster_blocks(blocks, min_cluster_size);
+            assert_eq!(clusters_par.len(), clusters_seq.len());
+            for c in clusters_par {
+                assert!(clusters_seq.contains(&c));
+            }
+
+            let (tiles, _) = raster::prime::par::map_to_tiles(&points, precision, threshold, 4);
+            let blocks = raster::prime::par::split_into_blocks(tiles, nr_columns, nr_rows);
+            let clusters_prime = raster::prime::par::cluster_blocks(blocks, min_cluster_size);
+            assert_eq!(clusters_prime.len(), clusters_seq.len());
+            for c in clusters_prime {
+                assert!(c.values().all(|ps| ps.len() >= threshold));
+                assert!(clusters_seq.contains(&c.keys().cloned().collect()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_para_tree_merge(){
+        use raster::{ClusterParams, SeamMerge};
+
+        let points = parallel_read("../0_data_generators/data_100_shuffled.csv").unwrap();
+        let precision = 3.5;
+        let threshold = 5;
+        let params = ClusterParams::new(4);
+        let (tiles_seq, _) = raster::map_to_tiles(&points, precision, threshold);
+        let clusters_seq = raster::cluster_tiles_with(tiles_seq, &params);
+
+        for &nr_slices in [2, 5, 16, 64].iter() {
+            for &merge in [SeamMerge::Sequential, SeamMerge::Tree].iter() {
+                let (tiles, _) = raster::par::map_to_tiles(&points, precision, threshold, 4);
+                let (slices, _) = raster::par::split_balanced(tiles, nr_slices);
+                let (clusters_par, _) = raster::par::cluster_tiles_timed(slices, &params.with_merge(merge));
+                assert_eq!(clusters_par.len(), clusters_seq.len());
+                for c in clusters_par {
+                    assert!(clusters_seq.contains(&c));
+                }
+
+                let (tiles, _) = raster::prime::par::map_to_tiles(&points, precision, threshold, 4);
+                let (slices, _) = raster::prime::par::split_balanced(tiles, nr_slices);
+                let (clusters_prime, _) = raster::prime::par::cluster_tiles_timed(slices, &params.with_merge(merge));
+                assert_eq!(clusters_prime.len(), clusters_seq.len());
+                for c in clusters_prime {
+                    assert!(clusters_seq.contains(&c.keys().cloned().collect()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_dense(){
+        use raster::{ClusterEngine, ClusterParams, Connectivity};
+
+        let points = parallel_read("../0_data_generators/data_100_shuffled.csv").unwrap();
+        for &precision in [2.5, 4.].iter() {
+            let (tiles, _) = raster::map_to_tiles(&points, precision, 3);
+            for &connectivity in [Connectivity::Four, Connectivity::Eight].iter() {
+                let bfs = ClusterParams::new(2).with_connectivity(connectivity).with_engine(ClusterEngine::Bfs);
+                let clusters_bfs = raster::cluster_tiles_with(tiles.clone(), &bfs);
+                for &engine in [ClusterEngine::Dense, ClusterEngine::Auto].iter() {
+                    let clusters = raster::cluster_tiles_with(tiles.clone(), &bfs.with_engine(engine));
+                    assert_eq!(clusters.len(), clusters_bfs.len());
+                    for c in clusters.iter() {
+                        assert!(clusters_bfs.contains(c));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_total_para_raster_connectivity(){
+        use raster::{ClusterParams, Connectivity};
+
+        let points = parallel_read("../0_data_generators/data_100_shuffled.csv").unwrap();
+        let precision = 4.;
+        let threshold = 3;
+        let nr_slices = 16;
+        for &connectivity in [Connectivity::Four, Connectivity::Radius(3)].iter() {
+            let params = ClusterParams::new(4).with_connectivity(connectivity);
+            let (tiles, scalar) = raster::par::map_to_tiles(&points, precision, threshold, 4);
+            let slices = raster::par::split_vertically(tiles, -180, 180, scalar, nr_slices);
+            let clusters_par = raster::par::cluster_tiles_with(slices, &params);
+
+            let (tiles, scalar) = raster::prime::par::map_to_tiles(&points, precision, threshold, 4);
+            let slices = raster::prime::par::split_vertically(tiles, -180, 180, scalar, nr_slices);
+            let clusters_prime = raster::prime::par::cluster_tiles_with(slices, &params);
+
+            let (left_tiles, right_tiles, _) = raster::par::dual_clustering::map_to_tile_slices(&points, precision, threshold, 4);
+            let clusters_duo = raster::par::dual_clustering::cluster_tiles_with(left_tiles, right_tiles, &params);
+
+            // regular raster should give the correct result
+            let (tiles_seq, _) = raster::map_to_tiles(&points, precision, threshold);
+            let clusters_seq = raster::cluster_tiles_with(tiles_seq, &params);
+
+            assert_eq!(clusters_par.len(), clusters_seq.len());
+            for c in clusters_par {
+                assert!(clusters_seq.contains(&c));
+            }
+            assert_eq!(clusters_prime.len(), clusters_seq.len());
+            for c in clusters_prime {
+                let c: TileSet = c.keys().cloned().collect();
+                assert!(clusters_seq.contains(&c));
+            }
+            assert_eq!(clusters_duo.len(), clusters_seq.len());
+            for c in clusters_duo {
+                assert!(clusters_seq.contains(&c));
+            }
+        }
+    }
+
+    #[test]
+    fn test_para_fit_predict(){
+        let points = parallel_read("../0_data_generators/data_100_shuffled.csv").unwrap();
+        let labels_seq = raster::fit_predict(&points, 4., 5, 4);
+        assert!(labels_seq.iter().any(|l| l.is_some()));
+
+        for &nr_threads in [1, 4, 16].iter() {
+            let labels_par = raster::par::fit_predict(&points, 4., 5, 4, nr_threads);
+            assert_eq!(labels_par.len(), points.len());
+
+            // the clusters may come in another order, so compare the partitions
+            let mut renaming = std::collections::HashMap::new();
+            for (seq, par) in labels_seq.iter().zip(labels_par.iter()) {
+                assert_eq!(seq.is_some(), par.is_some());
+                if let (Some(seq), Some(par)) = (seq, par) {
+                    assert_eq!(renaming.entry(par).or_insert(seq), &seq);
+                }
+            }
+            let distinct: IndexSet<_> = renaming.values().collect();
+            assert_eq!(distinct.len(), renaming.len());
+        }
+
+        let (tiles, scalar) = raster::map_to_tiles(&points, 4., 5);
+        let clusters = raster::cluster_tiles(tiles, 4);
+        let labels = raster::label_points(&points, &clusters, scalar);
+        assert_eq!(raster::par::label_points(&points, &clusters, scalar, 3), labels);
+    }
+
     #[test]
     fn test_total_duo_raster(){
         let points = parallel_read("../0_data_generators/data_100_shuffled.csv").unwrap();
@@ -148,7 +507,7 @@ mod tests {
 
         assert_eq!(clusters_par.len(), clusters_seq.len());
         for c in clusters_par {
-            assert!(clusters_seq.iter().any(|set| *set == c));
+            assert!(clusters_seq.contains(&c));
         }
     }
 }
\ No newline at end of file
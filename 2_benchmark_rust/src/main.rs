@@ -4,57 +4,102 @@ mod benchmark_lib;
 
 use benchmark_lib::{
     cluster_iter,
+    pyramid_iter,
     Raster,
+    Split,
+    sweep_iter,
     write_bench_times,
+    write_sweep_rows,
 };
-use raster::Float;
+use raster::{ClusterEngine, ClusterParams, KeyOrder, Projection, Rounding, SeamMerge, Tiling};
 use std::fs::remove_file;
 use docopt::Docopt;
 use serde::Deserialize;
 
 
 
-const USAGE: &'static str = "
+const USAGE: &str = "
 RASTER is an approximation algorithm for clustering.
 It can either run sequentially or concurrently. There is also a prime version of each.
 
 Usage: raster [options] [[-p P]... [-n N]... | --bench]
        raster par [options] [[-p P]... [-n N]... <cores>... | --bench]
+       raster sweep [options] [-p P]... [-n N]...
+       raster suggest [options] [-n N]...
        raster [-h | --help]
 
 Command:
-    par  Run concurrent RASTER with one or more number of threads/cores.
+    par    Run concurrent RASTER with one or more number of threads/cores.
+    sweep  Project once per precision and cluster every combination of the
+           thresholds and minimum sizes below, writing one csv row per combination.
+    suggest  Suggest a precision among the candidate precisions below, a threshold
+             and a minimum cluster size, and print the diagnostics behind them.
 
 Options:
-    -h, --help     Show this message.
-    -p P           Precision makes tiles by keeping P decimal digits [default: 3.5].
-    -t T           Threshold for significant tiles [default: 5].
-    -m M           Minimum number of tiles in a cluster [default: 4]
-    -i N           #Iterations to run the benchmark [default: 5].
-    -n <clusters>  The number of clusters [default: 1000].
-    --prime        Use RASTER' where the points are retained.
-    --bench        Sets precision, #clusters, and #cores depending on <type>.
-                   When a sequential type:
-                     precision = 3, 3.5, 4, 5
-                     #clusters = 10^2, 10^3, 10^4, 10^5, 10^6
-                   When a parallel type:
-                     precision = 3, 3.5, 4
-                     #clusters = 10^5, 10^6
-                     #cores    = 1, 2, 4, 8
+    -h, --help       Show this message.
+    -p P             Precision makes tiles by keeping P decimal digits [default: 3.5].
+                     Also accepts a precision per axis (e.g. 3.5,3) or
+                     a tile size per axis (e.g. cell=0.0005 or cell=0.0005,0.00025).
+    -t T             Threshold for significant tiles [default: 5].
+    -m M             Minimum number of tiles in a cluster [default: 4]
+    --thresholds T   Comma separated thresholds of sweep [default: 1,2,3,4,5,10].
+    --min-sizes M    Comma separated minimum cluster sizes of sweep [default: 1,2,4,8].
+    --precisions P   Comma separated candidate precisions of suggest
+                     [default: 1,1.5,2,2.5,3,3.5,4,4.5,5,5.5,6].
+    -i N             #Iterations to run the benchmark [default: 5].
+    -n <clusters>    The number of clusters [default: 1000].
+    --prime          Use RASTER' where the points are retained.
+    --engine E       Cluster the tiles with breadth-first search (bfs), union-find
+                     (union-find), labeling on a dense grid (dense) or the grid
+                     when the tiles fill enough of their bounding box (auto)
+                     [default: bfs].
+    --projection P   Count the points of each tile with a hash map (hash) or by sorting
+                     tile keys in row-major or Morton order (row-major, morton)
+                     [default: hash].
+    --split S        Cut the tiles of the concurrent versions into slices of equal
+                     width over -180..180 (equal-width) or over the range of the
+                     tiles (extent), or with about as many tiles (quantiles), or
+                     into a grid of blocks with about as many tiles per column
+                     and per row of blocks (blocks) [default: equal-width].
+    --merge M        Join the clusters across the seams between the slices one seam
+                     after the other (sequential) or pairwise in parallel as a
+                     reduction tree (tree) [default: tree].
+    --pyramid        Project the points of the sequential RASTER once onto the finest
+                     precision and derive the tile counts of the coarser precisions
+                     that nest in it, e.g. 4 and 3 from 5, see raster::pyramid.
+    --truncate       Round tiles towards zero instead of down, as in the published benchmarks.
+    --bench          Sets precision, #clusters, and #cores depending on <type>.
+                     When a sequential type:
+                       precision = 3, 3.5, 4, 5
+                       #clusters = 10^2, 10^3, 10^4, 10^5, 10^6
+                     When a parallel type:
+                       precision = 3, 3.5, 4
+                       #clusters = 10^5, 10^6
+                       #cores    = 1, 2, 4, 8
 ";
 
 
 #[derive(Deserialize, Debug)]
 struct Args {
     cmd_par: bool,
+    cmd_sweep: bool,
+    cmd_suggest: bool,
     arg_cores: Vec<usize>,
-    flag_help: bool,
-    flag_p: Vec<Float>, // precision [p]
+    flag_p: Vec<String>, // precision [p] or tiling specification
     flag_t: usize, // threshold
     flag_m: usize, // min cluster size
+    flag_thresholds: String, // thresholds of sweep
+    flag_min_sizes: String, // min cluster sizes of sweep
+    flag_precisions: String, // candidate precisions of suggest
     flag_i: usize, // iterations
     flag_n: Vec<usize>, // [n] clusters
     flag_prime: bool,
+    flag_engine: String,
+    flag_projection: String,
+    flag_split: String,
+    flag_merge: String,
+    flag_pyramid: bool,
+    flag_truncate: bool,
     flag_bench: bool,
 }
 
@@ -77,16 +122,61 @@ fn main() {
     let threshold = args.flag_t;
     let min_size = args.flag_m;
     let iterations = args.flag_i;
+    let rounding = if args.flag_truncate { Rounding::Truncate } else { Rounding::Floor };
+    let engine = match args.flag_engine.as_str() {
+        "bfs"        => ClusterEngine::Bfs,
+        "union-find" => ClusterEngine::UnionFind,
+        "dense"      => ClusterEngine::Dense,
+        "auto"       => ClusterEngine::Auto,
+        other => {
+            eprintln!("unknown engine '{}', expected bfs, union-find, dense or auto", other);
+            std::process::exit(1);
+        },
+    };
+    let merge = match args.flag_merge.as_str() {
+        "sequential" => SeamMerge::Sequential,
+        "tree"       => SeamMerge::Tree,
+        other => {
+            eprintln!("unknown merge '{}', expected sequential or tree", other);
+            std::process::exit(1);
+        },
+    };
+    let params = ClusterParams::new(min_size).with_engine(engine).with_merge(merge);
+    let projection = match args.flag_projection.as_str() {
+        "hash"      => Projection::Hash,
+        "row-major" => Projection::Sort(KeyOrder::RowMajor),
+        "morton"    => Projection::Sort(KeyOrder::Morton),
+        other => {
+            eprintln!("unknown projection '{}', expected hash, row-major or morton", other);
+            std::process::exit(1);
+        },
+    };
+
+    let split = match args.flag_split.as_str() {
+        "equal-width" => Split::EqualWidth,
+        "extent"      => Split::Extent,
+        "quantiles"   => Split::Quantiles,
+        "blocks"      => Split::Blocks,
+        other => {
+            eprintln!("unknown split '{}', expected equal-width, extent, quantiles or blocks", other);
+            std::process::exit(1);
+        },
+    };
+
+    if args.flag_pyramid && (!matches!(alg, Raster::Seq) || projection != Projection::Hash) {
+        eprintln!("--pyramid only applies to the sequential RASTER with the hash projection");
+        std::process::exit(1);
+    }
 
     if args.flag_bench {
         match alg {
             Raster::Seq | Raster::SeqPrime => {
                 nr_clusters_list = vec![100, 1_000, 10_000, 100_000, 1_000_000];
-                precisions = vec![3.0, 3.5, 4.0, 5.0];
+                precisions = vec!["3".into(), "3.5".into(), "4".into(), "5".into()];
             },
             Raster::Par | Raster::ParPrime => {
                 nr_clusters_list = vec![100_000, 1_000_000];
-                precisions = vec![3.0, 3.5, 4.0];
+                precisions = vec!["3".into(), "3.5".into(), "4".into()];
                 nr_cores = vec![1, 2, 4, 8];
             },
         }
@@ -98,6 +188,48 @@ fn main() {
         Raster::Par       => "par_raster",
         Raster::ParPrime  => "par_raster_prime",
     };
+    let alg_str = match engine {
+        ClusterEngine::Bfs => alg_str.to_string(),
+        _ => format!("{}_{}", alg_str, args.flag_engine.replace('-', "_")),
+    };
+    let alg_str = match projection {
+        Projection::Hash => alg_str,
+        Projection::Sort(_) => format!("{}_{}", alg_str, args.flag_projection.replace('-', "_")),
+    };
+    let alg_str = match split {
+        Split::EqualWidth => alg_str,
+        _ => format!("{}_{}", alg_str, args.flag_split),
+    };
+    let alg_str = match merge {
+        SeamMerge::Tree => alg_str,
+        SeamMerge::Sequential => format!("{}_{}", alg_str, args.flag_merge),
+    };
+    let alg_str = if args.flag_pyramid { format!("{}_pyramid", alg_str) } else { alg_str };
+
+    let tilings: Vec<Tiling> = precisions.iter()
+        .map(|p| p.parse::<Tiling>().unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }).with_rounding(rounding))
+        .collect();
+
+    if args.cmd_suggest {
+        let candidates: Vec<(&str, Tiling)> = args.flag_precisions.split(',')
+            .map(|p| (p.trim(), p.parse::<Tiling>().unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }).with_rounding(rounding)))
+            .collect();
+        suggest(&nr_clusters_list, &candidates);
+        return;
+    }
+
+    if args.cmd_sweep {
+        let thresholds = parse_list(&args.flag_thresholds);
+        let min_sizes = parse_list(&args.flag_min_sizes);
+        sweep(&nr_clusters_list, &precisions, &tilings, &thresholds, &min_sizes);
+        return;
+    }
 
     for precision in precisions.iter() {
         let _ = remove_file(format!("output/{}_rust_precision{}_threshold{}_min_size{}.csv"
@@ -112,9 +244,33 @@ fn main() {
         );
         println!("I found {} data points.", points.len());
 
+        if args.flag_pyramid {
+            let (checked, checked_tilings): (Vec<&String>, Vec<Tiling>) = precisions.iter()
+                .zip(tilings.iter())
+                .filter(|(precision, tiling)| match tiling.check(&points) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        println!("Skipping precision {}: {}", precision, e);
+                        false
+                    },
+                })
+                .unzip();
+            let rows = pyramid_iter(&points, nr_clusters, &checked_tilings, threshold, &params, iterations);
+            for (precision, row) in checked.into_iter().zip(rows) {
+                let desc_file = format!("output/{}_rust_precision{}_threshold{}_min_size{}.csv"
+                                    , alg_str, precision, threshold, min_size);
+                write_bench_times(row, desc_file).unwrap();
+            }
+            continue;
+        }
+
         for &cores in nr_cores.iter() {
-            for &precision in precisions.iter() {
-                let row = cluster_iter(&alg, &points, nr_clusters, precision, threshold, cores, min_size, iterations);
+            for (precision, tiling) in precisions.iter().zip(tilings.iter()) {
+                if let Err(e) = tiling.check(&points) {
+                    println!("Skipping precision {}: {}", precision, e);
+                    continue;
+                }
+                let row = cluster_iter(&alg, &points, nr_clusters, tiling, threshold, cores, &params, projection, split, iterations);
 
                 let desc_file = format!("output/{}_rust_precision{}_threshold{}_min_size{}.csv"
                                     , alg_str, precision, threshold, min_size);
@@ -123,3 +279,87 @@ fn main() {
         }
     }
 }
+
+
+fn sweep(
+    nr_clusters_list: &[usize],
+    precisions: &[String],
+    tilings: &[Tiling],
+    thresholds: &[usize],
+    min_sizes: &[usize],
+) {
+    for precision in precisions.iter() {
+        let _ = remove_file(format!("output/sweep_rust_precision{}.csv", precision));
+    }
+
+    for &nr_clusters in nr_clusters_list.iter() {
+        let file = format!("../0_data_generators/data_{}_shuffled.csv", nr_clusters);
+        println!("{} clusters", nr_clusters);
+        let (points, _time) = timeit!("Reading: {} ms",
+            data::parallel_read(file).unwrap()
+        );
+        println!("I found {} data points.", points.len());
+
+        for (precision, tiling) in precisions.iter().zip(tilings.iter()) {
+            if let Err(e) = tiling.check(&points) {
+                println!("Skipping precision {}: {}", precision, e);
+                continue;
+            }
+            let rows = sweep_iter(&points, nr_clusters, tiling, thresholds, min_sizes);
+            write_sweep_rows(rows, format!("output/sweep_rust_precision{}.csv", precision)).unwrap();
+        }
+    }
+}
+
+
+fn suggest(nr_clusters_list: &[usize], candidates: &[(&str, Tiling)]) {
+    let tilings: Vec<Tiling> = candidates.iter().map(|(_, t)| *t).collect();
+
+    for &nr_clusters in nr_clusters_list.iter() {
+        let file = format!("../0_data_generators/data_{}_shuffled.csv", nr_clusters);
+        println!("{} clusters", nr_clusters);
+        let points = data::parallel_read(file).unwrap();
+        println!("I found {} data points.\n", points.len());
+
+        let suggestion = match raster::suggest::suggest_with(&points, &tilings) {
+            Some(s) => s,
+            None => {
+                println!("Nothing to suggest without points.");
+                continue;
+            },
+        };
+
+        println!("{:>10} {:>10} {:>10} {:>10} {:>12}", "precision", "#tiles", "mean count", "max count", "singletons");
+        for ((precision, _), d) in candidates.iter().zip(suggestion.candidates.iter()) {
+            println!("{:>10} {:>10} {:>10.2} {:>10} {:>12.3}",
+                precision, d.nr_tiles, d.mean_count, d.max_count, d.singleton_fraction);
+        }
+        let chosen = &suggestion.candidates[suggestion.candidate];
+        println!("\nTile counts at precision {} (count: #tiles):", candidates[suggestion.candidate].0);
+        for (count, nr_tiles) in chosen.histogram.iter().take(20) {
+            println!("{:>10}: {}", count, nr_tiles);
+        }
+        if chosen.histogram.len() > 20 {
+            println!("{:>10}", "...");
+        }
+        println!("\n#clusters by threshold:");
+        for (threshold, n) in suggestion.clusters_by_threshold.iter() {
+            println!("{:>10}: {}", threshold, n);
+        }
+        println!("\nLargest clusters [tiles]: {:?}", &suggestion.cluster_sizes[..suggestion.cluster_sizes.len().min(20)]);
+
+        println!("\nSuggestion: -p {} -t {} -m {}\n",
+            candidates[suggestion.candidate].0, suggestion.threshold, suggestion.min_cluster_size);
+    }
+}
+
+
+/// Parses a comma separated list of numbers or exits.
+fn parse_list(list: &str) -> Vec<usize> {
+    list.split(',')
+        .map(|x| x.trim().parse().unwrap_or_else(|_| {
+            eprintln!("invalid number '{}' in '{}'", x, list);
+            std::process::exit(1);
+        }))
+        .collect()
+}
@@ -0,0 +1,299 @@
+/*!
+ * Clustering of significant tiles on a dense grid.
+ *
+ * When the significant tiles fill a large part of their bounding box, e.g. a single city at a
+ * precision of 3.5, a bitmap over the bounding box is smaller than a set of tiles and needs no
+ * hashing. The tiles are then clustered with the classic two-pass connected-component labeling:
+ * the first pass scans the grid row by row and gives every tile the label of a neighbor that was
+ * already scanned, recording the labels that meet in a [`DisjointSets`](../union_find/struct.DisjointSets.html),
+ * and the second pass replaces every label by the final label of its set.
+ *
+ * [`ClusterEngine::Auto`](../params/enum.ClusterEngine.html) uses the grid when at least
+ * [`DENSE_OCCUPANCY`](constant.DENSE_OCCUPANCY.html) of the bounding box is significant. The
+ * clusters are the same as with the other engines, ordered by the first of their tiles in the input.
+ */
+
+use indexmap::{IndexMap, IndexSet};
+
+use crate::{
+    ClusterEngine,
+    ClusterParams,
+    Connectivity,
+    Float,
+    Tile,
+    TileSet,
+    nd::{self, GridTile, TileContent},
+    union_find::DisjointSets,
+};
+
+
+/// The fraction of the bounding box that the tiles must fill for `ClusterEngine::Auto` to use a grid.
+pub const DENSE_OCCUPANCY: Float = 0.25;
+
+/// The largest number of cells of a grid, beyond which the tiles are never put on a grid, even
+/// with `ClusterEngine::Dense`, and are clustered with a breadth-first search instead. The labels
+/// of the two passes take 4 bytes per cell, i.e. at most 64 MiB.
+pub const MAX_CELLS: u64 = 1 << 24;
+
+
+/// A bitmap of the tiles within a bounding box, row by row.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TileGrid {
+    /// The lower left tile of the bounding box.
+    pub min: Tile,
+    pub width: usize,
+    pub height: usize,
+    bits: Vec<u64>,
+}
+
+impl TileGrid {
+    /// The grid of the bounding box of `tiles`, or `None` without tiles or with more than
+    /// `MAX_CELLS` cells.
+    pub fn new<'a>(tiles: impl Iterator<Item = &'a Tile> + Clone) -> Option<Self> {
+        let (min, width, height) = bounding_box(tiles.clone())?;
+        let mut grid = TileGrid { min, width, height, bits: vec![0; (width * height).div_ceil(64)] };
+        for tile in tiles {
+            let i = grid.index(tile).unwrap();
+            grid.bits[i / 64] |= 1 << (i % 64);
+        }
+        Some(grid)
+    }
+
+    /// Same as [`new`](#method.new) but only if `engine` asks for a grid, which for
+    /// `ClusterEngine::Auto` depends on the occupancy of the bounding box by the `len` tiles.
+    pub fn for_engine<'a>(tiles: impl Iterator<Item = &'a Tile> + Clone, len: usize, engine: ClusterEngine) -> Option<Self> {
+        match engine {
+            ClusterEngine::Dense => Self::new(tiles),
+            ClusterEngine::Auto => {
+                let (_, width, height) = bounding_box(tiles.clone())?;
+                if len as Float >= DENSE_OCCUPANCY * (width * height) as Float {
+                    Self::new(tiles)
+                }
+                else {
+                    None
+                }
+            },
+            ClusterEngine::Bfs | ClusterEngine::UnionFind => None,
+        }
+    }
+
+    /// The position of `tile` in the grid, row by row.
+    #[inline]
+    pub fn index(&self, tile: &Tile) -> Option<usize> {
+        let x = tile.0 as i64 - self.min.0 as i64;
+        let y = tile.1 as i64 - self.min.1 as i64;
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return None;
+        }
+        Some(y as usize * self.width + x as usize)
+    }
+
+    #[inline]
+    pub fn contains(&self, tile: &Tile) -> bool {
+        self.index(tile).is_some_and(|i| self.bits[i / 64] >> (i % 64) & 1 == 1)
+    }
+
+    /// The fraction of the cells that hold a tile.
+    pub fn occupancy(&self) -> Float {
+        let ones: u32 = self.bits.iter().map(|word| word.count_ones()).sum();
+        ones as Float / (self.width * self.height) as Float
+    }
+
+    /// Labels the connected tiles with two passes over the grid. Returns the label of every cell,
+    /// 0 for the cells without a tile and `1..=n` in scan order for the `n` clusters, and `n`.
+    pub fn labels(&self, connectivity: Connectivity) -> (Vec<u32>, usize) {
+        // the neighbors that come before a tile in scan order
+        let earlier: Vec<(i64, i64)> = (0, 0)
+            .neighbors(connectivity)
+            .into_iter()
+            .filter(|&(dx, dy)| dy < 0 || (dy == 0 && dx < 0))
+            .map(|(dx, dy)| (dx as i64, dy as i64))
+            .collect();
+
+        let mut labels = vec![0; self.width * self.height];
+        let mut sets = DisjointSets::new(1); // 0 is the background
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let i = y * self.width + x;
+                if self.bits[i / 64] >> (i % 64) & 1 == 0 {
+                    continue;
+                }
+                let mut label = 0;
+                for &(dx, dy) in earlier.iter() {
+                    let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                    if nx < 0 || ny < 0 || nx >= self.width as i64 {
+                        continue;
+                    }
+                    let n_label = labels[ny as usize * self.width + nx as usize] as usize;
+                    if n_label == 0 {
+                        continue;
+                    }
+                    if label == 0 {
+                        label = n_label;
+                    }
+                    else {
+                        sets.union(label, n_label);
+                    }
+                }
+                let label = if label == 0 { sets.push() } else { label };
+                labels[i] = label as u32; // a grid has at most MAX_CELLS cells
+            }
+        }
+
+        let mut final_labels = vec![0; sets.len()];
+        let mut nr_labels = 0;
+        for label in labels.iter_mut().filter(|l| **l != 0) {
+            let root = sets.find(*label as usize);
+            if final_labels[root] == 0 {
+                nr_labels += 1;
+                final_labels[root] = nr_labels;
+            }
+            *label = final_labels[root];
+        }
+        (labels, nr_labels as usize)
+    }
+
+    /// The cluster of each of `tiles`, which must be the tiles of the grid, numbered from 0 in the
+    /// order of their first tile in `tiles`.
+    fn cluster_ids<'a>(&self, tiles: impl Iterator<Item = &'a Tile>, connectivity: Connectivity) -> (Vec<usize>, usize) {
+        let (labels, nr_labels) = self.labels(connectivity);
+        let mut ids = vec![usize::MAX; nr_labels + 1];
+        let mut nr_clusters = 0;
+        let cluster_ids = tiles
+            .map(|tile| {
+                let label = labels[self.index(tile).unwrap()] as usize;
+                if ids[label] == usize::MAX {
+                    ids[label] = nr_clusters;
+                    nr_clusters += 1;
+                }
+                ids[label]
+            })
+            .collect();
+        (cluster_ids, nr_clusters)
+    }
+}
+
+
+/// The lower left corner, width and height of the bounding box of `tiles`,
+/// or `None` without tiles or with more than `MAX_CELLS` cells.
+fn bounding_box<'a>(tiles: impl Iterator<Item = &'a Tile>) -> Option<(Tile, usize, usize)> {
+    let (mut min, mut max) = ((i32::MAX, i32::MAX), (i32::MIN, i32::MIN));
+    let mut any = false;
+    for &(x, y) in tiles {
+        min = (min.0.min(x), min.1.min(y));
+        max = (max.0.max(x), max.1.max(y));
+        any = true;
+    }
+    if !any {
+        return None;
+    }
+    let width = (max.0 as i64 - min.0 as i64 + 1) as u64;
+    let height = (max.1 as i64 - min.1 as i64 + 1) as u64;
+    match width.checked_mul(height) {
+        Some(cells) if cells <= MAX_CELLS.min(usize::MAX as u64) => Some((min, width as usize, height as usize)),
+        _ => None,
+    }
+}
+
+
+/// Dense version of [`union_find::cluster_tiles`](../union_find/fn.cluster_tiles.html) for the
+/// tiles of `grid`, keeping every cluster regardless of its size.
+pub fn cluster_tiles(tiles: TileSet, grid: &TileGrid, connectivity: Connectivity) -> Vec<TileSet> {
+    let (ids, nr_clusters) = grid.cluster_ids(tiles.iter(), connectivity);
+    let mut clusters = vec![IndexSet::new(); nr_clusters];
+    for (tile, id) in tiles.into_iter().zip(ids) {
+        clusters[id].insert(tile);
+    }
+    clusters
+}
+
+
+/// Dense version of [`union_find::cluster_tile_map`](../union_find/fn.cluster_tile_map.html) for
+/// the tiles of `grid`, keeping every cluster regardless of its size.
+pub fn cluster_tile_map<V>(tiles: IndexMap<Tile, V>, grid: &TileGrid, connectivity: Connectivity) -> Vec<IndexMap<Tile, V>> {
+    let (ids, nr_clusters) = grid.cluster_ids(tiles.keys(), connectivity);
+    let mut clusters: Vec<IndexMap<Tile, V>> = (0..nr_clusters).map(|_| IndexMap::new()).collect();
+    for ((tile, value), id) in tiles.into_iter().zip(ids) {
+        clusters[id].insert(tile, value);
+    }
+    clusters
+}
+
+
+
+/// [`cluster_tiles`](fn.cluster_tiles.html) if `params.engine` asks for a grid,
+/// [`nd::cluster_tiles_with`](../nd/fn.cluster_tiles_with.html) otherwise.
+pub(crate) fn cluster_tiles_with(tiles: TileSet, params: &ClusterParams) -> Vec<TileSet> {
+    match TileGrid::for_engine(tiles.iter(), tiles.len(), params.engine) {
+        Some(grid) => {
+            let mut clusters = cluster_tiles(tiles, &grid, params.connectivity);
+            clusters.retain(|c| c.len() >= params.min_cluster_size);
+            clusters
+        },
+        None => nd::cluster_tiles_with(tiles, params),
+    }
+}
+
+
+/// [`cluster_tile_map`](fn.cluster_tile_map.html) if `params.engine` asks for a grid,
+/// [`nd::prime::cluster_tiles_with`](../nd/prime/fn.cluster_tiles_with.html) otherwise.
+pub(crate) fn cluster_tile_map_with<V: TileContent>(tiles: IndexMap<Tile, V>, params: &ClusterParams) -> Vec<IndexMap<Tile, V>> {
+    match TileGrid::for_engine(tiles.keys(), tiles.len(), params.engine) {
+        Some(grid) => {
+            let mut clusters = cluster_tile_map(tiles, &grid, params.connectivity);
+            clusters.retain(|c| params.keeps(c));
+            clusters
+        },
+        None => nd::prime::cluster_tiles_with(tiles, params),
+    }
+}
+
+
+/////////////////////////////////////////////////////////////////////////////////////
+/// Unit tests
+/////////////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn two_pass_labels() {
+        // a U whose arms only meet on the last row, and a tile touching it by a corner
+        let tiles: TileSet = [
+            (0, 0), (2, 0),
+            (0, 1), (2, 1),
+            (0, 2), (1, 2), (2, 2),
+            (3, 3),
+        ].iter().cloned().collect();
+        let grid = TileGrid::new(tiles.iter()).unwrap();
+        assert_eq!((grid.min, grid.width, grid.height), ((0, 0), 4, 4));
+        assert!(grid.contains(&(1, 2)) && !grid.contains(&(1, 1)) && !grid.contains(&(9, 9)));
+        assert_eq!(grid.occupancy(), 0.5);
+
+        let (labels, n) = grid.labels(Connectivity::Four);
+        assert_eq!(n, 2);
+        assert_eq!(labels[0], labels[2]); // the arms were joined by the second pass
+        assert_ne!(labels[15], labels[0]);
+        assert_eq!(grid.labels(Connectivity::Eight).1, 1);
+
+        assert!(TileGrid::new([].iter()).is_none());
+        assert!(TileGrid::new([(i32::MIN + 1, 0), (i32::MAX - 1, 1 << 20)].iter()).is_none());
+    }
+
+    #[test]
+    fn automatic_choice() {
+        let block: TileSet = (0..100).map(|i| (i % 10 - 5, i / 10)).collect();
+        assert!(TileGrid::for_engine(block.iter(), block.len(), ClusterEngine::Auto).is_some());
+        assert!(TileGrid::for_engine(block.iter(), block.len(), ClusterEngine::Bfs).is_none());
+
+        let sparse: TileSet = (0..100).map(|i| (i * 7, i * 3)).collect();
+        assert!(TileGrid::for_engine(sparse.iter(), sparse.len(), ClusterEngine::Auto).is_none());
+        assert!(TileGrid::for_engine(sparse.iter(), sparse.len(), ClusterEngine::Dense).is_some());
+
+        // too many cells for a grid even when it is asked for, the clusters come from a search
+        let far: TileSet = [(0, 0), (1, 0), (1 << 13, 1 << 12)].iter().cloned().collect();
+        assert!(TileGrid::for_engine(far.iter(), far.len(), ClusterEngine::Dense).is_none());
+        let params = ClusterParams::new(1).with_engine(ClusterEngine::Dense);
+        assert_eq!(cluster_tiles_with(far, &params).len(), 2);
+    }
+}
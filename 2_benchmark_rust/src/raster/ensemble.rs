@@ -0,0 +1,195 @@
+/*!
+ * Ensemble clustering over shifted grids.
+ *
+ * A dense blob sitting on a tile corner has its points split over several tiles, each of
+ * which may fall below the threshold. Running RASTER on grids whose origins are moved by a
+ * fraction of a tile, see [`Tiling::shifted`](../struct.Tiling.html#method.shifted), and
+ * reconciling the clusters removes most of these grid-alignment artifacts.
+ *
+ * The clusters of all runs are reconciled per point: a point is clustered if at least
+ * `min_votes` runs put it in a cluster, and two clusters of any runs end up with the same
+ * consensus label if they share such a point.
+ */
+
+use hashbrown::HashMap;
+
+use crate::{
+    ClusterParams,
+    Float,
+    Tiling,
+    nd::{self, GridPoint},
+    union_find::DisjointSets,
+};
+
+
+/// Offsets, in tiles, of the 2^D grids moved by either zero or half a tile along each axis.
+/// The first offset is the unshifted grid.
+pub fn half_tile_shifts<const D: usize>() -> Vec<[Float; D]> {
+    (0..1usize << D)
+        .map(|bits| {
+            let mut shift = [0.; D];
+            for (axis, s) in shift.iter_mut().enumerate() {
+                if bits & (1 << axis) != 0 {
+                    *s = 0.5;
+                }
+            }
+            shift
+        })
+        .collect()
+}
+
+
+/// Clusters `points` on `tiling` shifted by each of `shifts`, see
+/// [`Tiling::shifted`](../struct.Tiling.html#method.shifted), and returns the consensus label of
+/// each point, or `None` if fewer than `min_votes` runs put the point in a cluster.
+/// Labels are numbered from 0 in the order the points are given.
+pub fn consensus_labels<P: GridPoint<D>, const D: usize>(
+    points: &[P],
+    tiling: &Tiling<D>,
+    threshold: usize,
+    params: &ClusterParams,
+    shifts: &[[Float; D]],
+    min_votes: usize,
+) -> Vec<Option<usize>> {
+    // the clusters of all runs are numbered consecutively
+    let mut runs = Vec::with_capacity(shifts.len());
+    let mut nr_clusters = 0;
+    for shift in shifts {
+        let shifted = tiling.shifted(*shift);
+        let tiles = nd::map_to_tiles(points, &shifted, threshold);
+        let clusters = nd::cluster_tiles_with(tiles, params);
+
+        let mut cluster_of = nd::cluster_lookup(&clusters);
+        cluster_of.values_mut().for_each(|id| *id += nr_clusters);
+        nr_clusters += clusters.len();
+        runs.push((shifted, cluster_of));
+    }
+
+    let mut sets = DisjointSets::new(nr_clusters);
+    let mut firsts = Vec::with_capacity(points.len());
+    for p in points {
+        let votes: Vec<usize> = runs
+            .iter()
+            .filter_map(|(shifted, cluster_of)| cluster_of.get(&p.to_tile(shifted)).copied())
+            .collect();
+
+        // a point without enough votes does not join the clusters it is in
+        let first = votes.first().copied().filter(|_| votes.len() >= min_votes.max(1));
+        if let Some(first) = first {
+            for &other in votes[1..].iter() {
+                sets.union(first, other);
+            }
+        }
+        firsts.push(first);
+    }
+
+    // renumber the merged clusters in the order of the points
+    let mut labels = HashMap::new();
+    firsts
+        .into_iter()
+        .map(|first| {
+            first.map(|c| {
+                let root = sets.find(c);
+                let next = labels.len();
+                *labels.entry(root).or_insert(next)
+            })
+        })
+        .collect()
+}
+
+
+
+/////////////////////////////////////////////////////////////////////////////////////
+/// Unit tests
+/////////////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Connectivity, Point};
+
+    #[test]
+    fn shifts() {
+        assert_eq!(half_tile_shifts::<1>(), vec![[0.], [0.5]]);
+        assert_eq!(half_tile_shifts::<2>(), vec![[0., 0.], [0.5, 0.], [0., 0.5], [0.5, 0.5]]);
+        assert_eq!(half_tile_shifts::<3>().len(), 8);
+    }
+
+    #[test]
+    fn blob_on_a_corner() {
+        // one point in each quadrant around the origin and a lone point far away
+        let points = vec![
+            Point::new(0.1, 0.1),
+            Point::new(-0.1, 0.1),
+            Point::new(0.1, -0.1),
+            Point::new(-0.1, -0.1),
+            Point::new(5.1, 5.1),
+        ];
+        let tiling = Tiling::new(0.);
+        let params = ClusterParams::new(1);
+
+        assert_eq!(
+            consensus_labels(&points, &tiling, 4, &params, &[[0., 0.]], 1),
+            vec![None; 5],
+        );
+        assert_eq!(
+            consensus_labels(&points, &tiling, 4, &params, &half_tile_shifts(), 1),
+            vec![Some(0), Some(0), Some(0), Some(0), None],
+        );
+        // only the shift by half a tile along both axes finds the blob
+        assert_eq!(
+            consensus_labels(&points, &tiling, 4, &params, &half_tile_shifts(), 2),
+            vec![None; 5],
+        );
+    }
+
+    #[test]
+    fn merges_runs() {
+        // two clusters on the unshifted grid, separated by a tile with a single point,
+        // that all fall in two neighboring tiles of the grid shifted by half a tile
+        let points = vec![
+            Point::new(0.8, 0.5),
+            Point::new(0.9, 0.5),
+            Point::new(2.1, 0.5),
+            Point::new(2.2, 0.5),
+            Point::new(1.5, 0.5),
+        ];
+        let tiling = Tiling::new(0.);
+        let params = ClusterParams::new(1);
+
+        let unshifted = consensus_labels(&points, &tiling, 2, &params, &[[0., 0.]], 1);
+        assert_eq!(unshifted, vec![Some(0), Some(0), Some(1), Some(1), None]);
+
+        let shifts = [[0., 0.], [0.5, 0.]];
+        let labels = consensus_labels(&points, &tiling, 2, &params, &shifts, 1);
+        assert_eq!(labels, vec![Some(0); 5]);
+
+        let labels = consensus_labels(&points, &tiling, 2, &params, &shifts, 2);
+        assert_eq!(labels, vec![Some(0), Some(0), Some(0), Some(0), None]);
+    }
+
+    #[test]
+    fn bridge_without_enough_votes() {
+        // a pair of tiles on the left and a pair above, and a point between them that is
+        // next to the pair above on the unshifted grid and next to the pair on the left on
+        // the grid shifted along both axes, but alone on the grid shifted along y
+        let points = vec![
+            Point::new(-0.1, 2.1),
+            Point::new(0.9, 2.1),
+            Point::new(2.1, 3.9),
+            Point::new(2.1, 4.9),
+            Point::new(2.1, 2.1),
+        ];
+        let tiling = Tiling::new(0.);
+        let params = ClusterParams::new(2).with_connectivity(Connectivity::Four);
+        let shifts = [[0., 0.], [0.5, 0.5], [0., 0.5]];
+
+        assert_eq!(
+            consensus_labels(&points, &tiling, 1, &params, &shifts[..2], 2),
+            vec![Some(0); 5],
+        );
+        assert_eq!(
+            consensus_labels(&points, &tiling, 1, &params, &shifts, 3),
+            vec![Some(0), Some(0), Some(1), Some(1), None],
+        );
+    }
+}
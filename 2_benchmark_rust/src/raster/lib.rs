@@ -1,47 +1,106 @@
 /*!
  * This is the implementation of Contraction Clustering (RASTER) for 2D data.
  * It is covered in detail in a [paper](https://doi.org/10.1007/978-3-319-72926-8_6).
+ * Data with more dimensions is handled by the [`nd`](nd/index.html) module.
  */
 
+pub mod dense;
+pub mod ensemble;
+pub mod nd;
 pub mod par;
+pub mod params;
 pub mod prime;
-
-use indexmap::IndexSet;
+pub mod pyramid;
+pub mod sorted;
+pub mod suggest;
+pub mod summary;
+pub mod sweep;
+pub mod tiling;
+pub mod tree;
+pub mod union_find;
+
+use indexmap::{IndexMap, IndexSet};
 use hashbrown::HashMap;
 use serde::Deserialize;
 
+pub use params::{ClusterEngine, ClusterParams, Connectivity, Projection, SeamMerge, Significance};
+pub use sorted::KeyOrder;
+pub use summary::ClusterSummary;
+pub use tiling::{ProjectionError, Rounding, Tiling};
 
 pub type Tile = (i32, i32);
 pub type TileSet = IndexSet<Tile>;
+/// The number of points projected onto each tile.
+pub type TileCounts = IndexMap<Tile, usize>;
 pub type Float = f64;
+/// The index of a cluster in the output of the clustering step.
+pub type ClusterId = usize;
 #[derive(Debug, Deserialize, PartialEq, Clone, Copy)]
 pub struct Point(Float, Float);
+/// A point with a weight, e.g. the multiplicity of an aggregated ping or the confidence of a reading.
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy)]
+pub struct WeightedPoint(Float, Float, Float);
+/// The sum of the weights of the points projected onto each tile.
+pub type TileWeights = IndexMap<Tile, Float>;
 
 impl Point {
     pub fn new(x: Float, y: Float) -> Self {
         Point(x, y)
     }
 
+    /// Rounds the scaled coordinates towards zero.
+    /// Kept for compatibility, see [`Rounding::Truncate`](enum.Rounding.html).
     pub fn truncate(&self, scalar: Float) -> Tile{
         ( (self.0 * scalar) as i32, (self.1 * scalar) as i32 )
     }
+
+    /// Rounds the scaled coordinates towards negative infinity.
+    pub fn floor(&self, scalar: Float) -> Tile{
+        ( (self.0 * scalar).floor() as i32, (self.1 * scalar).floor() as i32 )
+    }
+
+    /// The tile containing this point.
+    #[inline]
+    pub fn to_tile(&self, tiling: &Tiling) -> Tile {
+        ( tiling.to_coordinate(0, self.0), tiling.to_coordinate(1, self.1) )
+    }
+
+    /// The tile containing this point, or an error if it cannot be represented.
+    #[inline]
+    pub fn checked_to_tile(&self, tiling: &Tiling) -> Result<Tile, ProjectionError> {
+        Ok(( tiling.checked_coordinate(0, self.0)?, tiling.checked_coordinate(1, self.1)? ))
+    }
 }
 
 
-/// Counts the number of points for each tile containing at least one point.
-fn to_tile_counts<'a>(
-    points: &'a [Point],
-    scalar: Float,
-) -> HashMap<Tile, usize> {
-    let mut tile_count = HashMap::new();
+impl WeightedPoint {
+    pub fn new(x: Float, y: Float, weight: Float) -> Self {
+        WeightedPoint(x, y, weight)
+    }
+
+    pub fn from_point(point: Point, weight: Float) -> Self {
+        WeightedPoint(point.0, point.1, weight)
+    }
 
-    for p in points {
-        let p_int = p.truncate(scalar);
-        let count = tile_count.entry(p_int).or_insert(0);
-        *count += 1;
+    /// The point without its weight.
+    #[inline]
+    pub fn point(&self) -> Point {
+        Point(self.0, self.1)
     }
 
-    tile_count
+    #[inline]
+    pub fn weight(&self) -> Float {
+        self.2
+    }
+}
+
+
+/// Counts the number of points for each tile containing at least one point.
+fn to_tile_counts(
+    points: &[Point],
+    tiling: &Tiling,
+) -> HashMap<Tile, usize> {
+    nd::to_tile_counts(points, tiling)
 }
 
 
@@ -49,62 +108,236 @@ fn to_tile_counts<'a>(
 /// A tile is significant if it maps to at least `threshold` number of points.
 /// Also returns the scaling factor used for creating tiles.
 pub fn map_to_tiles(
-    points: &Vec<Point>,
+    points: &[Point],
     precision: Float,
     threshold: usize,
 ) -> (TileSet, Float) {
-    let scalar = (10 as Float).powf(precision);
-    let all_tiles = to_tile_counts(points, scalar);
+    let tiling = Tiling::new(precision);
+    (map_to_tiles_with(points, &tiling, threshold), tiling.scalars[0])
+}
+
+
+/// Same as [`map_to_tiles`](fn.map_to_tiles.html) but on the grid described by `tiling`.
+pub fn map_to_tiles_with(
+    points: &[Point],
+    tiling: &Tiling,
+    threshold: usize,
+) -> TileSet {
+    nd::map_to_tiles(points, tiling, threshold)
+}
+
+
+/// Same as [`map_to_tiles_with`](fn.map_to_tiles_with.html) but with the given projection strategy.
+/// The tiles are the same, with [`Projection::Sort`](enum.Projection.html) they are inserted in
+/// the order of their keys.
+pub fn map_to_tiles_using(
+    points: &[Point],
+    tiling: &Tiling,
+    threshold: usize,
+    projection: Projection,
+) -> TileSet {
+    match projection {
+        Projection::Hash => map_to_tiles_with(points, tiling, threshold),
+        Projection::Sort(order) => sorted::map_to_sorted_tiles(points, tiling, threshold, order).to_tile_set(),
+    }
+}
+
+
+/// Same as [`map_to_tiles`](fn.map_to_tiles.html) but the threshold is given by a rule relative to
+/// the tile counts of the data, e.g. a percentile. Also returns the scaling factor used for creating
+/// tiles and the threshold that was applied.
+pub fn map_to_significant_tiles(
+    points: &[Point],
+    precision: Float,
+    significance: &Significance,
+) -> (TileSet, Float, usize) {
+    let tiling = Tiling::new(precision);
+    let (tiles, threshold) = map_to_significant_tiles_with(points, &tiling, significance);
+    (tiles, tiling.scalars[0], threshold)
+}
+
+
+/// Same as [`map_to_significant_tiles`](fn.map_to_significant_tiles.html) but on the grid
+/// described by `tiling`. Also returns the threshold that was applied.
+pub fn map_to_significant_tiles_with(
+    points: &[Point],
+    tiling: &Tiling,
+    significance: &Significance,
+) -> (TileSet, usize) {
+    nd::map_to_significant_tiles(points, tiling, significance)
+}
+
+
+/// Counts the points of every tile containing at least one point, significant or not.
+/// Also returns the scaling factor used for creating tiles.
+pub fn tile_counts(points: &[Point], precision: Float) -> (TileCounts, Float) {
+    let tiling = Tiling::new(precision);
+    (tile_counts_with(points, &tiling), tiling.scalars[0])
+}
+
+
+/// Same as [`tile_counts`](fn.tile_counts.html) but on the grid described by `tiling`.
+pub fn tile_counts_with(points: &[Point], tiling: &Tiling) -> TileCounts {
+    nd::tile_counts(points, tiling)
+}
+
+
+/// Keeps the tiles of `counts` with at least `threshold` points, which is the same as projecting
+/// the points again with another threshold.
+pub fn significant_tile_counts(counts: &TileCounts, threshold: usize) -> TileCounts {
+    nd::significant_tile_counts(counts, threshold)
+}
+
+
+/// Same as [`map_to_tiles`](fn.map_to_tiles.html) but a tile is significant if the sum of the
+/// weights of its points is at least `threshold`.
+pub fn map_to_weighted_tiles(
+    points: &[WeightedPoint],
+    precision: Float,
+    threshold: Float,
+) -> (TileSet, Float) {
+    let tiling = Tiling::new(precision);
+    (map_to_weighted_tiles_with(points, &tiling, threshold), tiling.scalars[0])
+}
+
+
+/// Same as [`map_to_weighted_tiles`](fn.map_to_weighted_tiles.html) but on the grid described by `tiling`.
+pub fn map_to_weighted_tiles_with(
+    points: &[WeightedPoint],
+    tiling: &Tiling,
+    threshold: Float,
+) -> TileSet {
+    nd::map_to_weighted_tiles(points, tiling, threshold)
+}
+
+
+/// Sums the weights of the points of every tile containing at least one point.
+/// Also returns the scaling factor used for creating tiles.
+pub fn tile_weights(points: &[WeightedPoint], precision: Float) -> (TileWeights, Float) {
+    let tiling = Tiling::new(precision);
+    (tile_weights_with(points, &tiling), tiling.scalars[0])
+}
+
+
+/// Same as [`tile_weights`](fn.tile_weights.html) but on the grid described by `tiling`.
+pub fn tile_weights_with(points: &[WeightedPoint], tiling: &Tiling) -> TileWeights {
+    nd::tile_weights(points, tiling)
+}
+
 
-    let tiles = all_tiles
-        .into_iter()
-        .filter(|(_, count)| count >= &threshold )
-        .map(|(tile, _)| tile)
-        .collect();
+/// Same as [`map_to_tiles`](fn.map_to_tiles.html) but keeps the number of points of each
+/// significant tile, e.g. for [`cluster_tile_counts`](fn.cluster_tile_counts.html).
+pub fn map_to_tile_counts(
+    points: &[Point],
+    precision: Float,
+    threshold: usize,
+) -> (TileCounts, Float) {
+    let tiling = Tiling::new(precision);
+    (map_to_tile_counts_with(points, &tiling, threshold), tiling.scalars[0])
+}
 
-    (tiles, scalar)
+
+/// Same as [`map_to_tile_counts`](fn.map_to_tile_counts.html) but on the grid described by `tiling`.
+pub fn map_to_tile_counts_with(
+    points: &[Point],
+    tiling: &Tiling,
+    threshold: usize,
+) -> TileCounts {
+    nd::map_to_tile_counts(points, tiling, threshold)
+}
+
+
+/// Same as [`map_to_tiles`](fn.map_to_tiles.html) but returns an error instead of saturating
+/// when a point and `precision` do not fit in a tile coordinate.
+pub fn try_map_to_tiles(
+    points: &[Point],
+    precision: Float,
+    threshold: usize,
+) -> Result<(TileSet, Float), ProjectionError> {
+    let tiling = Tiling::new(precision);
+    Ok((try_map_to_tiles_with(points, &tiling, threshold)?, tiling.scalars[0]))
+}
+
+
+/// Same as [`map_to_tiles_with`](fn.map_to_tiles_with.html) but returns an error instead of
+/// saturating when a point does not fit in a tile coordinate.
+pub fn try_map_to_tiles_with(
+    points: &[Point],
+    tiling: &Tiling,
+    threshold: usize,
+) -> Result<TileSet, ProjectionError> {
+    nd::try_map_to_tiles(points, tiling, threshold)
 }
 
 
 /// Cluster a set of significant tiles.
 pub fn cluster_tiles(tiles: TileSet, min_cluster_size: usize) -> Vec<TileSet> {
-    let mut to_visit = tiles;
-    let mut clusters = Vec::new();
+    cluster_tiles_with(tiles, &ClusterParams::new(min_cluster_size))
+}
 
-    while let Some(x) = to_visit.pop() { // starting point for a new cluster
-        let mut cluster = IndexSet::new(); // NOTE: if we don't need sets it faster with a Vec
-        cluster.insert(x);
 
-        let mut to_check: Vec<Tile> = pop_neighbors(x, &mut to_visit).collect();
-        while let Some(p) = to_check.pop() {
-            cluster.insert(p);
+/// Same as [`cluster_tiles`](fn.cluster_tiles.html) but with all parameters of the clustering step,
+/// e.g. the connectivity of the tiles. The tiles do not know their points, use
+/// [`cluster_tile_counts_with`](fn.cluster_tile_counts_with.html) for a minimum number of points.
+pub fn cluster_tiles_with(tiles: TileSet, params: &ClusterParams) -> Vec<TileSet> {
+    dense::cluster_tiles_with(tiles, params)
+}
 
-            let new_neighbors = pop_neighbors(p, &mut to_visit);
-            to_check.extend(new_neighbors);
-        }
 
-        if cluster.len() >= min_cluster_size {
-            clusters.push(cluster);
-        }
-    }
-    clusters
+/// Same as [`cluster_tiles`](fn.cluster_tiles.html) but each tile keeps its number of points.
+pub fn cluster_tile_counts(counts: TileCounts, min_cluster_size: usize) -> Vec<TileCounts> {
+    cluster_tile_counts_with(counts, &ClusterParams::new(min_cluster_size))
 }
 
 
-/// Returns all neighbors to (x,y) in tiles and removes them from tiles.
-fn pop_neighbors<'a>((x, y): Tile, tiles: &'a mut TileSet) -> impl Iterator<Item = Tile>  + 'a {
-    let candidates = vec![
-        (x + 1, y    ),
-        (x - 1, y    ),
-        (x    , y + 1),
-        (x    , y - 1),
-        (x + 1, y - 1),
-        (x + 1, y + 1),
-        (x - 1, y - 1),
-        (x - 1, y + 1),
-    ];
+/// Same as [`cluster_tiles_with`](fn.cluster_tiles_with.html) but each tile keeps its number of points,
+/// which is used for the minimum number of points and weight of `params`.
+pub fn cluster_tile_counts_with(counts: TileCounts, params: &ClusterParams) -> Vec<TileCounts> {
+    dense::cluster_tile_map_with(counts, params)
+}
+
+
+/// Returns the cluster of each point in input order, i.e. the index in `clusters` of the cluster
+/// containing its tile, or `None` for noise. `scalar` is the one returned by
+/// [`map_to_tiles`](fn.map_to_tiles.html).
+pub fn label_points(points: &[Point], clusters: &[TileSet], scalar: Float) -> Vec<Option<ClusterId>> {
+    label_points_with(points, clusters, &Tiling::from_scalars([scalar; 2]))
+}
+
+
+/// Same as [`label_points`](fn.label_points.html) but on the grid described by `tiling`,
+/// which must be the one used to create the clusters.
+pub fn label_points_with(points: &[Point], clusters: &[TileSet], tiling: &Tiling) -> Vec<Option<ClusterId>> {
+    nd::label_points(points, clusters, tiling)
+}
+
+
+/// Runs both steps of RASTER and returns the cluster of each point in input order,
+/// or `None` for noise. Same as `label_points` on the output of `cluster_tiles`.
+pub fn fit_predict(
+    points: &[Point],
+    precision: Float,
+    threshold: usize,
+    min_cluster_size: usize,
+) -> Vec<Option<ClusterId>> {
+    fit_predict_with(points, &Tiling::new(precision), threshold, &ClusterParams::new(min_cluster_size))
+}
+
 
-    candidates.into_iter().filter( move |n| tiles.remove(n) ) // set's remove returns a bool
+/// Same as [`fit_predict`](fn.fit_predict.html) but with a custom grid and clustering parameters.
+pub fn fit_predict_with(
+    points: &[Point],
+    tiling: &Tiling,
+    threshold: usize,
+    params: &ClusterParams,
+) -> Vec<Option<ClusterId>> {
+    nd::fit_predict(points, tiling, threshold, params)
+}
+
+
+/// Returns all neighbors to (x,y) in tiles and removes them from tiles.
+fn pop_neighbors(tile: Tile, tiles: &mut TileSet, connectivity: Connectivity) -> impl Iterator<Item = Tile> + '_ {
+    nd::pop_neighbors(tile, tiles, connectivity)
 }
 
 
@@ -135,6 +368,167 @@ mod tests {
         assert_eq!(map_to_tiles(&points, 1., 3), (truth, scalar));
     }
 
+    #[test]
+    fn counts() {
+        let points = vec![
+            Point(0.05, 0.05), Point(0.06, 0.06), Point(0.07, 0.07),
+            Point(0.15, 0.05), Point(0.16, 0.06),
+            Point(0.55, 0.55),
+        ];
+        let (all, scalar) = tile_counts(&points, 1.);
+        assert_eq!(scalar, 10.);
+        assert_eq!(all.len(), 3);
+        assert_eq!((all[&(0, 0)], all[&(1, 0)], all[&(5, 5)]), (3, 2, 1));
+
+        let significant = significant_tile_counts(&all, 2);
+        assert_eq!(significant, map_to_tile_counts(&points, 1., 2).0);
+        assert_eq!(significant.len(), 2);
+
+        let clusters = cluster_tile_counts(significant, 1);
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters[0], [((0, 0), 3), ((1, 0), 2)].iter().cloned().collect::<TileCounts>());
+        let tiles: TileSet = clusters[0].keys().cloned().collect();
+        assert_eq!(cluster_tiles(map_to_tiles(&points, 1., 2).0, 1), vec![tiles]);
+    }
+
+    #[test]
+    fn significance() {
+        let counts = [7, 1, 3, 1, 2, 10, 3, 1];
+        assert_eq!(Significance::Count(4).threshold(counts.iter().cloned()), 4);
+        assert_eq!(Significance::Percentile(50.).threshold(counts.iter().cloned()), 2);
+        assert_eq!(Significance::Percentile(75.).threshold(counts.iter().cloned()), 3);
+        assert_eq!(Significance::Percentile(100.).threshold(counts.iter().cloned()), 10);
+        assert_eq!(Significance::Percentile(0.).threshold(counts.iter().cloned()), 1);
+        assert_eq!(Significance::Top(2).threshold(counts.iter().cloned()), 7);
+        assert_eq!(Significance::Top(3).threshold(counts.iter().cloned()), 3); // 4 tiles with ties
+        assert_eq!(Significance::Top(0).threshold(counts.iter().cloned()), 11);
+        assert_eq!(Significance::Top(20).threshold(counts.iter().cloned()), 1);
+        assert_eq!(Significance::TimesMean(1.).threshold(counts.iter().cloned()), 4); // mean 3.5
+        assert_eq!(Significance::TimesMean(2.).threshold(counts.iter().cloned()), 7);
+        assert_eq!(Significance::Percentile(50.).threshold(None), 1);
+        assert_eq!(Significance::TimesMean(2.).threshold(None), 1);
+
+        let points = vec![
+            Point(0.05, 0.05), Point(0.06, 0.06), Point(0.07, 0.07),
+            Point(0.15, 0.05), Point(0.16, 0.06),
+            Point(0.55, 0.55),
+        ];
+        let (tiles, scalar, threshold) = map_to_significant_tiles(&points, 1., &Significance::Top(1));
+        assert_eq!((scalar, threshold), (10., 3));
+        assert_eq!(tiles, map_to_tiles(&points, 1., 3).0);
+        let (tiles, threshold) = map_to_significant_tiles_with(&points, &Tiling::new(1.), &Significance::Count(2));
+        assert_eq!((tiles, threshold), (map_to_tiles(&points, 1., 2).0, 2));
+    }
+
+    #[test]
+    fn min_points() {
+        let points = vec![
+            Point(0.05, 0.05), Point(0.06, 0.06), Point(0.07, 0.07), Point(0.15, 0.05), // 2 tiles, 4 points
+            Point(0.55, 0.55), Point(0.56, 0.56), Point(0.65, 0.55), Point(0.66, 0.56), // 2 tiles, 4 points
+            Point(0.75, 0.55), Point(0.76, 0.56), Point(0.77, 0.57),                    // 1 tile, 3 points
+            Point(0.95, 0.95), Point(0.96, 0.96), Point(0.97, 0.97), Point(0.98, 0.98), // 1 tile, 4 points
+        ];
+        let (counts, _) = tile_counts(&points, 1.);
+
+        let clusters = cluster_tile_counts_with(counts.clone(), &ClusterParams::new(2).with_min_points(5));
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters[0].len(), 3);
+        assert_eq!(cluster_tile_counts_with(counts.clone(), &ClusterParams::new(1).with_min_points(4)).len(), 3);
+        assert_eq!(cluster_tile_counts_with(counts.clone(), &ClusterParams::new(1).with_min_weight(4.5)).len(), 1);
+
+        // the tiles and the points must both be enough
+        let params = ClusterParams::new(2).with_min_points(4);
+        assert_eq!(cluster_tile_counts_with(counts, &params).len(), 2);
+        assert_eq!(cluster_tiles_with(map_to_tiles(&points, 1., 1).0, &params).len(), 2);
+
+        let weighted: Vec<_> = points.iter().map(|p| WeightedPoint::from_point(*p, 0.5)).collect();
+        let (tiles, _) = prime::map_to_weighted_tiles(&weighted, 1., 0.);
+        let params = ClusterParams::new(1).with_min_weight(2.5);
+        let clusters = prime::cluster_weighted_tiles_with(tiles, &params);
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters[0].len(), 3);
+        assert_eq!(prime::cluster_tiles_with(prime::map_to_tiles(&points, 1., 1).0, &params).len(), 3);
+    }
+
+    #[test]
+    fn weighted() {
+        let points = vec![
+            WeightedPoint::new(0.05, 0.05, 1.), WeightedPoint::new(0.06, 0.06, 1.),
+            WeightedPoint::new(0.15, 0.05, 2.5),
+            WeightedPoint::new(0.55, 0.55, 0.5), WeightedPoint::new(0.56, 0.56, 0.25),
+        ];
+        let (weights, _) = tile_weights(&points, 1.);
+        assert_eq!((weights[&(0, 0)], weights[&(1, 0)], weights[&(5, 5)]), (2., 2.5, 0.75));
+
+        let (tiles, scalar) = map_to_weighted_tiles(&points, 1., 2.);
+        assert_eq!(scalar, 10.);
+        assert_eq!(tiles, [(0, 0), (1, 0)].iter().cloned().collect::<TileSet>());
+        assert_eq!(map_to_weighted_tiles(&points, 1., 0.75).0.len(), 3);
+
+        // unit weights are the same as counting the points
+        let unweighted: Vec<_> = points.iter().map(|p| p.point()).collect();
+        let ones: Vec<_> = unweighted.iter().map(|p| WeightedPoint::from_point(*p, 1.)).collect();
+        assert_eq!(map_to_weighted_tiles(&ones, 1., 2.).0, map_to_tiles(&unweighted, 1., 2).0);
+
+        // the prime clusters keep the weighted points
+        let (tile_map, _) = prime::map_to_weighted_tiles(&points, 1., 2.);
+        let clusters = prime::cluster_weighted_tiles(tile_map, 1);
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters[0][&(1, 0)], vec![points[2]]);
+    }
+
+    #[test]
+    fn labels() {
+        let points = vec![
+            Point(0.05, 0.05), Point(0.06, 0.06), // tile (0, 0)
+            Point(0.15, 0.05), Point(0.16, 0.06), // tile (1, 0)
+            Point(0.55, 0.55),                    // noise
+            Point(0.95, 0.95), Point(0.96, 0.96), // tile (9, 9)
+            Point(0.17, 0.07),
+        ];
+        let (tiles, scalar) = map_to_tiles(&points, 1., 2);
+        let clusters = cluster_tiles(tiles, 1);
+        assert_eq!(clusters.len(), 2);
+
+        let labels = label_points(&points, &clusters, scalar);
+        let big = labels[0].unwrap();
+        assert_eq!(clusters[big].len(), 2);
+        let small = 1 - big;
+        assert_eq!(labels, vec![
+            Some(big), Some(big), Some(big), Some(big), None, Some(small), Some(small), Some(big),
+        ]);
+        assert_eq!(fit_predict(&points, 1., 2, 1), labels);
+        assert_eq!(fit_predict(&points, 1., 2, 2), vec![
+            Some(0), Some(0), Some(0), Some(0), None, None, None, Some(0),
+        ]);
+        assert_eq!(label_points(&points, &[], scalar), vec![None; points.len()]);
+    }
+
+    #[test]
+    fn mapping_around_origin() {
+        let points = vec![
+            Point(-0.05, 0.01), Point(-0.02, 0.03),
+            Point(0.01, 0.01), Point(0.02, 0.03),
+        ];
+        let floor: TileSet = [ (-1, 0), (0, 0) ].iter().cloned().collect();
+        let truncate: TileSet = [ (0, 0) ].iter().cloned().collect();
+
+        assert_eq!(map_to_tiles(&points, 1., 2).0, floor);
+        let tiling = Tiling::new(1.).with_rounding(Rounding::Truncate);
+        assert_eq!(map_to_tiles_with(&points, &tiling, 2), truncate);
+    }
+
+    #[test]
+    fn high_precision() {
+        let points = vec![Point(179.9, 1.), Point(179.8, 1.), Point(-180., 1.)];
+
+        let (tiles, _) = try_map_to_tiles(&points, 7., 1).unwrap();
+        assert_eq!(tiles.len(), 3);
+        assert!(try_map_to_tiles(&points, 7.5, 1).is_err());
+        // Saturated tiles collapse into one
+        assert_eq!(map_to_tiles(&points, 9., 2).0.len(), 1);
+    }
+
     #[test]
     fn who_are_my_neighbors() {
         let tile = (2, 5);
@@ -151,7 +545,7 @@ mod tests {
             (1, 4),
         ];
 
-        let result: Vec<_> = pop_neighbors(tile, &mut tiles).collect();
+        let result: Vec<_> = pop_neighbors(tile, &mut tiles, Connectivity::Eight).collect();
         assert_eq!(result, truth);
     }
 
@@ -160,7 +554,7 @@ mod tests {
         let tile = (2, 5);
         let mut tiles = IndexSet::new();
 
-        let result: Vec<_> = pop_neighbors(tile, &mut tiles).collect();
+        let result: Vec<_> = pop_neighbors(tile, &mut tiles, Connectivity::Eight).collect();
         assert_eq!(result, Vec::new());
     }
 
@@ -197,7 +591,7 @@ mod tests {
         // taking the order of the clusters into consideration.
         assert_eq!(res.len(), output.len());
         for o in output {
-            assert!(res.iter().any(|set| *set == o));
+            assert!(res.contains(&o));
         }
     }
 
@@ -224,13 +618,60 @@ mod tests {
         assert_eq!(tiles, union);
     }
 
+    #[test]
+    fn split_keeps_sentinels_free() {
+        let tiles = vec![(-5, 0), (5, 0)];
+        let tile_slices = par::split_vertically(tiles.into_iter(), -180, 180, 1e9, 4);
+
+        assert_eq!(tile_slices.first().unwrap().0, i32::MIN);
+        assert_eq!(tile_slices.last().unwrap().2, i32::MAX);
+        for (left, _, right) in &tile_slices[1..] {
+            assert!(*left > i32::MIN);
+            assert!(*right > i32::MIN);
+        }
+        for (left, _, right) in &tile_slices[..3] {
+            assert!(*left < i32::MAX);
+            assert!(*right < i32::MAX);
+        }
+    }
+
+    #[test]
+    fn connectivity() {
+        let input: TileSet = [(0, 0), (1, 1), (2, 1), (5, 1), (8, 1)].iter().cloned().collect();
+
+        let four = ClusterParams::new(1).with_connectivity(Connectivity::Four);
+        assert_eq!(cluster_tiles_with(input.clone(), &four).len(), 4);
+        assert_eq!(cluster_tiles(input.clone(), 1).len(), 3);
+        let radius = ClusterParams::new(1).with_connectivity(Connectivity::Radius(3));
+        assert_eq!(cluster_tiles_with(input, &radius).len(), 1);
+        assert_eq!(Connectivity::Radius(u16::MAX).reach(), i32::from(u16::MAX));
+    }
+
+    #[test]
+    fn narrow_slices_with_radius() {
+        let slices = vec![
+            (i32::MIN, [(-1, 0)].iter().cloned().collect(), -1),
+            (0, TileSet::new(), 1),
+            (2, [(2, 0)].iter().cloned().collect(), 2),
+            (3, [(4, 0)].iter().cloned().collect(), i32::MAX),
+        ];
+        let params = ClusterParams::new(1).with_connectivity(Connectivity::Radius(3));
+        let merged = par::merge_narrow_slices(slices.clone(), 3);
+        assert_eq!(merged.len(), 3);
+        assert_eq!((merged[1].0, merged[1].2), (0, 2));
+
+        let clusters = par::cluster_tiles_with(slices, &params);
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters[0].len(), 3);
+    }
+
     #[test]
     fn left_right_edge_case() {
         let slices = vec![
-            (std::i32::MIN, [(-2,0)].iter().cloned().collect(), -2),
+            (i32::MIN, [(-2,0)].iter().cloned().collect(), -2),
             (-1, [(-1,0), (0,0), (0,-2)].iter().cloned().collect(), 0),
             (1, [(1,1), (2,1), (1,-2)].iter().cloned().collect(), 2),
-            (3, [].iter().cloned().collect(), std::i32::MAX),
+            (3, [].iter().cloned().collect(), i32::MAX),
         ];
         let clusters2 = par::cluster_tiles(slices, 2);
         let regular_input = [(-2,0), (-1,0), (0,0), (0,-2), (1,1), (2,1), (1,-2)].iter().cloned().collect();
@@ -238,7 +679,38 @@ mod tests {
 
         assert_eq!(clusters1.len(), clusters2.len());
         for c in clusters2 {
-            assert!(clusters1.iter().any(|set| *set == c));
+            assert!(clusters1.contains(&c));
+        }
+    }
+
+    #[test]
+    fn engines_agree() {
+        // blocks of 3 by 2 tiles, some of which are bridged by scattered tiles
+        let tiles: TileSet = (0..1600)
+            .map(|i: i32| (i % 40 - 20, i / 40))
+            .filter(|(x, y)| (x.rem_euclid(6) < 3 && y % 5 < 2) || (x * y).rem_euclid(13) == 1)
+            .collect();
+        let counts: TileCounts = tiles.iter().map(|t| (*t, t.1 as usize)).collect();
+
+        for &connectivity in [Connectivity::Four, Connectivity::Eight, Connectivity::Radius(2)].iter() {
+            let bfs = ClusterParams::new(3).with_connectivity(connectivity).with_engine(ClusterEngine::Bfs);
+            let expected = cluster_tiles_with(tiles.clone(), &bfs);
+            assert!(expected.len() > 5);
+
+            for &engine in [ClusterEngine::UnionFind, ClusterEngine::Dense, ClusterEngine::Auto].iter() {
+                let clusters = cluster_tiles_with(tiles.clone(), &bfs.with_engine(engine));
+                assert_eq!(clusters.len(), expected.len(), "{:?}", engine);
+                for c in clusters.iter() {
+                    assert!(expected.contains(c));
+                }
+
+                let maps = cluster_tile_counts_with(counts.clone(), &bfs.with_engine(engine));
+                assert_eq!(maps.len(), expected.len(), "{:?}", engine);
+                for m in maps {
+                    assert!(m.iter().all(|(t, v)| t.1 as usize == *v));
+                    assert!(expected.contains(&m.keys().cloned().collect::<TileSet>()));
+                }
+            }
         }
     }
 }
@@ -0,0 +1,618 @@
+/*!
+ * RASTER for data of any dimensionality.
+ *
+ * Points and tiles are arrays of `D` coordinates and by default the neighborhood of a tile is
+ * every tile that differs by at most one in each coordinate, i.e. 3^D-1 neighbors.
+ * The functions are generic over [`GridPoint`](trait.GridPoint.html) and
+ * [`GridTile`](trait.GridTile.html), so the 2D [`Point`](../struct.Point.html) and
+ * [`Tile`](../type.Tile.html) work as well. The 2D functions in the crate root are thin wrappers
+ * around the functions in this module.
+ */
+
+pub mod prime;
+
+use indexmap::{IndexMap, IndexSet};
+use hashbrown::HashMap;
+use std::hash::Hash;
+
+use crate::{
+    ClusterEngine,
+    ClusterId,
+    ClusterParams,
+    Connectivity,
+    Float,
+    ProjectionError,
+    Significance,
+    Tiling,
+    union_find,
+};
+
+
+pub type Tile<const D: usize> = [i32; D];
+pub type TileSet<const D: usize> = IndexSet<Tile<D>>;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Point<const D: usize>(pub [Float; D]);
+
+impl<const D: usize> Point<D> {
+    pub fn new(coordinates: [Float; D]) -> Self {
+        Point(coordinates)
+    }
+}
+
+/// A point with `D` coordinates and a weight, e.g. a multiplicity or a confidence.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct WeightedPoint<const D: usize>(pub [Float; D], pub Float);
+
+impl<const D: usize> WeightedPoint<D> {
+    pub fn new(coordinates: [Float; D], weight: Float) -> Self {
+        WeightedPoint(coordinates, weight)
+    }
+}
+
+
+/// A tile that knows its neighborhood.
+pub trait GridTile: Copy + Eq + Hash {
+    /// All neighbors of this tile for the given connectivity.
+    fn neighbors(&self, connectivity: Connectivity) -> Vec<Self>;
+}
+
+/// A point with `D` coordinates that can be projected onto a tile.
+pub trait GridPoint<const D: usize>: Copy {
+    type Tile: GridTile;
+
+    /// The tile containing this point.
+    fn to_tile(&self, tiling: &Tiling<D>) -> Self::Tile;
+
+    /// The tile containing this point, or an error if it cannot be represented.
+    fn checked_to_tile(&self, tiling: &Tiling<D>) -> Result<Self::Tile, ProjectionError>;
+
+    /// The contribution of this point to the weight of its tile.
+    #[inline]
+    fn weight(&self) -> Float {
+        1.
+    }
+}
+
+/// What a tile holds in a clustering that keeps a value per tile, e.g. its number of points or
+/// the points themselves. Used for the minimum number of points and weight of a cluster,
+/// see [`ClusterParams`](../params/struct.ClusterParams.html).
+pub trait TileContent {
+    fn nr_points(&self) -> usize;
+
+    fn weight(&self) -> Float;
+}
+
+impl TileContent for usize {
+    #[inline]
+    fn nr_points(&self) -> usize {
+        *self
+    }
+
+    #[inline]
+    fn weight(&self) -> Float {
+        *self as Float
+    }
+}
+
+impl TileContent for Vec<crate::Point> {
+    #[inline]
+    fn nr_points(&self) -> usize {
+        self.len()
+    }
+
+    #[inline]
+    fn weight(&self) -> Float {
+        self.len() as Float
+    }
+}
+
+impl TileContent for Vec<crate::WeightedPoint> {
+    #[inline]
+    fn nr_points(&self) -> usize {
+        self.len()
+    }
+
+    #[inline]
+    fn weight(&self) -> Float {
+        self.iter().map(|p| p.weight()).sum()
+    }
+}
+
+impl<const D: usize> TileContent for Vec<Point<D>> {
+    #[inline]
+    fn nr_points(&self) -> usize {
+        self.len()
+    }
+
+    #[inline]
+    fn weight(&self) -> Float {
+        self.len() as Float
+    }
+}
+
+impl<const D: usize> TileContent for Vec<WeightedPoint<D>> {
+    #[inline]
+    fn nr_points(&self) -> usize {
+        self.len()
+    }
+
+    #[inline]
+    fn weight(&self) -> Float {
+        self.iter().map(|p| p.1).sum()
+    }
+}
+
+
+impl GridTile for crate::Tile {
+    fn neighbors(&self, connectivity: Connectivity) -> Vec<Self> {
+        let (x, y) = *self;
+        match connectivity {
+            Connectivity::Four => vec![
+                (x + 1, y    ),
+                (x - 1, y    ),
+                (x    , y + 1),
+                (x    , y - 1),
+            ],
+            Connectivity::Eight => vec![
+                (x + 1, y    ),
+                (x - 1, y    ),
+                (x    , y + 1),
+                (x    , y - 1),
+                (x + 1, y - 1),
+                (x + 1, y + 1),
+                (x - 1, y - 1),
+                (x - 1, y + 1),
+            ],
+            Connectivity::Radius(k) => {
+                let k = i32::from(k);
+                (-k..=k)
+                    .flat_map(|dx| (-k..=k).map(move |dy| (x + dx, y + dy)))
+                    .filter(|n| *n != (x, y))
+                    .collect()
+            },
+        }
+    }
+}
+
+impl<const D: usize> GridTile for Tile<D> {
+    fn neighbors(&self, connectivity: Connectivity) -> Vec<Self> {
+        match connectivity {
+            Connectivity::Four => {
+                let mut neighbors = Vec::with_capacity(2 * D);
+                for axis in 0..D {
+                    for step in [1, -1].iter() {
+                        let mut n = *self;
+                        n[axis] += step;
+                        neighbors.push(n);
+                    }
+                }
+                neighbors
+            },
+            Connectivity::Eight => cube_neighbors(self, 1),
+            Connectivity::Radius(k) => cube_neighbors(self, i32::from(k)),
+        }
+    }
+}
+
+/// All tiles within Chebyshev distance `k` of `tile`, except `tile` itself.
+fn cube_neighbors<const D: usize>(tile: &Tile<D>, k: i32) -> Vec<Tile<D>> {
+    let side = (2 * k + 1) as usize;
+    let size = side.pow(D as u32);
+    let mut neighbors = Vec::with_capacity(size - 1);
+
+    // Every offset in {-k, ..., k}^D is a number in base 2k+1.
+    for code in 0..size {
+        let mut offset = code;
+        let mut n = *tile;
+        for c in n.iter_mut() {
+            *c += (offset % side) as i32 - k;
+            offset /= side;
+        }
+        if n != *tile {
+            neighbors.push(n);
+        }
+    }
+    neighbors
+}
+
+
+impl GridPoint<2> for crate::Point {
+    type Tile = crate::Tile;
+
+    #[inline]
+    fn to_tile(&self, tiling: &Tiling) -> Self::Tile {
+        crate::Point::to_tile(self, tiling)
+    }
+
+    #[inline]
+    fn checked_to_tile(&self, tiling: &Tiling) -> Result<Self::Tile, ProjectionError> {
+        crate::Point::checked_to_tile(self, tiling)
+    }
+}
+
+impl<const D: usize> GridPoint<D> for Point<D> {
+    type Tile = Tile<D>;
+
+    #[inline]
+    fn to_tile(&self, tiling: &Tiling<D>) -> Self::Tile {
+        let mut tile = [0; D];
+        for (axis, (t, x)) in tile.iter_mut().zip(self.0.iter()).enumerate() {
+            *t = tiling.to_coordinate(axis, *x);
+        }
+        tile
+    }
+
+    #[inline]
+    fn checked_to_tile(&self, tiling: &Tiling<D>) -> Result<Self::Tile, ProjectionError> {
+        let mut tile = [0; D];
+        for (axis, (t, x)) in tile.iter_mut().zip(self.0.iter()).enumerate() {
+            *t = tiling.checked_coordinate(axis, *x)?;
+        }
+        Ok(tile)
+    }
+}
+
+
+impl GridPoint<2> for crate::WeightedPoint {
+    type Tile = crate::Tile;
+
+    #[inline]
+    fn to_tile(&self, tiling: &Tiling) -> Self::Tile {
+        self.point().to_tile(tiling)
+    }
+
+    #[inline]
+    fn checked_to_tile(&self, tiling: &Tiling) -> Result<Self::Tile, ProjectionError> {
+        self.point().checked_to_tile(tiling)
+    }
+
+    #[inline]
+    fn weight(&self) -> Float {
+        crate::WeightedPoint::weight(self)
+    }
+}
+
+impl<const D: usize> GridPoint<D> for WeightedPoint<D> {
+    type Tile = Tile<D>;
+
+    #[inline]
+    fn to_tile(&self, tiling: &Tiling<D>) -> Self::Tile {
+        Point(self.0).to_tile(tiling)
+    }
+
+    #[inline]
+    fn checked_to_tile(&self, tiling: &Tiling<D>) -> Result<Self::Tile, ProjectionError> {
+        Point(self.0).checked_to_tile(tiling)
+    }
+
+    #[inline]
+    fn weight(&self) -> Float {
+        self.1
+    }
+}
+
+
+/// Counts the number of points for each tile containing at least one point.
+pub(crate) fn to_tile_counts<P: GridPoint<D>, const D: usize>(
+    points: &[P],
+    tiling: &Tiling<D>,
+) -> HashMap<P::Tile, usize> {
+    let mut tile_count = HashMap::new();
+
+    for p in points {
+        let p_int = p.to_tile(tiling);
+        let count = tile_count.entry(p_int).or_insert(0);
+        *count += 1;
+    }
+
+    tile_count
+}
+
+
+/// N-dimensional version of [`map_to_tiles_with`](../fn.map_to_tiles_with.html).
+pub fn map_to_tiles<P: GridPoint<D>, const D: usize>(
+    points: &[P],
+    tiling: &Tiling<D>,
+    threshold: usize,
+) -> IndexSet<P::Tile> {
+    let all_tiles = to_tile_counts(points, tiling);
+
+    all_tiles
+        .into_iter()
+        .filter(|(_, count)| count >= &threshold )
+        .map(|(tile, _)| tile)
+        .collect()
+}
+
+
+/// N-dimensional version of [`tile_counts_with`](../fn.tile_counts_with.html).
+pub fn tile_counts<P: GridPoint<D>, const D: usize>(
+    points: &[P],
+    tiling: &Tiling<D>,
+) -> IndexMap<P::Tile, usize> {
+    to_tile_counts(points, tiling).into_iter().collect()
+}
+
+
+/// N-dimensional version of [`map_to_tile_counts_with`](../fn.map_to_tile_counts_with.html).
+pub fn map_to_tile_counts<P: GridPoint<D>, const D: usize>(
+    points: &[P],
+    tiling: &Tiling<D>,
+    threshold: usize,
+) -> IndexMap<P::Tile, usize> {
+    to_tile_counts(points, tiling)
+        .into_iter()
+        .filter(|(_, count)| count >= &threshold )
+        .collect()
+}
+
+
+/// Sums the weights of the points for each tile containing at least one point.
+pub(crate) fn to_tile_weights<P: GridPoint<D>, const D: usize>(
+    points: &[P],
+    tiling: &Tiling<D>,
+) -> HashMap<P::Tile, Float> {
+    let mut tile_weight = HashMap::new();
+
+    for p in points {
+        let weight = tile_weight.entry(p.to_tile(tiling)).or_insert(0.);
+        *weight += p.weight();
+    }
+
+    tile_weight
+}
+
+
+/// N-dimensional version of [`tile_weights_with`](../fn.tile_weights_with.html).
+pub fn tile_weights<P: GridPoint<D>, const D: usize>(
+    points: &[P],
+    tiling: &Tiling<D>,
+) -> IndexMap<P::Tile, Float> {
+    to_tile_weights(points, tiling).into_iter().collect()
+}
+
+
+/// N-dimensional version of [`map_to_weighted_tiles_with`](../fn.map_to_weighted_tiles_with.html).
+pub fn map_to_weighted_tiles<P: GridPoint<D>, const D: usize>(
+    points: &[P],
+    tiling: &Tiling<D>,
+    threshold: Float,
+) -> IndexSet<P::Tile> {
+    to_tile_weights(points, tiling)
+        .into_iter()
+        .filter(|(_, weight)| *weight >= threshold )
+        .map(|(tile, _)| tile)
+        .collect()
+}
+
+
+/// N-dimensional version of [`map_to_significant_tiles_with`](../fn.map_to_significant_tiles_with.html).
+pub fn map_to_significant_tiles<P: GridPoint<D>, const D: usize>(
+    points: &[P],
+    tiling: &Tiling<D>,
+    significance: &Significance,
+) -> (IndexSet<P::Tile>, usize) {
+    let all_tiles = to_tile_counts(points, tiling);
+    let threshold = significance.threshold(all_tiles.values().cloned());
+
+    let tiles = all_tiles
+        .into_iter()
+        .filter(|(_, count)| *count >= threshold )
+        .map(|(tile, _)| tile)
+        .collect();
+    (tiles, threshold)
+}
+
+
+/// N-dimensional version of [`significant_tile_counts`](../fn.significant_tile_counts.html).
+pub fn significant_tile_counts<T: GridTile>(counts: &IndexMap<T, usize>, threshold: usize) -> IndexMap<T, usize> {
+    counts
+        .iter()
+        .filter(|(_, count)| **count >= threshold )
+        .map(|(tile, count)| (*tile, *count))
+        .collect()
+}
+
+
+/// N-dimensional version of [`try_map_to_tiles_with`](../fn.try_map_to_tiles_with.html).
+pub fn try_map_to_tiles<P: GridPoint<D>, const D: usize>(
+    points: &[P],
+    tiling: &Tiling<D>,
+    threshold: usize,
+) -> Result<IndexSet<P::Tile>, ProjectionError> {
+    let mut all_tiles = HashMap::new();
+
+    for p in points {
+        let p_int = p.checked_to_tile(tiling)?;
+        let count = all_tiles.entry(p_int).or_insert(0);
+        *count += 1;
+    }
+
+    Ok(all_tiles
+        .into_iter()
+        .filter(|(_, count)| count >= &threshold )
+        .map(|(tile, _)| tile)
+        .collect())
+}
+
+
+/// N-dimensional version of [`cluster_tiles`](../fn.cluster_tiles.html).
+pub fn cluster_tiles<T: GridTile>(tiles: IndexSet<T>, min_cluster_size: usize) -> Vec<IndexSet<T>> {
+    cluster_tiles_with(tiles, &ClusterParams::new(min_cluster_size))
+}
+
+
+/// N-dimensional version of [`cluster_tiles_with`](../fn.cluster_tiles_with.html).
+/// Only the minimum number of tiles of `params` applies, the tiles do not know their points.
+pub fn cluster_tiles_with<T: GridTile>(tiles: IndexSet<T>, params: &ClusterParams) -> Vec<IndexSet<T>> {
+    if params.engine == ClusterEngine::UnionFind {
+        let mut clusters = union_find::cluster_tiles(tiles, params.connectivity);
+        clusters.retain(|c| c.len() >= params.min_cluster_size);
+        return clusters;
+    }
+
+    let mut to_visit = tiles;
+    let mut clusters = Vec::new();
+
+    while let Some(x) = to_visit.pop() { // starting point for a new cluster
+        let mut cluster = IndexSet::new();
+        cluster.insert(x);
+
+        let mut to_check: Vec<T> = pop_neighbors(x, &mut to_visit, params.connectivity).collect();
+        while let Some(p) = to_check.pop() {
+            cluster.insert(p);
+
+            let new_neighbors = pop_neighbors(p, &mut to_visit, params.connectivity);
+            to_check.extend(new_neighbors);
+        }
+
+        if cluster.len() >= params.min_cluster_size {
+            clusters.push(cluster);
+        }
+    }
+    clusters
+}
+
+
+/// N-dimensional version of [`label_points_with`](../fn.label_points_with.html).
+pub fn label_points<P: GridPoint<D>, const D: usize>(
+    points: &[P],
+    clusters: &[IndexSet<P::Tile>],
+    tiling: &Tiling<D>,
+) -> Vec<Option<ClusterId>> {
+    let cluster_of = cluster_lookup(clusters);
+    points.iter().map(|p| cluster_of.get(&p.to_tile(tiling)).copied()).collect()
+}
+
+
+/// N-dimensional version of [`fit_predict_with`](../fn.fit_predict_with.html).
+pub fn fit_predict<P: GridPoint<D>, const D: usize>(
+    points: &[P],
+    tiling: &Tiling<D>,
+    threshold: usize,
+    params: &ClusterParams,
+) -> Vec<Option<ClusterId>> {
+    let tiles = map_to_tiles(points, tiling, threshold);
+    let clusters = cluster_tiles_with(tiles, params);
+    label_points(points, &clusters, tiling)
+}
+
+
+/// Maps every tile of `clusters` to the index of its cluster.
+pub(crate) fn cluster_lookup<T: GridTile>(clusters: &[IndexSet<T>]) -> HashMap<T, ClusterId> {
+    clusters
+        .iter()
+        .enumerate()
+        .flat_map(|(id, cluster)| cluster.iter().map(move |&t| (t, id)))
+        .collect()
+}
+
+
+/// Returns all neighbors to `tile` in tiles and removes them from tiles.
+pub(crate) fn pop_neighbors<T: GridTile>(
+    tile: T,
+    tiles: &mut IndexSet<T>,
+    connectivity: Connectivity,
+) -> impl Iterator<Item = T> + '_ {
+    tile.neighbors(connectivity).into_iter().filter( move |n| tiles.remove(n) ) // set's remove returns a bool
+}
+
+
+
+/////////////////////////////////////////////////////////////////////////////////////
+/// Unit tests
+/////////////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn neighborhood_sizes() {
+        let eight = Connectivity::Eight;
+        assert_eq!([0; 1].neighbors(eight).len(), 2);
+        assert_eq!([0; 2].neighbors(eight).len(), 8);
+        assert_eq!([0; 3].neighbors(eight).len(), 26);
+        assert_eq!([0; 4].neighbors(eight).len(), 80);
+        assert!(![3, -1, 7].neighbors(eight).contains(&[3, -1, 7]));
+        assert!([3, -1, 7].neighbors(eight).contains(&[2, 0, 8]));
+
+        assert_eq!([0; 3].neighbors(Connectivity::Four).len(), 6);
+        assert!(![3, -1, 7].neighbors(Connectivity::Four).contains(&[2, 0, 7]));
+        assert_eq!([0; 3].neighbors(Connectivity::Radius(2)).len(), 124);
+        assert_eq!([0; 2].neighbors(Connectivity::Radius(1)), [0; 2].neighbors(eight));
+    }
+
+    #[test]
+    fn neighborhood_2d() {
+        for &c in [Connectivity::Four, Connectivity::Eight, Connectivity::Radius(1), Connectivity::Radius(3)].iter() {
+            let mut tuples = (4, -2).neighbors(c);
+            let mut arrays: Vec<_> = [4, -2].neighbors(c).iter().map(|t| (t[0], t[1])).collect();
+            tuples.sort();
+            arrays.sort();
+            assert_eq!(tuples, arrays);
+        }
+    }
+
+    #[test]
+    fn same_as_2d() {
+        let points = [
+            (1., 23.22), (1.05, 23.28), (-0.3, 2.), (-0.31, 2.01), (-0.2, 2.1), (-0.21, 2.13),
+        ];
+        let tiling = Tiling::new(1.);
+        let points_2d: Vec<_> = points.iter().map(|&(x, y)| crate::Point::new(x, y)).collect();
+        let points_nd: Vec<_> = points.iter().map(|&(x, y)| Point::new([x, y])).collect();
+
+        let tiles_2d = crate::map_to_tiles_with(&points_2d, &tiling, 2);
+        let tiles_nd = map_to_tiles(&points_nd, &tiling, 2);
+        let expected: TileSet<2> = tiles_2d.iter().map(|&(x, y)| [x, y]).collect();
+        assert_eq!(tiles_nd, expected);
+
+        let clusters_2d = crate::cluster_tiles(tiles_2d, 1);
+        let clusters_nd = cluster_tiles(tiles_nd, 1);
+        assert_eq!(clusters_2d.len(), clusters_nd.len());
+        for c in clusters_2d {
+            let c: TileSet<2> = c.iter().map(|&(x, y)| [x, y]).collect();
+            assert!(clusters_nd.contains(&c));
+        }
+    }
+
+    #[test]
+    fn clustering_3d() {
+        let input = [
+            [0, 0, 0],
+            [1, 1, 1],
+            [2, 2, 1],
+            [0, 0, 3],
+            [0, 0, 4],
+        ].iter().cloned().collect();
+
+        let output: Vec<TileSet<3>> = vec![
+            [[0, 0, 0], [1, 1, 1], [2, 2, 1]].iter().cloned().collect(),
+            [[0, 0, 3], [0, 0, 4]].iter().cloned().collect(),
+        ];
+
+        let res = cluster_tiles(input, 1);
+        assert_eq!(res.len(), output.len());
+        for o in output {
+            assert!(res.contains(&o));
+        }
+    }
+
+    #[test]
+    fn mapping_4d() {
+        let points = vec![
+            Point::new([0.1, 0.2, -0.33, 10.]),
+            Point::new([0.15, 0.25, -0.36, 10.05]),
+            Point::new([5., 5., 5., 5.]),
+        ];
+        let truth: TileSet<4> = [[1, 2, -4, 100]].iter().cloned().collect();
+        assert_eq!(map_to_tiles(&points, &Tiling::new(1.), 2), truth);
+        assert_eq!(try_map_to_tiles(&points, &Tiling::new(1.), 2), Ok(truth));
+        assert!(try_map_to_tiles(&points, &Tiling::new(9.), 2).is_err());
+
+        let tiling = Tiling::from_cell_sizes([0.5, 0.5, 0.5, 20.]);
+        let truth: TileSet<4> = [[0, 0, -1, 0]].iter().cloned().collect();
+        assert_eq!(map_to_tiles(&points, &tiling, 2), truth);
+    }
+}
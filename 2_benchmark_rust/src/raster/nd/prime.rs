@@ -0,0 +1,144 @@
+/*!
+ * N-dimensional RASTER', where the input points are retained with the tiles.
+ */
+
+use indexmap::IndexMap;
+use hashbrown::HashMap;
+
+use crate::{
+    ClusterEngine,
+    ClusterParams,
+    Connectivity,
+    Float,
+    Tiling,
+    nd::{GridPoint, GridTile, Point, Tile, TileContent},
+    union_find,
+};
+
+pub type TileMap<const D: usize> = IndexMap<Tile<D>, Vec<Point<D>>>;
+
+
+/// N-dimensional version of [`prime::map_to_tiles_with`](../../prime/fn.map_to_tiles_with.html).
+pub fn map_to_tiles<P: GridPoint<D>, const D: usize>(
+    points: &[P],
+    tiling: &Tiling<D>,
+    threshold: usize,
+) -> IndexMap<P::Tile, Vec<P>> {
+    let mut tile_map = HashMap::new();
+
+    for p in points {
+        let p_int = p.to_tile(tiling);
+
+        let ps = tile_map.entry(p_int).or_insert(vec![]);
+        ps.push(*p);
+    }
+
+    tile_map
+        .into_iter()
+        .filter(|(_, ps)| ps.len() >= threshold )
+        .collect()
+}
+
+
+/// N-dimensional version of [`prime::map_to_weighted_tiles_with`](../../prime/fn.map_to_weighted_tiles_with.html).
+pub fn map_to_weighted_tiles<P: GridPoint<D>, const D: usize>(
+    points: &[P],
+    tiling: &Tiling<D>,
+    threshold: Float,
+) -> IndexMap<P::Tile, Vec<P>> {
+    let mut tile_map = HashMap::new();
+
+    for p in points {
+        let (weight, ps) = tile_map.entry(p.to_tile(tiling)).or_insert((0., vec![]));
+        *weight += p.weight();
+        ps.push(*p);
+    }
+
+    tile_map
+        .into_iter()
+        .filter(|(_, (weight, _))| *weight >= threshold )
+        .map(|(tile, (_, ps))| (tile, ps))
+        .collect()
+}
+
+
+/// N-dimensional version of [`prime::cluster_tiles`](../../prime/fn.cluster_tiles.html).
+/// The values of the map, usually the points of each tile, are kept with their tiles.
+pub fn cluster_tiles<T: GridTile, V: TileContent>(tiles: IndexMap<T, V>, min_cluster_size: usize) -> Vec<IndexMap<T, V>> {
+    cluster_tiles_with(tiles, &ClusterParams::new(min_cluster_size))
+}
+
+
+/// N-dimensional version of [`prime::cluster_tiles_with`](../../prime/fn.cluster_tiles_with.html).
+pub fn cluster_tiles_with<T: GridTile, V: TileContent>(tiles: IndexMap<T, V>, params: &ClusterParams) -> Vec<IndexMap<T, V>> {
+    if params.engine == ClusterEngine::UnionFind {
+        let mut clusters = union_find::cluster_tile_map(tiles, params.connectivity);
+        clusters.retain(|c| params.keeps(c));
+        return clusters;
+    }
+
+    let mut to_visit = tiles;
+    let mut clusters = Vec::new();
+
+    while let Some((kx, vx)) = to_visit.pop() { // starting point for a new cluster
+
+        let mut cluster = IndexMap::new();
+        cluster.insert(kx, vx);
+
+        let mut to_check: IndexMap<_,_> = pop_neighbors(kx, &mut to_visit, params.connectivity).collect();
+        while let Some((kc, vc)) = to_check.pop() {
+            cluster.insert(kc, vc);
+
+            let new_neighbors = pop_neighbors(kc, &mut to_visit, params.connectivity);
+            for (kn, vn) in new_neighbors {
+                to_check.insert(kn, vn);
+            }
+        }
+
+        if params.keeps(&cluster) {
+            clusters.push(cluster);
+        }
+    }
+    clusters
+}
+
+
+/// Returns all neighbors to `tile` and their points, and removes them from tiles.
+pub(crate) fn pop_neighbors<T: GridTile, V>(
+    tile: T,
+    tiles: &mut IndexMap<T, V>,
+    connectivity: Connectivity,
+) -> impl Iterator<Item = (T, V)> + '_ {
+    // map neighbors to points if they exists
+    tile.neighbors(connectivity).into_iter().filter_map( move |n| tiles.remove(&n).map(|v| (n, v)) )
+}
+
+
+
+/////////////////////////////////////////////////////////////////////////////////////
+/// Unit tests
+/////////////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nd::Point;
+
+    #[test]
+    fn retains_points_3d() {
+        let points = vec![
+            Point::new([0.01, 0.01, 0.01]),
+            Point::new([0.02, 0.02, 0.02]),
+            Point::new([0.11, 0.11, 0.11]),
+            Point::new([0.12, 0.12, 0.12]),
+            Point::new([0.91, 0.91, 0.91]),
+        ];
+        let tiles: TileMap<3> = map_to_tiles(&points, &Tiling::new(1.), 2);
+        assert_eq!(tiles.len(), 2);
+
+        let clusters = cluster_tiles(tiles, 2);
+        assert_eq!(clusters.len(), 1);
+        let mut retained: Vec<_> = clusters[0].values().flatten().cloned().collect();
+        retained.sort_by(|a, b| a.0[0].total_cmp(&b.0[0]));
+        assert_eq!(retained, points[..4].to_vec());
+    }
+}
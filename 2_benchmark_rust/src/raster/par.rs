@@ -5,79 +5,249 @@
  * - extra parameters (e.g. `nr_threads`)
  * - [`map_to_tiles`](fn.map_to_tiles.html) returns an iterator instead of a set
  * - [`split_vertically`](fn.split_vertically.html) is a preprocessing step for [`cluster_tiles`](fn.cluster_tiles.html)
+ *
+ * The work runs on the rayon thread pool the functions are called from, `nr_threads` only sets
+ * the number of batches of points. This is the global pool unless the call is made within
+ * `ThreadPool::install` of a pool owned by the caller, and no other threads are created.
  */
 
-use indexmap::IndexSet;
+use indexmap::{IndexMap, IndexSet};
 use hashbrown::HashMap;
-use std::sync::mpsc::{Sender, Receiver};
-use std::sync::mpsc;
-use std::thread;
+use hashbrown::hash_map::Entry;
+use rayon::prelude::*;
+use std::ops::{AddAssign, RangeInclusive};
+use std::time::{Duration, Instant};
 
+pub mod blocks;
 pub mod dual_clustering;
 
+pub use rayon::{ThreadPool, ThreadPoolBuilder};
+
 use crate::{
+    ClusterEngine,
+    ClusterId,
+    ClusterParams,
+    Connectivity,
     Float,
     Point,
+    SeamMerge,
+    Significance,
     Tile,
+    TileCounts,
     TileSet,
+    TileWeights,
+    Tiling,
+    WeightedPoint,
+    nd,
+    tiling::{MAX_COORDINATE, MIN_COORDINATE},
     pop_neighbors,
     to_tile_counts,
+    union_find::{self, DisjointSets},
+    sorted::{KeyOrder, SortedCounts},
 };
 
+/// Clusters touching the (left edge, both edges, right edge) of a slice.
+type EdgeClusters = (Vec<TileSet>, Vec<TileSet>, Vec<TileSet>);
+
+
+/// Split data into at most `nr_parts` batches, none of them empty.
+fn batch_data<P: Sync>(points: &[P], nr_parts: usize) -> rayon::slice::Chunks<'_, P> {
+    points.par_chunks(batch_size(points.len(), nr_parts))
+}
+
 
-/// Split data into `nr_parts` batches.
-fn batch_data<'a>(points: &'a Vec<Point>, nr_parts: usize) -> impl Iterator<Item = Vec<Point>> + 'a {
-    let chunk_size = points.len() / nr_parts;
-    points.chunks(chunk_size).map(|b| b.to_vec())
+/// The number of points of each of at most `nr_parts` batches of `len` points.
+pub(crate) fn batch_size(len: usize, nr_parts: usize) -> usize {
+    len.div_ceil(nr_parts.max(1)).max(1)
 }
 
 
 /// Concurrent version of [`map_to_tiles`](../fn.map_to_tiles.html).
 pub fn map_to_tiles(
-    points: &Vec<Point>,
+    points: &[Point],
     precision: Float,
     threshold: usize,
     nr_threads: usize,
 ) -> (impl Iterator<Item=Tile>, Float) {
+    let tiling = Tiling::new(precision);
+    (map_to_tiles_with(points, &tiling, threshold, nr_threads), tiling.scalars[0])
+}
 
-    type Counter = HashMap<Tile, usize>;
-    let (sx, rx): (Sender<Counter>, Receiver<Counter>) = mpsc::channel();
-    let scalar = (10 as Float).powf(precision);
-    let batches = batch_data(points, nr_threads);
 
-    for data in batches {
-        let thread_sx = sx.clone();
-        thread::spawn(move || {
-            let tiles = to_tile_counts(&data, scalar);
-            thread_sx.send(tiles).unwrap();
-        });
-    }
-    drop(sx); // need to drop all Sender references
-
-    let mut accumulate_tiles = rx.recv().unwrap();
-    if nr_threads > 1 {
-        accumulate_tiles.reserve(accumulate_tiles.len()) // add more capacity
-    };
-    // Wait for the threads to finish and sum counters for each tile.
-    while let Ok(tiles) = rx.recv() {
-        for (t, t_count) in tiles {
-            let count = accumulate_tiles.entry(t).or_insert(0);
-            *count += t_count;
-        }
-    }
+/// Concurrent version of [`map_to_tiles_with`](../fn.map_to_tiles_with.html).
+pub fn map_to_tiles_with(
+    points: &[Point],
+    tiling: &Tiling,
+    threshold: usize,
+    nr_threads: usize,
+) -> impl Iterator<Item=Tile> {
+    accumulate_tile_counts(points, tiling, nr_threads)
+        .into_iter()
+        .filter(move |(_, count)| *count >= threshold )
+        .map(|(tile, _)| tile)
+}
 
-    (
-        accumulate_tiles.into_iter()
-            .filter(move |(_, count)| *count >= threshold )
-            .map(|(tile, _)| tile),
-        scalar,
-    )
+
+/// Concurrent version of [`map_to_significant_tiles`](../fn.map_to_significant_tiles.html).
+pub fn map_to_significant_tiles(
+    points: &[Point],
+    precision: Float,
+    significance: &Significance,
+    nr_threads: usize,
+) -> (impl Iterator<Item=Tile>, Float, usize) {
+    let tiling = Tiling::new(precision);
+    let (tiles, threshold) = map_to_significant_tiles_with(points, &tiling, significance, nr_threads);
+    (tiles, tiling.scalars[0], threshold)
+}
+
+
+/// Concurrent version of [`map_to_significant_tiles_with`](../fn.map_to_significant_tiles_with.html).
+/// The counts of all the batches are needed before the threshold is known.
+pub fn map_to_significant_tiles_with(
+    points: &[Point],
+    tiling: &Tiling,
+    significance: &Significance,
+    nr_threads: usize,
+) -> (impl Iterator<Item=Tile>, usize) {
+    let counts = accumulate_tile_counts(points, tiling, nr_threads);
+    let threshold = significance.threshold(counts.values().cloned());
+    let tiles = counts
+        .into_iter()
+        .filter(move |(_, count)| *count >= threshold )
+        .map(|(tile, _)| tile);
+    (tiles, threshold)
+}
+
+
+/// Concurrent version of [`tile_counts`](../fn.tile_counts.html).
+pub fn tile_counts(points: &[Point], precision: Float, nr_threads: usize) -> (TileCounts, Float) {
+    let tiling = Tiling::new(precision);
+    (tile_counts_with(points, &tiling, nr_threads), tiling.scalars[0])
+}
+
+
+/// Concurrent version of [`tile_counts_with`](../fn.tile_counts_with.html).
+pub fn tile_counts_with(points: &[Point], tiling: &Tiling, nr_threads: usize) -> TileCounts {
+    accumulate_tile_counts(points, tiling, nr_threads).into_iter().collect()
+}
+
+
+/// Concurrent version of [`map_to_tile_counts`](../fn.map_to_tile_counts.html).
+pub fn map_to_tile_counts(
+    points: &[Point],
+    precision: Float,
+    threshold: usize,
+    nr_threads: usize,
+) -> (TileCounts, Float) {
+    let tiling = Tiling::new(precision);
+    (map_to_tile_counts_with(points, &tiling, threshold, nr_threads), tiling.scalars[0])
+}
+
+
+/// Concurrent version of [`map_to_tile_counts_with`](../fn.map_to_tile_counts_with.html).
+pub fn map_to_tile_counts_with(
+    points: &[Point],
+    tiling: &Tiling,
+    threshold: usize,
+    nr_threads: usize,
+) -> TileCounts {
+    accumulate_tile_counts(points, tiling, nr_threads)
+        .into_iter()
+        .filter(|(_, count)| *count >= threshold )
+        .collect()
+}
+
+
+/// Concurrent version of [`map_to_weighted_tiles`](../fn.map_to_weighted_tiles.html).
+pub fn map_to_weighted_tiles(
+    points: &[WeightedPoint],
+    precision: Float,
+    threshold: Float,
+    nr_threads: usize,
+) -> (impl Iterator<Item=Tile>, Float) {
+    let tiling = Tiling::new(precision);
+    (map_to_weighted_tiles_with(points, &tiling, threshold, nr_threads), tiling.scalars[0])
+}
+
+
+/// Concurrent version of [`map_to_weighted_tiles_with`](../fn.map_to_weighted_tiles_with.html).
+pub fn map_to_weighted_tiles_with(
+    points: &[WeightedPoint],
+    tiling: &Tiling,
+    threshold: Float,
+    nr_threads: usize,
+) -> impl Iterator<Item=Tile> {
+    accumulate(points, tiling, nr_threads, nd::to_tile_weights)
+        .into_iter()
+        .filter(move |(_, weight)| *weight >= threshold )
+        .map(|(tile, _)| tile)
+}
+
+
+/// Concurrent version of [`tile_weights`](../fn.tile_weights.html).
+pub fn tile_weights(points: &[WeightedPoint], precision: Float, nr_threads: usize) -> (TileWeights, Float) {
+    let tiling = Tiling::new(precision);
+    (tile_weights_with(points, &tiling, nr_threads), tiling.scalars[0])
+}
+
+
+/// Concurrent version of [`tile_weights_with`](../fn.tile_weights_with.html).
+pub fn tile_weights_with(points: &[WeightedPoint], tiling: &Tiling, nr_threads: usize) -> TileWeights {
+    accumulate(points, tiling, nr_threads, nd::to_tile_weights).into_iter().collect()
+}
+
+
+/// Concurrent version of [`sorted::sorted_tile_counts`](../sorted/fn.sorted_tile_counts.html).
+/// Every batch of points is sorted as a task of its own and the sorted batches are merged.
+pub fn sorted_tile_counts(points: &[Point], tiling: &Tiling, order: KeyOrder, nr_threads: usize) -> SortedCounts {
+    let batches = batch_data(points, nr_threads)
+        .map(|data| crate::sorted::sorted_tile_counts(data, tiling, order))
+        .collect();
+    SortedCounts::merge(batches)
+}
+
+
+/// Counts the points of every tile in `nr_threads` batches and sums the counts.
+fn accumulate_tile_counts(points: &[Point], tiling: &Tiling, nr_threads: usize) -> HashMap<Tile, usize> {
+    accumulate(points, tiling, nr_threads, to_tile_counts)
+}
+
+
+/// Applies `count` to `nr_threads` batches of points and sums the values of each tile.
+fn accumulate<P, V>(
+    points: &[P],
+    tiling: &Tiling,
+    nr_threads: usize,
+    count: fn(&[P], &Tiling) -> HashMap<Tile, V>,
+) -> HashMap<Tile, V>
+where
+    P: Sync,
+    V: AddAssign + Send,
+{
+    batch_data(points, nr_threads)
+        .map(|data| count(data, tiling))
+        .reduce_with(|mut acc, mut tiles| {
+            // sum the counters of the smaller map into the larger one
+            if acc.len() < tiles.len() {
+                std::mem::swap(&mut acc, &mut tiles);
+            }
+            for (t, t_count) in tiles {
+                match acc.entry(t) {
+                    Entry::Occupied(mut e) => *e.get_mut() += t_count,
+                    Entry::Vacant(e) => { e.insert(t_count); },
+                }
+            }
+            acc
+        })
+        .unwrap_or_default()
 }
 
 
 /// Takes an iterator of significant tiles and split them into `nr_slices` slices
-/// depending on their spacial horizontal alignments. These tiles need to be between
-/// `min` and `max` on the y-axis and this bound will be scaled with `scalar` to fit the tiles.
+/// depending on their spacial horizontal alignments. The range `min..=max` is cut into slices of
+/// equal width, which are scaled with `scalar` to fit the tiles. Tiles outside of the range end up
+/// in the first or last slice, see [`split_by_extent`](fn.split_by_extent.html) to take the range
+/// from the tiles instead.
 #[inline]
 pub fn split_vertically(
     all_tiles: impl Iterator<Item=Tile>,
@@ -86,64 +256,176 @@ pub fn split_vertically(
     scalar: Float,
     nr_slices: usize,
 ) -> Vec<(i32, TileSet, i32)>{
-    // Compute splitting coordinates depending on nr_slices.
-    if nr_slices < 2 {
-        return vec![(std::i32::MIN, all_tiles.collect(), std::i32::MAX)];
-    }
-
-    let step = ((max.abs() + min.abs()) as Float / nr_slices as Float) as i32;
-    let mut splits = Vec::new();
-    let mut split = min + step;
-    for _ in 1..nr_slices {
-        splits.push((split as Float * scalar) as i32); // scale and push
-        split += step;
-    }
-
-    // Initialize tile_slices with border values
-    let mut tile_slices: Vec<(i32, TileSet, i32)> = Vec::with_capacity(splits.len()+1);
-    for i in 0..tile_slices.capacity() {
-        let left_bound = *splits.get(i.wrapping_sub(1)).unwrap_or(&std::i32::MIN);
-        let right_bound = (*splits.get(i).unwrap_or(&std::i32::MIN)).wrapping_sub(1); // split-1 or wrap around to MAX
-        tile_slices.push((left_bound, IndexSet::new(), right_bound));
-    }
-    // Split all_tiles into nr_threads sets
-    for x in all_tiles {
-        for i in 0..splits.len() {
-            if x.0 < splits[i] {
-                tile_slices[i].1.insert(x);
-                break;
-            }
-        }
-        if x.0 >= *splits.last().unwrap() {
-            tile_slices[splits.len()].1.insert(x);
-        }
+    slice_at(all_tiles, &equal_width_splits(min, max, scalar, nr_slices))
+}
+
+
+/// Splits `all_tiles` into `nr_slices` slices of equal width over the columns of the tiles,
+/// whatever their coordinate system.
+pub fn split_by_extent(all_tiles: impl Iterator<Item=Tile>, nr_slices: usize) -> Vec<(i32, TileSet, i32)> {
+    let tiles: Vec<Tile> = all_tiles.collect();
+    let splits = extent_splits(tiles.iter().map(|t| t.0), nr_slices);
+    slice_at(tiles.into_iter(), &splits)
+}
+
+
+/// Puts every tile in the slice between the `splits` that holds its column.
+fn slice_at(all_tiles: impl Iterator<Item=Tile>, splits: &[i32]) -> Vec<(i32, TileSet, i32)> {
+    let mut tile_slices: Vec<(i32, TileSet, i32)> = empty_slices(splits);
+    for tile in all_tiles {
+        tile_slices[splits.partition_point(|s| *s <= tile.0)].1.insert(tile);
     }
     tile_slices
 }
 
 
+/// The boundaries between `nr_slices` slices of equal width over `min..=max`, scaled with `scalar`
+/// and kept within `MIN_COORDINATE..=MAX_COORDINATE` so that the sentinels `i32::MIN` and
+/// `i32::MAX` stay free. Boundaries that fall together are only kept once.
+pub(crate) fn equal_width_splits(min: i32, max: i32, scalar: Float, nr_slices: usize) -> Vec<i32> {
+    let (min, max) = (min.min(max) as i64, min.max(max) as i64);
+    let step = (max - min) / nr_slices.max(1) as i64;
+    let mut splits: Vec<i32> = (1..nr_slices as i64)
+        .map(|k| ((((min + k * step) as Float) * scalar) as i64).clamp(MIN_COORDINATE as i64, MAX_COORDINATE as i64) as i32)
+        .collect();
+    splits.sort_unstable();
+    splits.dedup();
+    splits
+}
+
+
+/// The boundaries between `nr_slices` slices of equal width over the columns `xs`.
+pub(crate) fn extent_splits(xs: impl Iterator<Item=i32>, nr_slices: usize) -> Vec<i32> {
+    let (min, max) = xs.fold((i32::MAX, i32::MIN), |(lo, hi), x| (lo.min(x), hi.max(x)));
+    if min > max {
+        return vec![];
+    }
+    // the slices cover the columns min..=max, i.e. the range min..max + 1
+    let splits = equal_width_splits(min, max.saturating_add(1), 1., nr_slices);
+    splits.into_iter().filter(|&s| s > min).collect()
+}
+
+
+/// Splits `all_tiles` into at most `nr_slices` slices of columns that hold about as many tiles,
+/// with boundaries at the quantiles of the x-coordinates of the tiles, so that every thread gets
+/// as much work wherever the data is. The range of the tiles need not be known. The tiles of a
+/// column are never split, so a column with more than its share of the tiles leaves fewer slices.
+/// Returns the slices and their number of tiles.
+pub fn split_balanced(all_tiles: impl Iterator<Item=Tile>, nr_slices: usize) -> (Vec<(i32, TileSet, i32)>, Vec<usize>) {
+    let tiles: Vec<Tile> = all_tiles.collect();
+    let splits = quantile_splits(tiles.iter().map(|t| t.0).collect(), nr_slices);
+    let tile_slices = slice_at(tiles.into_iter(), &splits);
+    let counts = tile_slices.iter().map(|(_, tiles, _)| tiles.len()).collect();
+    (tile_slices, counts)
+}
+
+
+/// The boundaries between at most `nr_slices` slices holding about as many of `xs`, each boundary
+/// being the first column of a slice. No slice is empty unless `xs` is.
+pub(crate) fn quantile_splits(mut xs: Vec<i32>, nr_slices: usize) -> Vec<i32> {
+    if xs.is_empty() {
+        return vec![];
+    }
+    xs.sort_unstable();
+    let mut splits: Vec<i32> = (1..nr_slices)
+        .map(|k| xs[k * xs.len() / nr_slices])
+        .filter(|&x| x > xs[0])
+        .collect();
+    splits.dedup();
+    splits
+}
+
+
+/// Slices without tiles between the boundaries `splits`, from `i32::MIN` to `i32::MAX`.
+pub(crate) fn empty_slices<S: Default>(splits: &[i32]) -> Vec<(i32, S, i32)> {
+    (0..=splits.len())
+        .map(|i| {
+            let left_bound = if i == 0 { i32::MIN } else { splits[i - 1] };
+            let right_bound = splits.get(i).map_or(i32::MAX, |s| s - 1);
+            (left_bound, S::default(), right_bound)
+        })
+        .collect()
+}
+
+
 /// Concurrent version of [`cluster_tiles`](../fn.cluster_tiles.html).
 pub fn cluster_tiles(slices: Vec<(i32, TileSet, i32)>, min_cluster_size: usize) -> Vec<TileSet> {
+    cluster_tiles_with(slices, &ClusterParams::new(min_cluster_size))
+}
+
+
+/// Concurrent version of [`cluster_tiles_with`](../fn.cluster_tiles_with.html).
+/// Only the minimum number of tiles of `params` applies, see
+/// [`cluster_tile_counts_with`](fn.cluster_tile_counts_with.html) for a minimum number of points.
+pub fn cluster_tiles_with(slices: Vec<(i32, TileSet, i32)>, params: &ClusterParams) -> Vec<TileSet> {
+    cluster_tiles_timed(slices, params).0
+}
+
+
+/// Same as [`cluster_tiles_with`](fn.cluster_tiles_with.html) but also returns the time spent on
+/// clustering the slices on their own and on joining their clusters across the seams.
+///
+/// With `SeamMerge::Tree` every slice is clustered with the engine of `params` and the slices are
+/// then merged pairwise as a reduction tree: the two halves of the slices are merged in parallel
+/// and the clusters at the seam between them are joined by unioning their ids. A cluster is only
+/// kept for the next level if it still touches an outer edge of the merged slices.
+pub fn cluster_tiles_timed(slices: Vec<(i32, TileSet, i32)>, params: &ClusterParams) -> (Vec<TileSet>, PhaseTimes) {
+    let slices = merge_narrow_slices(slices, params.connectivity.reach());
 
     if slices.len() < 2 {
-        if let Some((_, tiles, _)) = slices.into_iter().next() {
-            return crate::cluster_tiles(tiles, min_cluster_size);
-        }
-        else {
-            return vec![];
-        }
+        let start = Instant::now();
+        let clusters = match slices.into_iter().next() {
+            Some((_, tiles, _)) => crate::cluster_tiles_with(tiles, params),
+            None => vec![],
+        };
+        return (clusters, PhaseTimes { local: start.elapsed(), merge: Duration::ZERO });
     }
-
-    let mut forks = Vec::new();
-    for (left, tiles, right) in slices {
-        forks.push(thread::spawn(move || {
-            cluster_slice(tiles, min_cluster_size, left, right)
-        }));
+    match (params.merge, params.engine) {
+        (SeamMerge::Tree, _) => merge_slices(slices, params),
+        (SeamMerge::Sequential, ClusterEngine::UnionFind) => union_slices(slices, params),
+        (SeamMerge::Sequential, _) => join_slices(slices, params),
     }
+}
+
+
+/// Tree version of the clustering of slices, see [`cluster_tiles_timed`](fn.cluster_tiles_timed.html).
+fn merge_slices(slices: Vec<(i32, TileSet, i32)>, params: &ClusterParams) -> (Vec<TileSet>, PhaseTimes) {
+    let reach = params.connectivity.reach();
+    let unfiltered = ClusterParams { min_cluster_size: 1, ..*params };
+    let keep = |c: &TileSet| c.len() >= params.min_cluster_size;
 
-    let (fst, snd) = forks.into_iter().map(|f| f.join().unwrap()).unzip();
+    let start = Instant::now();
+    let segments: Vec<Segment<TileSet>> = slices
+        .into_par_iter()
+        .map(|(left, tiles, right)| {
+            let clusters = crate::cluster_tiles_with(tiles, &unfiltered);
+            Segment::new(clusters, |c| edge_tiles(c.iter(), left, right, reach), keep)
+        })
+        .collect();
+    let local = start.elapsed();
+
+    let start = Instant::now();
+    let clusters = merge_tree(segments, params.connectivity, &keep).into_clusters(keep);
+    (clusters, PhaseTimes { local, merge: start.elapsed() })
+}
+
+
+/// Sequential version of the clustering of slices, which joins the clusters at the seams from
+/// right to left.
+fn join_slices(slices: Vec<(i32, TileSet, i32)>, params: &ClusterParams) -> (Vec<TileSet>, PhaseTimes) {
+    let min_cluster_size = params.min_cluster_size;
+
+    let mut seams: Vec<i32> = slices.iter().map(|(left, _, _)| *left).collect();
+    let start = Instant::now();
+    let (fst, snd) = slices
+        .into_par_iter()
+        .map(|(left, tiles, right)| cluster_slice(tiles, params, left, right))
+        .unzip();
     let clusters_2d: Vec<Vec<TileSet>> = fst;
-    let edges: Vec<(Vec<TileSet>, Vec<TileSet>, Vec<TileSet>)> = snd;
+    let edges: Vec<EdgeClusters> = snd;
+    let local = start.elapsed();
+
+    let start = Instant::now();
     let mut left_edges = Vec::new();
     let mut left_right_edges = Vec::new();
     let mut right_edges = Vec::new();
@@ -162,11 +444,12 @@ pub fn cluster_tiles(slices: Vec<(i32, TileSet, i32)>, min_cluster_size: usize)
     );
 
     // traverse right_edges, left_edges, and left_right_edges backwards (from right to left)
-    assert_eq!(0, right_edges.pop().unwrap().len()); // The right most is touching infinity (i32::MAX),
-                                                     // which no cluster should be able to do.
-    assert_eq!(0, left_right_edges.pop().unwrap().len());
-
-    let mut trans_slices = Vec::new();
+    // The clusters at the right edge of the rightmost slice have no slice to join to their right,
+    // which only happens when the edge is not at i32::MAX. The ones that also touch the left edge
+    // of that slice are joined at its seam like the clusters that cross slices.
+    let outer_right = right_edges.pop().unwrap();
+    clusters.extend(outer_right.into_iter().filter(|c| c.len() >= min_cluster_size));
+    let mut trans_slices = left_right_edges.pop().unwrap();
     while let Some(right) = right_edges.pop() {
         let mut left = left_edges.pop().unwrap();
         left.extend(trans_slices);
@@ -174,30 +457,378 @@ pub fn cluster_tiles(slices: Vec<(i32, TileSet, i32)>, min_cluster_size: usize)
         // Note that "right" and "left" switch place in the call to join_clusters.
         // This is because "right" is clusters to the right within a slice while the parameter
         // to join_clusters refers to the right side of a border between slices
-        let (joined_clusters, transient) = join_clusters(right, left, left_right, min_cluster_size);
+        // the seam is at the left edge of the slice to its right
+        let seam = seams.pop().unwrap();
+        let (joined_clusters, transient) = join_clusters(right, left, left_right, seam, params);
         trans_slices = transient;
         clusters.extend(joined_clusters);
     }
 
     clusters.extend(trans_slices.into_iter().filter(|c| c.len() >= min_cluster_size));
 
-    assert_eq!(0, left_edges.pop().unwrap().len()); // The right most is touching infinity (i32::MIN),
-                                                    // which no cluster should be able to do.
+    // Likewise for the left edge of the leftmost slice when it is not at i32::MIN.
+    let outer_left = left_edges.pop().unwrap();
+    clusters.extend(outer_left.into_iter().filter(|c| c.len() >= min_cluster_size));
+
+    (clusters, PhaseTimes { local, merge: start.elapsed() })
+}
+
+
+/// The time spent on each phase of the concurrent clustering.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct PhaseTimes {
+    /// Clustering every slice on its own.
+    pub local: Duration,
+    /// Joining the clusters across the seams between the slices.
+    pub merge: Duration,
+}
+
 
+/// The tiles of a cluster within `reach` columns of the (left edge, right edge) of its slice.
+pub(crate) fn edge_tiles<'a>(tiles: impl Iterator<Item=&'a Tile>, left: i32, right: i32, reach: i32) -> (Vec<Tile>, Vec<Tile>) {
+    let mut edges = (Vec::new(), Vec::new());
+    for tile in tiles {
+        let (touches_left, touches_right) = touches_edges(tile.0, left, right, reach);
+        if touches_left {
+            edges.0.push(*tile);
+        }
+        if touches_right {
+            edges.1.push(*tile);
+        }
+    }
+    edges
+}
+
+
+/// A cluster of consecutive slices with its tiles near the left and the right edge of the slices.
+struct OpenCluster<C> {
+    cluster: C,
+    left: Vec<Tile>,
+    right: Vec<Tile>,
+}
+
+
+/// The clusters of consecutive slices: the ones that are done and the ones that touch an outer
+/// edge of the slices, which can still be joined with the clusters of the adjacent slices.
+pub(crate) struct Segment<C> {
+    done: Vec<C>,
+    open: Vec<OpenCluster<C>>,
+}
+
+impl<C> Segment<C>
+where
+    C: Default + IntoIterator + Extend<<C as IntoIterator>::Item>,
+{
+    /// The segment of one slice and its `clusters`, where `edges` gives the tiles of a cluster
+    /// near the (left edge, right edge) of the slice and `keep` tells the clusters that are done.
+    pub(crate) fn new(clusters: Vec<C>, edges: impl Fn(&C) -> (Vec<Tile>, Vec<Tile>), keep: impl Fn(&C) -> bool) -> Self {
+        let mut segment = Segment { done: Vec::new(), open: Vec::new() };
+        for cluster in clusters {
+            let (left, right) = edges(&cluster);
+            segment.push(OpenCluster { cluster, left, right }, &keep);
+        }
+        segment
+    }
+
+    fn push(&mut self, cluster: OpenCluster<C>, keep: impl Fn(&C) -> bool) {
+        if !cluster.left.is_empty() || !cluster.right.is_empty() {
+            self.open.push(cluster);
+        }
+        else if keep(&cluster.cluster) {
+            self.done.push(cluster.cluster);
+        }
+    }
+
+    /// Merges with the segment to the right, joining the clusters that have neighboring tiles at
+    /// the seam with a union-find over their ids.
+    fn join(self, right: Self, connectivity: Connectivity, keep: impl Fn(&C) -> bool) -> Self {
+        let (seam_left, mut open): (Vec<_>, Vec<_>) = self.open.into_iter().partition(|c| !c.right.is_empty());
+        let (seam_right, right_open): (Vec<_>, Vec<_>) = right.open.into_iter().partition(|c| !c.left.is_empty());
+        open.extend(right_open);
+        let mut done = self.done;
+        done.extend(right.done);
+
+        let nr_left = seam_left.len();
+        let index = SeamIndex::new(seam_right.iter().map(|c| c.left.iter().cloned()));
+        let mut sets = DisjointSets::new(nr_left + seam_right.len());
+        for (id, c) in seam_left.iter().enumerate() {
+            for tile in c.right.iter() {
+                for other in index.neighbors(tile, connectivity) {
+                    sets.union(id, nr_left + other);
+                }
+            }
+        }
+
+        let mut members: Vec<Option<OpenCluster<C>>> = seam_left.into_iter().chain(seam_right).map(Some).collect();
+        let mut segment = Segment { done, open };
+        for group in sets.groups() {
+            let mut joined = OpenCluster { cluster: C::default(), left: Vec::new(), right: Vec::new() };
+            for id in group {
+                let member = members[id].take().unwrap();
+                joined.cluster.extend(member.cluster);
+                // the tiles at the seam can no longer be joined with anything
+                if id < nr_left {
+                    joined.left.extend(member.left);
+                }
+                else {
+                    joined.right.extend(member.right);
+                }
+            }
+            segment.push(joined, &keep);
+        }
+        segment
+    }
+
+    /// The clusters that are kept. The outer edges are the sentinels, which no tile touches.
+    pub(crate) fn into_clusters(self, keep: impl Fn(&C) -> bool) -> Vec<C> {
+        let mut clusters = self.done;
+        clusters.extend(self.open.into_iter().map(|c| c.cluster).filter(|c| keep(c)));
+        clusters
+    }
+}
+
+
+/// Merges consecutive `segments` as a reduction tree, the two halves being merged in parallel.
+pub(crate) fn merge_tree<C, K>(mut segments: Vec<Segment<C>>, connectivity: Connectivity, keep: &K) -> Segment<C>
+where
+    C: Default + IntoIterator + Extend<<C as IntoIterator>::Item> + Send,
+    K: Fn(&C) -> bool + Sync,
+{
+    if segments.len() < 2 {
+        return segments.pop().unwrap_or(Segment { done: Vec::new(), open: Vec::new() });
+    }
+    let right = segments.split_off(segments.len() / 2);
+    let (left, right) = rayon::join(
+        || merge_tree(segments, connectivity, keep),
+        || merge_tree(right, connectivity, keep),
+    );
+    left.join(right, connectivity, keep)
+}
+
+
+/// Union-find version of the clustering of slices. Every slice is clustered on its own and the
+/// clusters touching an edge of their slice are joined by unioning their ids across the seams,
+/// when one of their edge tiles has a neighbor in another such cluster.
+fn union_slices(slices: Vec<(i32, TileSet, i32)>, params: &ClusterParams) -> (Vec<TileSet>, PhaseTimes) {
+    let connectivity = params.connectivity;
+    let reach = connectivity.reach();
+
+    let start = Instant::now();
+    let parts = slices
+        .into_par_iter()
+        .map(|(left, tiles, right)| {
+            with_edge_tiles(union_find::cluster_tiles(tiles, connectivity), |t| {
+                touches_edges(t.0, left, right, reach) != (false, false)
+            })
+        })
+        .collect();
+    let local = start.elapsed();
+
+    let start = Instant::now();
+    let clusters = join_parts(parts, connectivity, |c| c.len() >= params.min_cluster_size);
+    (clusters, PhaseTimes { local, merge: start.elapsed() })
+}
+
+
+/// A cluster whose tiles can be listed, either a set of tiles or tiles that hold a value each.
+pub(crate) trait ClusterTiles {
+    fn tiles(&self) -> impl Iterator<Item=&Tile>;
+}
+
+impl ClusterTiles for TileSet {
+    fn tiles(&self) -> impl Iterator<Item=&Tile> {
+        self.iter()
+    }
+}
+
+impl<V> ClusterTiles for IndexMap<Tile, V> {
+    fn tiles(&self) -> impl Iterator<Item=&Tile> {
+        self.keys()
+    }
+}
+
+
+/// Pairs every cluster with its tiles for which `is_edge` holds.
+pub(crate) fn with_edge_tiles<C: ClusterTiles>(clusters: Vec<C>, is_edge: impl Fn(&Tile) -> bool) -> Vec<(C, Vec<Tile>)> {
     clusters
+        .into_iter()
+        .map(|cluster| {
+            let edge_tiles = cluster.tiles().filter(|t| is_edge(t)).cloned().collect();
+            (cluster, edge_tiles)
+        })
+        .collect()
+}
+
+
+/// Joins the clusters of parts of the tiles that were clustered on their own. Every cluster comes
+/// with its tiles that can have neighbors in another part, and the clusters are joined when one of
+/// these tiles has a neighbor among the ones of another cluster. Returns the joined clusters that
+/// `keep` accepts, the clusters without such tiles first.
+pub(crate) fn join_parts<C>(
+    parts: Vec<Vec<(C, Vec<Tile>)>>,
+    connectivity: Connectivity,
+    keep: impl Fn(&C) -> bool,
+) -> Vec<C>
+where
+    C: Default + IntoIterator + Extend<<C as IntoIterator>::Item>,
+{
+    let mut clusters = Vec::new();
+    let mut edge_clusters = Vec::new();
+    let mut edge_tiles = Vec::new();
+    for part in parts {
+        for (cluster, tiles) in part {
+            if !tiles.is_empty() {
+                edge_clusters.push(Some(cluster));
+                edge_tiles.push(tiles);
+            }
+            else if keep(&cluster) {
+                clusters.push(cluster);
+            }
+        }
+    }
+
+    let index = SeamIndex::new(edge_tiles.iter().map(|tiles| tiles.iter().cloned()));
+    let mut sets = DisjointSets::new(edge_tiles.len());
+    for (id, tiles) in edge_tiles.iter().enumerate() {
+        for tile in tiles {
+            for other in index.neighbors(tile, connectivity) {
+                sets.union(id, other);
+            }
+        }
+    }
+
+    for group in sets.groups() {
+        let mut joined = C::default();
+        for id in group {
+            joined.extend(edge_clusters[id].take().unwrap());
+        }
+        if keep(&joined) {
+            clusters.push(joined);
+        }
+    }
+    clusters
+}
+
+
+/// Concurrent version of [`cluster_tile_counts`](../fn.cluster_tile_counts.html).
+/// The slices come from [`prime::par::split_vertically`](../prime/par/fn.split_vertically.html),
+/// which also splits tile counts.
+pub fn cluster_tile_counts(slices: Vec<(i32, TileCounts, i32)>, min_cluster_size: usize) -> Vec<TileCounts> {
+    cluster_tile_counts_with(slices, &ClusterParams::new(min_cluster_size))
+}
+
+
+/// Concurrent version of [`cluster_tile_counts_with`](../fn.cluster_tile_counts_with.html).
+/// The minimum number of points and weight of `params` apply to the clusters joined across slices.
+pub fn cluster_tile_counts_with(slices: Vec<(i32, TileCounts, i32)>, params: &ClusterParams) -> Vec<TileCounts> {
+    crate::prime::par::cluster_maps_with(slices, params)
+}
+
+
+/// Concurrent version of [`label_points`](../fn.label_points.html).
+pub fn label_points(
+    points: &[Point],
+    clusters: &[TileSet],
+    scalar: Float,
+    nr_threads: usize,
+) -> Vec<Option<ClusterId>> {
+    label_points_with(points, clusters, &Tiling::from_scalars([scalar; 2]), nr_threads)
+}
+
+
+/// Concurrent version of [`label_points_with`](../fn.label_points_with.html).
+/// The points are labeled in `nr_threads` batches that are put back in input order.
+pub fn label_points_with(
+    points: &[Point],
+    clusters: &[TileSet],
+    tiling: &Tiling,
+    nr_threads: usize,
+) -> Vec<Option<ClusterId>> {
+    let cluster_of = nd::cluster_lookup(clusters);
+    points
+        .par_chunks(batch_size(points.len(), nr_threads))
+        .flat_map_iter(|data| data.iter().map(|p| cluster_of.get(&p.to_tile(tiling)).copied()))
+        .collect()
+}
+
+
+/// Concurrent version of [`fit_predict`](../fn.fit_predict.html).
+pub fn fit_predict(
+    points: &[Point],
+    precision: Float,
+    threshold: usize,
+    min_cluster_size: usize,
+    nr_threads: usize,
+) -> Vec<Option<ClusterId>> {
+    let params = ClusterParams::new(min_cluster_size);
+    fit_predict_with(points, &Tiling::new(precision), threshold, &params, nr_threads)
+}
+
+
+/// Concurrent version of [`fit_predict_with`](../fn.fit_predict_with.html).
+/// The tiles are split into `nr_threads` slices over their own range of columns.
+pub fn fit_predict_with(
+    points: &[Point],
+    tiling: &Tiling,
+    threshold: usize,
+    params: &ClusterParams,
+    nr_threads: usize,
+) -> Vec<Option<ClusterId>> {
+    let tiles = map_to_tiles_with(points, tiling, threshold, nr_threads);
+    let slices = split_by_extent(tiles, nr_threads);
+    let clusters = cluster_tiles_with(slices, params);
+    label_points_with(points, &clusters, tiling, nr_threads)
+}
+
+
+/// Merges every inner slice that is narrower than `reach` columns with the slice to its right.
+/// Afterwards a tile can only have neighbors in its own slice and the two adjacent slices.
+pub(crate) fn merge_narrow_slices<S, I>(slices: Vec<(i32, S, i32)>, reach: i32) -> Vec<(i32, S, i32)>
+where
+    S: IntoIterator<Item = I> + Extend<I>,
+{
+    let mut merged: Vec<(i32, S, i32)> = Vec::with_capacity(slices.len());
+
+    for (left, tiles, right) in slices {
+        if let Some(last) = merged.last_mut() {
+            let (last_left, _, last_right) = *last;
+            let is_inner = last_left != i32::MIN && last_right != i32::MAX;
+            if is_inner && (last_right as i64 - last_left as i64 + 1) < reach as i64 {
+                last.1.extend(tiles);
+                last.2 = right;
+                continue;
+            }
+        }
+        merged.push((left, tiles, right));
+    }
+    merged
+}
+
+
+/// Returns whether column `x` is within `reach` columns of the (left edge, right edge) of a slice.
+/// The sentinels `i32::MIN` and `i32::MAX` are never touched.
+#[inline]
+pub(crate) fn touches_edges(x: i32, left_edge: i32, right_edge: i32, reach: i32) -> (bool, bool) {
+    (
+        left_edge != i32::MIN && x < left_edge.saturating_add(reach),
+        right_edge != i32::MAX && x > right_edge.saturating_sub(reach),
+    )
 }
 
 
 /// Regular clustering within a slice, where clusters that touch left or right edge are marked in
 /// the output: (Significant clusters, (left clusters, left & right clusters, right clusters)).
+/// A cluster touches an edge if it has a tile within `connectivity.reach()` columns of it.
 /// Significant clusters are those that does not touch any edge and contains at least
 /// `min_cluster_size` tiles.
 fn cluster_slice(
     tiles: TileSet,
-    min_cluster_size: usize,
+    params: &ClusterParams,
     left_edge: i32,
     right_edge: i32,
-) -> (Vec<TileSet>, (Vec<TileSet>, Vec<TileSet>, Vec<TileSet>)) {
+) -> (Vec<TileSet>, EdgeClusters) {
+    let connectivity = params.connectivity;
+    let reach = connectivity.reach();
     let mut to_visit = tiles;
     let mut clusters = Vec::new();
     let mut left_clusters = Vec::new();
@@ -205,21 +836,21 @@ fn cluster_slice(
     let mut left_right_clusters = Vec::new();
 
     while let Some(x) = to_visit.pop() { // starting point for a new cluster
-        let mut is_left_edge_cluster = false;
-        let mut is_right_edge_cluster = false;
-        is_left_edge_cluster |= x.0 == left_edge; // mark cluster if x is an edge-tile
-        is_right_edge_cluster |= x.0 == right_edge; // mark cluster if x is an edge-tile
+        // mark cluster if x is an edge-tile
+        let (mut is_left_edge_cluster, mut is_right_edge_cluster) = touches_edges(x.0, left_edge, right_edge, reach);
 
         let mut cluster = IndexSet::new();
         cluster.insert(x);
 
-        let mut to_check: Vec<_> = pop_neighbors(x, &mut to_visit).collect();
+        let mut to_check: Vec<_> = pop_neighbors(x, &mut to_visit, connectivity).collect();
         while let Some(p) = to_check.pop() {
-            is_left_edge_cluster |= p.0 == left_edge; // mark cluster if p is an edge-tile
-            is_right_edge_cluster |= p.0 == right_edge; // mark cluster if p is an edge-tile
+            // mark cluster if p is an edge-tile
+            let (is_left, is_right) = touches_edges(p.0, left_edge, right_edge, reach);
+            is_left_edge_cluster |= is_left;
+            is_right_edge_cluster |= is_right;
             cluster.insert(p);
 
-            let new_neighbors = pop_neighbors(p, &mut to_visit);
+            let new_neighbors = pop_neighbors(p, &mut to_visit, connectivity);
             to_check.extend(new_neighbors);
         }
 
@@ -232,7 +863,7 @@ fn cluster_slice(
         else if is_right_edge_cluster {
             right_clusters.push(cluster);
         }
-        else if cluster.len() >= min_cluster_size {
+        else if cluster.len() >= params.min_cluster_size {
             clusters.push(cluster);
         }
     }
@@ -243,120 +874,359 @@ fn cluster_slice(
 /// Join clusters that are part of a neighborhood chain (connected). The left_clusters are those
 /// that touches the border from the left side, right_clusters from the right side and
 /// left_right_clusters are those that touches the border from the left side and another border to
-/// the left of this one.
+/// the left of this one. The border is between the columns `seam - 1` and `seam`.
 /// The output is two things:
 /// * significant clusters (#tiles >= min_cluster_size)
 /// * clusters that are a superset of a left_right_clusters
+///
 /// These clusters were the result of joining multiple clusters if there were a connection.
 fn join_clusters(
     left_clusters: Vec<TileSet>,
     right_clusters: Vec<TileSet>,
     left_right_clusters: Vec<TileSet>,
-    min_cluster_size: usize,
+    seam: i32,
+    params: &ClusterParams,
 ) -> (Vec<TileSet>, Vec<TileSet>,) {
-    let mut clusters = Vec::new();
-    let mut new_left_right_clusters = Vec::new();
-    let mut left_right = left_right_clusters;
-    let mut left = left_clusters;
-    let mut right = right_clusters;
-    let mut is_left_and_right;
-
-    while let Some(start) = right.pop() {
-        is_left_and_right = false;
-        let mut to_visit = vec![(false, start)];
-        let mut cluster = IndexSet::new();
-
-        while let Some((go_right, visit)) = to_visit.pop() {
-            if go_right {
-                let (neighbors, leftovers) = right.into_iter().partition(|c| is_cluster_neighbors(c, &visit));
-                right = leftovers;
+    join_at_seam(
+        left_clusters,
+        right_clusters,
+        left_right_clusters,
+        seam,
+        params.connectivity,
+        |c, columns| c.iter().filter(|t| columns.contains(&t.0)).cloned().collect(),
+        |c| c.len() >= params.min_cluster_size,
+    )
+}
 
-                for n in neighbors {
-                    to_visit.push((false, n));
-                }
-            } else {
-                let (neighbors, leftovers) = left.into_iter().partition(|c| is_cluster_neighbors(c, &visit));
-                left = leftovers;
 
-                for n in neighbors {
-                    to_visit.push((true, n));
-                }
-                // when going from right to left left_right_clusters is considered to be on the left side
-                let (neighbors, leftovers) = left_right.into_iter().partition(|c| is_cluster_neighbors(c, &visit));
-                left_right = leftovers; // remove old (unjoined) cluster
+/// Joins the clusters on the two sides of the seam before column `seam`, see
+/// [`join_clusters`](fn.join_clusters.html). `border` gives the tiles of a cluster in a range of
+/// columns, and only the tiles within reach of the seam are matched, through a
+/// [`SeamIndex`](struct.SeamIndex.html) of the ones on the right side.
+pub(crate) fn join_at_seam<C>(
+    left_clusters: Vec<C>,
+    right_clusters: Vec<C>,
+    left_right_clusters: Vec<C>,
+    seam: i32,
+    connectivity: Connectivity,
+    border: impl Fn(&C, RangeInclusive<i32>) -> Vec<Tile>,
+    keep: impl Fn(&C) -> bool,
+) -> (Vec<C>, Vec<C>)
+where
+    C: Default + IntoIterator + Extend<<C as IntoIterator>::Item>,
+{
+    let reach = connectivity.reach();
+    let left_columns = seam.saturating_sub(reach)..=seam.saturating_sub(1);
+    let right_columns = seam..=seam.saturating_add(reach - 1);
 
-                for n in neighbors {
-                    is_left_and_right = true; // assumed to happen quite rarely
-                    to_visit.push((true, n));
-                }
+    // the left_right_clusters follow the left_clusters, and the right_clusters come last
+    let nr_left = left_clusters.len();
+    let nr_both = nr_left + left_right_clusters.len();
+    let index = SeamIndex::new(right_clusters.iter().map(|c| border(c, right_columns.clone())));
+    let mut sets = DisjointSets::new(nr_both + right_clusters.len());
+    for (id, c) in left_clusters.iter().chain(left_right_clusters.iter()).enumerate() {
+        for tile in border(c, left_columns.clone()) {
+            for other in index.neighbors(&tile, connectivity) {
+                sets.union(id, nr_both + other);
             }
-            cluster.extend(visit); // join the visited sub-cluster
+        }
+    }
+
+    let mut members: Vec<Option<C>> = left_clusters
+        .into_iter()
+        .chain(left_right_clusters)
+        .chain(right_clusters)
+        .map(Some)
+        .collect();
+    let mut clusters = Vec::new();
+    let mut left_right = Vec::new();
+    for group in sets.groups() {
+        let is_left_and_right = group.iter().any(|id| (nr_left..nr_both).contains(id));
+        let mut cluster = C::default();
+        for id in group {
+            cluster.extend(members[id].take().unwrap());
         }
         if is_left_and_right {
-            new_left_right_clusters.push(cluster) // insert new (joined) cluster
+            left_right.push(cluster);
         }
-        else if cluster.len() >= min_cluster_size {
+        else if keep(&cluster) {
             clusters.push(cluster);
         }
     }
-    for c in left {
-        if c.len() >= min_cluster_size {
-            clusters.push(c);
-        }
-    }
-    left_right.extend(new_left_right_clusters);
     (clusters, left_right)
 }
 
 
-/// Returns true if cluster c1 is a neighbor to cluster c2.
-fn is_cluster_neighbors(c1: &TileSet, c2: &TileSet) -> bool {
-    for tile in c1.iter() {
-        if is_neighbors(*tile, c2) {
-            return true;
+/// The border tiles of clusters, e.g. the ones on one side of a seam, indexed by their row and
+/// sorted by their column within a row. The tiles that can neighbor a tile are in the rows within
+/// reach of its row, so every lookup only visits the few tiles of these rows within reach of its
+/// column instead of every tile of every cluster. The tiles along a horizontal seam share a few
+/// rows, which is why the columns of a row are searched rather than scanned.
+pub(crate) struct SeamIndex {
+    rows: HashMap<i32, Vec<(i32, usize)>>,
+}
+
+impl SeamIndex {
+    /// Indexes the tiles of every cluster, by the position of the cluster in `clusters`.
+    pub(crate) fn new<I>(clusters: impl Iterator<Item = I>) -> Self
+    where
+        I: IntoIterator<Item = Tile>,
+    {
+        let mut rows: HashMap<i32, Vec<(i32, usize)>> = HashMap::new();
+        for (id, tiles) in clusters.enumerate() {
+            for (x, y) in tiles {
+                rows.entry(y).or_default().push((x, id));
+            }
         }
+        for tiles in rows.values_mut() {
+            tiles.sort_unstable();
+        }
+        SeamIndex { rows }
     }
-    false
-}
 
+    /// The ids of the clusters with a tile that is a neighbor of `tile`, once per such tile.
+    pub(crate) fn neighbors<'a>(&'a self, tile: &Tile, connectivity: Connectivity) -> impl Iterator<Item = usize> + 'a {
+        let (x, y) = *tile;
+        let reach = connectivity.reach();
+        (y.saturating_sub(reach)..=y.saturating_add(reach))
+            .filter_map(move |row| self.rows.get(&row).map(|tiles| (row, tiles)))
+            .flat_map(move |(row, tiles)| {
+                let first = tiles.partition_point(|(column, _)| *column < x.saturating_sub(reach));
+                tiles[first..]
+                    .iter()
+                    .take_while(move |(column, _)| *column <= x.saturating_add(reach))
+                    .filter(move |(column, _)| is_adjacent((x, y), (*column, row), connectivity))
+                    .map(|(_, id)| *id)
+            })
+    }
+}
 
-/// Returns true if tile (x,y) is a neighbor to a tile in a set.
-fn is_neighbors((x, y): Tile, tiles: &TileSet) -> bool {
-    let candidates = [
-        (x + 1, y    ),
-        (x - 1, y    ),
-        (x    , y + 1),
-        (x    , y - 1),
-        (x + 1, y - 1),
-        (x + 1, y + 1),
-        (x - 1, y - 1),
-        (x - 1, y + 1),
-    ];
 
-    candidates.iter().any(|c| tiles.contains(c))
+/// Returns true if the tiles `a` and `b` are neighbors, cf. `GridTile::neighbors`.
+#[inline]
+fn is_adjacent(a: Tile, b: Tile, connectivity: Connectivity) -> bool {
+    let dx = (a.0 as i64 - b.0 as i64).abs();
+    let dy = (a.1 as i64 - b.1 as i64).abs();
+    match connectivity {
+        Connectivity::Four => dx + dy == 1,
+        Connectivity::Eight | Connectivity::Radius(_) => {
+            (dx, dy) != (0, 0) && dx.max(dy) <= connectivity.reach() as i64
+        },
+    }
 }
 
 
 
-#[test]
-fn join_right_to_left_n_right_to_right() {
-    use std::iter::FromIterator;
+/////////////////////////////////////////////////////////////////////////////////////
+/// Unit tests
+/////////////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Asserts that `clusters` are the `expected` ones, in any order.
+    fn assert_same_clusters<C: PartialEq + std::fmt::Debug>(clusters: &[C], expected: &[C]) {
+        assert_eq!(clusters.len(), expected.len());
+        for c in clusters {
+            assert!(expected.contains(c), "unexpected cluster {:?}", c);
+        }
+    }
+
+    #[test]
+    fn join_right_to_left_n_right_to_right() {
+        use std::iter::FromIterator;
+
+        let left: Vec<TileSet> = vec![];
+        let left_right: Vec<TileSet> = vec![IndexSet::from_iter(vec![(-2, 1), (-1, 1)])];
+        let right: Vec<TileSet> = vec![
+            IndexSet::from_iter(vec![(0, 0)]),
+            IndexSet::from_iter(vec![(0, 2)]),
+        ];
+        let (lr, rlrr) = join_clusters(left, right, left_right, 0, &ClusterParams::new(2));
+
+        let long_cluster: TileSet = IndexSet::from_iter(vec![(-2, 1), (-1, 1), (0, 0), (0, 2)]);
+
+        assert_eq!(lr.len(), 0);
+        assert_same_clusters(&rlrr, &[long_cluster]);
+    }
 
-    let left: Vec<TileSet> = vec![];
-    let left_right: Vec<TileSet> = vec![IndexSet::from_iter(vec![(-2, 1), (-1, 1)])];
-    let right: Vec<TileSet> = vec![
-        IndexSet::from_iter(vec![(0, 0)]),
-        IndexSet::from_iter(vec![(0, 2)]),
-    ];
-    let (lr, rlrr) = join_clusters(left, right, left_right, 2);
 
-    let long_cluster: TileSet = IndexSet::from_iter(vec![(-2, 1), (-1, 1), (0, 0), (0, 2)]);
+    #[test]
+    fn crowded_seam() {
+        // thousands of short clusters on both sides of the seam, every other pair of them joined
+        let left: Vec<TileSet> = (0..4000).map(|i| [(-2, 3 * i), (-1, 3 * i)].iter().cloned().collect()).collect();
+        let right: Vec<TileSet> = (0..4000).map(|i| [(i % 2 * 5, 3 * i), (1, 3 * i)].iter().cloned().collect()).collect();
+        let (clusters, left_right) = join_clusters(left.clone(), right.clone(), vec![], 0, &ClusterParams::new(4));
+        assert!(left_right.is_empty());
+        assert_eq!(clusters.len(), 2000);
+        assert!(clusters.iter().all(|c| c.len() == 4 && c.contains(&(0, c[0].1))));
 
-    assert_eq!(lr.len(), 0);
+        let index = SeamIndex::new(right.iter().map(|c| c.iter().cloned()));
+        assert_eq!(index.neighbors(&(-1, 3), Connectivity::Four).collect::<Vec<_>>(), vec![]);
+        assert_eq!(index.neighbors(&(-1, 4), Connectivity::Eight).collect::<Vec<_>>(), vec![]);
+        assert_eq!(index.neighbors(&(-1, 5), Connectivity::Eight).collect::<Vec<_>>(), vec![2]);
+        assert_eq!(index.neighbors(&(-1, 4), Connectivity::Radius(2)).count(), 3);
+
+        // the border of one cluster on the left and a cluster that crosses slices on the right
+        let left_right: Vec<TileSet> = vec![[(-3, 0), (-1, 1)].iter().cloned().collect()];
+        let (clusters, left_right) = join_clusters(left, right, left_right, 0, &ClusterParams::new(4));
+        assert_eq!((clusters.len(), left_right.len()), (1999, 1));
+        assert_eq!(left_right[0].len(), 6);
+    }
+
+
+    #[test]
+    fn slices_within_bounds() {
+        // the slices do not reach i32::MIN and i32::MAX, and clusters touch their outer edges
+        let tiles: TileSet = [(0, 0), (1, 0), (28, 5), (29, 5), (5, 30), (5, 31)].iter().cloned()
+            .chain((15..=29).map(|x| (x, 10)))
+            .chain((0..=9).map(|x| (x, 20)))
+            .collect();
+        let bounds = [(0, 9), (10, 19), (20, 29)];
+        let slices: Vec<(i32, TileSet, i32)> = bounds
+            .iter()
+            .map(|&(left, right)| (left, tiles.iter().filter(|t| left <= t.0 && t.0 <= right).cloned().collect(), right))
+            .collect();
+
+        for &engine in [ClusterEngine::Bfs, ClusterEngine::UnionFind, ClusterEngine::Auto].iter() {
+            for &merge in [SeamMerge::Sequential, SeamMerge::Tree].iter() {
+                let params = ClusterParams::new(2).with_engine(engine).with_merge(merge);
+                let expected = crate::cluster_tiles_with(tiles.clone(), &params);
+                assert_eq!(expected.len(), 5);
+                assert_same_clusters(&cluster_tiles_with(slices.clone(), &params), &expected);
+
+                let maps: Vec<(i32, crate::prime::TileMap, i32)> = slices
+                    .iter()
+                    .map(|(left, tiles, right)| (*left, tiles.iter().map(|t| (*t, vec![Point::new(t.0 as Float, t.1 as Float)])).collect(), *right))
+                    .collect();
+                assert_eq!(crate::prime::par::cluster_tiles_with(maps, &params).len(), expected.len());
+            }
+        }
+    }
+
+
+    #[test]
+    fn balanced_slices() {
+        // most tiles are in a few columns on the right
+        let tiles: TileSet = (0..40).map(|y| (-100, y))
+            .chain((0..400).map(|i| (1000 + i % 8, i / 8)))
+            .collect();
+        let (slices, counts) = split_balanced(tiles.iter().cloned(), 4);
+        assert_eq!(counts, vec![90, 100, 100, 150]); // the quantiles are in the columns 1001, 1003 and 1005
+        assert_eq!(counts, slices.iter().map(|s| s.1.len()).collect::<Vec<_>>());
+        assert_eq!((slices[0].0, slices[3].2), (i32::MIN, i32::MAX));
+        for w in slices.windows(2) {
+            assert_eq!(w[0].2 + 1, w[1].0);
+        }
+        for (left, tiles, right) in slices.iter() {
+            assert!(tiles.iter().all(|t| *left <= t.0 && t.0 <= *right));
+        }
+
+        assert_same_clusters(&cluster_tiles(slices, 1), &crate::cluster_tiles(tiles.clone(), 1));
+
+        // one column can not be split
+        let (slices, counts) = split_balanced((0..10).map(|y| (3, y)), 4);
+        assert_eq!((slices.len(), counts), (1, vec![10]));
+        let (slices, counts) = split_balanced(std::iter::empty(), 4);
+        assert_eq!((slices.len(), counts), (1, vec![0]));
+    }
+
+
+    #[test]
+    fn any_coordinate_system() {
+        // pixel coordinates of an image, far from -180..180
+        let points: Vec<Point> = (0..3000)
+            .map(|i| Point::new(5000. + (i % 60) as Float * 3. + (i / 600) as Float * 400., 1200. + (i / 60 % 10) as Float * 3.))
+            .collect();
+        let tiling = Tiling::from_cell_sizes([10., 10.]);
+        let expected = crate::cluster_tiles(crate::map_to_tiles_with(&points, &tiling, 2), 2);
+        assert_eq!(expected.len(), 5);
+
+        for &nr_slices in [1, 2, 3, 7, 64].iter() {
+            let tiles = map_to_tiles_with(&points, &tiling, 2, nr_slices);
+            let slices = split_by_extent(tiles, nr_slices);
+            assert!(slices.len() <= nr_slices.max(1));
+            assert_same_clusters(&cluster_tiles(slices, 2), &expected);
+
+            let tiles = crate::prime::par::map_to_tiles_with(&points, &tiling, 2, nr_slices);
+            let slices = crate::prime::par::split_by_extent(tiles, nr_slices);
+            assert_eq!(crate::prime::par::cluster_tiles(slices, 2).len(), expected.len());
+        }
+
+        // the geographic range of the benchmark puts the tiles in one slice, but does not fail
+        let tiles = map_to_tiles_with(&points, &tiling, 2, 4);
+        let slices = split_vertically(tiles, -180, 180, 0.1, 4);
+        assert_eq!(cluster_tiles(slices, 2).len(), expected.len());
+        for &(min, max) in [(i32::MIN, i32::MAX), (200, 100), (0, 0)].iter() {
+            let slices = split_vertically(expected.iter().flatten().cloned(), min, max, 1., 4);
+            assert_eq!(cluster_tiles(slices, 2).len(), expected.len());
+        }
+
+        // fewer points than threads, or none at all
+        assert_eq!(tile_counts_with(&points[..3], &tiling, 8).values().sum::<usize>(), 3);
+        assert_eq!(fit_predict_with(&points[..3], &tiling, 1, &ClusterParams::new(1), 8), vec![Some(0); 3]);
+        assert!(fit_predict(&[], 0., 1, 1, 4).is_empty());
+        assert_eq!(map_to_tiles_with(&[], &tiling, 1, 0).count(), 0);
+        assert_eq!(crate::prime::par::map_to_tiles_with(&[], &tiling, 1, 4).count(), 0);
+        assert!(cluster_tiles(split_by_extent(std::iter::empty(), 4), 1).is_empty());
+    }
+
+
+    #[test]
+    fn caller_supplied_pool() {
+        let points: Vec<Point> = (0..2000)
+            .map(|i| Point::new((i % 50) as Float * 0.1, (i / 50) as Float * 0.1))
+            .collect();
+        let tiling = Tiling::new(0.);
+        let expected = crate::cluster_tiles(crate::map_to_tiles_with(&points, &tiling, 2), 1);
+
+        let pool = ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+        let (clusters, labels) = pool.install(|| {
+            assert_eq!(rayon::current_num_threads(), 2);
+            let tiles = map_to_tiles_with(&points, &tiling, 2, 8);
+            let clusters = cluster_tiles(split_vertically(tiles, -180, 180, 1., 3), 1);
+            let labels = label_points_with(&points, &clusters, &tiling, 8);
+            (clusters, labels)
+        });
+        assert_same_clusters(&clusters, &expected);
+        assert!(labels.iter().all(|l| l.is_some()));
+        assert_eq!(fit_predict(&points, 0., 2, 1, 4), pool.install(|| fit_predict(&points, 0., 2, 1, 4)));
+    }
+
+
+    #[test]
+    fn tree_merge() {
+        // combs whose teeth are only joined far from most seams, a long diagonal and scattered blobs
+        let mut tiles: TileSet = (0..60).map(|x| (x, 0)).collect();
+        tiles.extend((0..60).step_by(2).flat_map(|x| (1..10).map(move |y| (x, y))));
+        tiles.extend((0..40).map(|i| (i, 40 + i)));
+        tiles.extend((0..300).map(|i: i32| (i % 7 * 9, 15 + i / 7 % 3 + i / 21 * 5)));
+        let slices = |n| split_balanced(tiles.iter().cloned(), n).0;
+
+        for &connectivity in [Connectivity::Four, Connectivity::Eight, Connectivity::Radius(2)].iter() {
+            for &engine in [ClusterEngine::Bfs, ClusterEngine::UnionFind, ClusterEngine::Auto].iter() {
+                let params = ClusterParams::new(3).with_connectivity(connectivity).with_engine(engine);
+                let expected = crate::cluster_tiles_with(tiles.clone(), &params);
+                assert!(expected.len() > 5);
+
+                for &nr_slices in [2, 3, 8, 30].iter() {
+                    for &merge in [SeamMerge::Sequential, SeamMerge::Tree].iter() {
+                        let (clusters, _) = cluster_tiles_timed(slices(nr_slices), &params.with_merge(merge));
+                        assert_same_clusters(&clusters, &expected);
+                    }
+                }
+            }
+        }
 
-    assert_eq!(rlrr.len(), 1);
-    assert_eq!(long_cluster.len(), rlrr[0].len());
-    for o in rlrr[0].clone() {
-        assert!(long_cluster.iter().any(|set| *set == o));
+        // the points of the whole cluster count, not the ones of a slice
+        let maps: Vec<(i32, crate::prime::TileMap, i32)> = slices(16)
+            .into_iter()
+            .map(|(left, tiles, right)| (left, tiles.into_iter().map(|t| (t, vec![Point::new(t.0 as Float, t.1 as Float)])).collect(), right))
+            .collect();
+        let params = ClusterParams::new(1).with_min_points(50);
+        let sequential = crate::prime::par::cluster_tiles_with(maps.clone(), &params.with_merge(SeamMerge::Sequential));
+        let (tree, _) = crate::prime::par::cluster_tiles_timed(maps, &params);
+        assert_eq!(tree.len(), 2);
+        assert_same_clusters(&tree, &sequential);
     }
 }
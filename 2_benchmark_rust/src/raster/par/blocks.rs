@@ -0,0 +1,214 @@
+/*!
+ * Concurrent clustering of a 2D grid of blocks of tiles.
+ *
+ * The slices of [`split_vertically`](../fn.split_vertically.html) only cut the columns, so the
+ * tiles of one tall and dense column can not be spread over several threads. Here the tiles are
+ * cut along both axes, at the quantiles of their columns and of their rows, and every block is
+ * clustered on its own. The clusters with a tile near an edge of their block are then stitched
+ * across the seams between blocks, horizontally, vertically and at the corners, by unioning the
+ * clusters that have neighboring edge tiles. The clusters are the same as with the sequential
+ * [`cluster_tiles_with`](../../fn.cluster_tiles_with.html).
+ *
+ * See [`prime::par::split_into_blocks`](../../prime/par/fn.split_into_blocks.html) for tiles that
+ * keep their points.
+ */
+
+use rayon::prelude::*;
+use std::time::Instant;
+
+use crate::{
+    ClusterParams,
+    Connectivity,
+    Tile,
+    TileSet,
+    par::{ClusterTiles, PhaseTimes, empty_slices, join_parts, quantile_splits, touches_edges, with_edge_tiles},
+};
+
+
+/// A rectangle of the columns `left..=right` and the rows `bottom..=top`, and its tiles.
+/// The outer blocks reach `i32::MIN` or `i32::MAX`, which no tile can touch.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Block<S> {
+    pub left: i32,
+    pub right: i32,
+    pub bottom: i32,
+    pub top: i32,
+    pub tiles: S,
+}
+
+impl<S> Block<S> {
+    /// Returns whether `tile` is within `reach` columns or rows of an edge of the block,
+    /// i.e. whether it can have neighbors in another block.
+    #[inline]
+    pub fn is_edge(&self, tile: &Tile, reach: i32) -> bool {
+        touches_edges(tile.0, self.left, self.right, reach) != (false, false)
+            || touches_edges(tile.1, self.bottom, self.top, reach) != (false, false)
+    }
+}
+
+
+/// Splits `all_tiles` into a grid of at most `nr_columns` by `nr_rows` blocks, which hold about as
+/// many tiles per column of blocks and per row of blocks. The blocks are ordered row by row, from
+/// the lowest row and the leftmost column.
+pub fn split_into_blocks(all_tiles: impl Iterator<Item=Tile>, nr_columns: usize, nr_rows: usize) -> Vec<Block<TileSet>> {
+    let tiles: Vec<Tile> = all_tiles.collect();
+    let (x_splits, y_splits) = block_splits(tiles.iter(), nr_columns, nr_rows);
+
+    let mut blocks: Vec<Block<TileSet>> = empty_blocks(&x_splits, &y_splits);
+    for tile in tiles {
+        blocks[block_index(&tile, &x_splits, &y_splits)].tiles.insert(tile);
+    }
+    blocks
+}
+
+
+/// The boundaries between the columns of blocks and between the rows of blocks.
+pub(crate) fn block_splits<'a>(tiles: impl Iterator<Item=&'a Tile> + Clone, nr_columns: usize, nr_rows: usize) -> (Vec<i32>, Vec<i32>) {
+    (
+        quantile_splits(tiles.clone().map(|t| t.0).collect(), nr_columns),
+        quantile_splits(tiles.map(|t| t.1).collect(), nr_rows),
+    )
+}
+
+
+/// Blocks without tiles between the boundaries `x_splits` and `y_splits`, row by row.
+pub(crate) fn empty_blocks<S: Default>(x_splits: &[i32], y_splits: &[i32]) -> Vec<Block<S>> {
+    let columns: Vec<(i32, (), i32)> = empty_slices(x_splits);
+    let rows: Vec<(i32, (), i32)> = empty_slices(y_splits);
+    rows.iter()
+        .flat_map(|&(bottom, _, top)| {
+            columns.iter().map(move |&(left, _, right)| Block { left, right, bottom, top, tiles: S::default() })
+        })
+        .collect()
+}
+
+
+/// The position of the block holding `tile` among the blocks of
+/// [`empty_blocks`](fn.empty_blocks.html).
+#[inline]
+pub(crate) fn block_index(tile: &Tile, x_splits: &[i32], y_splits: &[i32]) -> usize {
+    let column = x_splits.partition_point(|s| *s <= tile.0);
+    let row = y_splits.partition_point(|s| *s <= tile.1);
+    row * (x_splits.len() + 1) + column
+}
+
+
+/// Concurrent version of [`cluster_tiles`](../../fn.cluster_tiles.html) for blocks.
+pub fn cluster_blocks(blocks: Vec<Block<TileSet>>, min_cluster_size: usize) -> Vec<TileSet> {
+    cluster_blocks_with(blocks, &ClusterParams::new(min_cluster_size))
+}
+
+
+/// Concurrent version of [`cluster_tiles_with`](../../fn.cluster_tiles_with.html) for blocks.
+/// Every block is clustered with the engine of `params`, and the clusters are only filtered once
+/// they are stitched across the seams.
+pub fn cluster_blocks_with(blocks: Vec<Block<TileSet>>, params: &ClusterParams) -> Vec<TileSet> {
+    cluster_blocks_timed(blocks, params).0
+}
+
+
+/// Same as [`cluster_blocks_with`](fn.cluster_blocks_with.html) but also returns the time spent on
+/// clustering the blocks on their own and on stitching their clusters across the seams.
+pub fn cluster_blocks_timed(blocks: Vec<Block<TileSet>>, params: &ClusterParams) -> (Vec<TileSet>, PhaseTimes) {
+    let unfiltered = ClusterParams { min_cluster_size: 1, ..*params };
+    cluster_blocks_using(
+        blocks,
+        params.connectivity,
+        |tiles| crate::cluster_tiles_with(tiles, &unfiltered),
+        |c| c.len() >= params.min_cluster_size,
+    )
+}
+
+
+/// Clusters every block with `cluster`, which must not filter the clusters, and stitches the
+/// clusters across the seams. Returns the stitched clusters that `keep` accepts.
+pub(crate) fn cluster_blocks_using<C>(
+    blocks: Vec<Block<C>>,
+    connectivity: Connectivity,
+    cluster: impl Fn(C) -> Vec<C> + Sync,
+    keep: impl Fn(&C) -> bool,
+) -> (Vec<C>, PhaseTimes)
+where
+    C: ClusterTiles + Default + Send + IntoIterator + Extend<<C as IntoIterator>::Item>,
+{
+    let reach = connectivity.reach();
+
+    let start = Instant::now();
+    let parts = blocks
+        .into_par_iter()
+        .map(|mut block| {
+            let clusters = cluster(std::mem::take(&mut block.tiles));
+            with_edge_tiles(clusters, |t| block.is_edge(t, reach))
+        })
+        .collect();
+    let local = start.elapsed();
+
+    let start = Instant::now();
+    let clusters = join_parts(parts, connectivity, keep);
+    (clusters, PhaseTimes { local, merge: start.elapsed() })
+}
+
+
+/////////////////////////////////////////////////////////////////////////////////////
+/// Unit tests
+/////////////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClusterEngine;
+
+    /// A tall corridor of a few columns with gaps and diagonal steps, and scattered blobs.
+    fn corridor() -> TileSet {
+        let mut tiles: TileSet = (0..4000)
+            .map(|i: i32| (i % 4, i / 4))
+            .filter(|(x, y)| y % 37 != 0 && !(y % 53 == 1 && *x < 3) && (x + y) % 11 != 0)
+            .collect();
+        tiles.extend((0..300).map(|i: i32| (20 + i % 15 + i / 15 % 3, 7 * (i / 15))));
+        tiles
+    }
+
+    #[test]
+    fn grid_of_blocks() {
+        let tiles = corridor();
+        let blocks = split_into_blocks(tiles.iter().cloned(), 3, 4);
+        assert_eq!(blocks.len(), 12);
+        assert_eq!(blocks.iter().map(|b| b.tiles.len()).sum::<usize>(), tiles.len());
+        for (i, block) in blocks.iter().enumerate() {
+            assert!(block.tiles.iter().all(|t| block.left <= t.0 && t.0 <= block.right));
+            assert!(block.tiles.iter().all(|t| block.bottom <= t.1 && t.1 <= block.top));
+            assert_eq!(i % 3 == 0, block.left == i32::MIN);
+            assert_eq!(i >= 9, block.top == i32::MAX);
+        }
+        // the rows of blocks cut the corridor
+        assert!(blocks.iter().filter(|b| b.tiles.contains(&(0, 1))).all(|b| b.top < 999));
+
+        let (x, y) = (blocks[4].left, blocks[4].bottom);
+        assert!(blocks[4].is_edge(&(x, y + 5), 1));
+        assert!(blocks[4].is_edge(&(x + 5, y), 1));
+        assert!(!blocks[0].is_edge(&(i32::MIN + 1, i32::MIN + 1), 1));
+
+        assert_eq!(split_into_blocks(std::iter::empty(), 3, 4).len(), 1);
+        assert!(cluster_blocks(split_into_blocks(std::iter::empty(), 3, 4), 1).is_empty());
+    }
+
+    #[test]
+    fn same_as_sequential() {
+        let tiles = corridor();
+        for &connectivity in [Connectivity::Four, Connectivity::Eight, Connectivity::Radius(2)].iter() {
+            for &engine in [ClusterEngine::Bfs, ClusterEngine::UnionFind, ClusterEngine::Auto].iter() {
+                let params = ClusterParams::new(3).with_connectivity(connectivity).with_engine(engine);
+                let expected = crate::cluster_tiles_with(tiles.clone(), &params);
+                assert!(expected.len() > 5);
+
+                for &(nr_columns, nr_rows) in [(1, 1), (1, 8), (3, 3), (8, 16)].iter() {
+                    let blocks = split_into_blocks(tiles.iter().cloned(), nr_columns, nr_rows);
+                    let clusters = cluster_blocks_with(blocks, &params);
+                    assert_eq!(clusters.len(), expected.len());
+                    for c in clusters.iter() {
+                        assert!(expected.contains(c));
+                    }
+                }
+            }
+        }
+    }
+}
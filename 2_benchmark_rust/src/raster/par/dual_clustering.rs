@@ -4,74 +4,75 @@
  */
 
 use indexmap::IndexSet;
-use std::thread;
+use rayon::prelude::*;
 
 use crate::{
+    ClusterParams,
+    Connectivity,
     Float,
     Point,
     TileSet,
+    Tiling,
     par::batch_data,
     pop_neighbors,
-    par::is_cluster_neighbors,
+    par::join_at_seam,
 };
 use crate::to_tile_counts;
 
 
 
 pub fn map_to_tile_slices(
-    points: &Vec<Point>,
+    points: &[Point],
     precision: Float,
     threshold: usize,
     nr_threads: usize,
 ) -> (TileSet, TileSet, Float) {
-    let mut workers = vec![];
-
-    let scalar = (10 as Float).powf(precision);
-    let batches = batch_data(points, nr_threads);
-    for data in batches {
-        // Spin up another thread
-        workers.push(thread::spawn(move || {
-            to_tile_counts(&data, scalar)
-        }));
-    }
+    let tiling = Tiling::new(precision);
+    let (tiles_left, tiles_right) = map_to_tile_slices_with(points, &tiling, threshold, nr_threads);
+    (tiles_left, tiles_right, tiling.scalars[0])
+}
 
-    let mut accumulate_tiles = workers.pop().unwrap().join().unwrap();
-    // Wait for the threads to finish and sum counters for each tile.
-    for work in workers {
-        let tiles = work.join().unwrap();
 
-        for (t, t_count) in tiles {
-            let count = accumulate_tiles.entry(t).or_insert(0);
-            *count += t_count;
-        }
-    }
+/// Same as [`map_to_tile_slices`](fn.map_to_tile_slices.html) but on the grid described by `tiling`.
+pub fn map_to_tile_slices_with(
+    points: &[Point],
+    tiling: &Tiling,
+    threshold: usize,
+    nr_threads: usize,
+) -> (TileSet, TileSet) {
+    let accumulate_tiles = batch_data(points, nr_threads)
+        .map(|data| to_tile_counts(data, tiling))
+        .reduce_with(|mut acc, tiles| {
+            // sum counters for each tile
+            for (t, t_count) in tiles {
+                let count = acc.entry(t).or_insert(0);
+                *count += t_count;
+            }
+            acc
+        })
+        .unwrap_or_default();
 
-    let (tiles_left, tiles_right) = accumulate_tiles
+    accumulate_tiles
         .into_iter()
         .filter(|(_, count)| count >= &threshold )
         .map(|(tile, _)| tile)
-        .partition(|(x,_y)| *x < 0);
-
-    (
-        tiles_left,
-        tiles_right,
-        scalar,
-    )
+        .partition(|(x,_y)| *x < 0)
 }
 
 
 pub fn cluster_tiles(left_tiles: TileSet, right_tiles: TileSet, min_cluster_size: usize) -> Vec<TileSet> {
+    cluster_tiles_with(left_tiles, right_tiles, &ClusterParams::new(min_cluster_size))
+}
 
-    let fork1 = thread::spawn(move || {
-        cluster_slice(left_tiles, min_cluster_size, Edge::Left)
-    });
-    let fork2 = thread::spawn(move || {
-        cluster_slice(right_tiles, min_cluster_size, Edge::Right)
-    });
-    let (left_clusters, left_join) = fork1.join().unwrap();
-    let (right_clusters, right_join) = fork2.join().unwrap();
 
-    let mid_clusters = join_clusters(left_join, right_join, min_cluster_size);
+/// Same as [`cluster_tiles`](fn.cluster_tiles.html) but with all parameters of the clustering step.
+pub fn cluster_tiles_with(left_tiles: TileSet, right_tiles: TileSet, params: &ClusterParams) -> Vec<TileSet> {
+    let ((left_clusters, left_join), (right_clusters, right_join)) = rayon::join(
+        || cluster_slice(left_tiles, params, Edge::Left),
+        || cluster_slice(right_tiles, params, Edge::Right),
+    );
+
+    let mid_clusters = join_clusters(left_join, right_join, params);
 
     let mut left_clusters = left_clusters;
     left_clusters.extend(mid_clusters);
@@ -79,43 +80,17 @@ pub fn cluster_tiles(left_tiles: TileSet, right_tiles: TileSet, min_cluster_size
     left_clusters
 }
 
-fn join_clusters(left_clusters: Vec<TileSet>, right_clusters: Vec<TileSet>, min_cluster_size: usize) -> Vec<TileSet> {
-    let mut clusters = Vec::new();
-    let mut xs = left_clusters;
-    let mut ys = right_clusters;
-
-    while let Some(start) = xs.pop() {
-        let mut to_visit = vec![(true, start.clone())];
-        let mut cluster = start;
-
-        while let Some((go_right, x)) = to_visit.pop() {
-            if go_right {
-                let (neighbors, ys_left) = ys.into_iter().partition(|c| is_cluster_neighbors(c, &x));
-                ys = ys_left;
-
-                for n in neighbors {
-                    to_visit.push((false, n.clone()));
-                    cluster.extend(n);
-                }
-            } else {
-                let (neighbors, xs_left) = xs.into_iter().partition(|c| is_cluster_neighbors(c, &x));
-                xs = xs_left;
-
-                for n in neighbors {
-                    to_visit.push((true, n.clone()));
-                    cluster.extend(n);
-                }
-            }
-        }
-        if cluster.len() >= min_cluster_size {
-            clusters.push(cluster);
-        }
-    }
-    for y in ys {
-        if y.len() >= min_cluster_size {
-            clusters.push(y);
-        }
-    }
+/// Joins the clusters on the two sides of the border at column 0 that have neighboring tiles.
+fn join_clusters(left_clusters: Vec<TileSet>, right_clusters: Vec<TileSet>, params: &ClusterParams) -> Vec<TileSet> {
+    let (clusters, _) = join_at_seam(
+        left_clusters,
+        right_clusters,
+        Vec::new(),
+        Edge::Right as i32,
+        params.connectivity,
+        |c, columns| c.iter().filter(|t| columns.contains(&t.0)).cloned().collect(),
+        |c| c.len() >= params.min_cluster_size,
+    );
     clusters
 }
 
@@ -125,25 +100,36 @@ enum Edge {
     Right = 0,
 }
 
-fn cluster_slice(tiles: TileSet, min_cluster_size: usize, y_edge: Edge) -> (Vec<TileSet>, Vec<TileSet>) {
+impl Edge {
+    /// Returns true if column `x` is within `connectivity.reach()` columns of the edge.
+    fn is_touched(self, x: i32, connectivity: Connectivity) -> bool {
+        match self {
+            Edge::Left => x > Edge::Left as i32 - connectivity.reach(),
+            Edge::Right => x < Edge::Right as i32 + connectivity.reach(),
+        }
+    }
+}
+
+fn cluster_slice(tiles: TileSet, params: &ClusterParams, y_edge: Edge) -> (Vec<TileSet>, Vec<TileSet>) {
+    let min_cluster_size = params.min_cluster_size;
+    let connectivity = params.connectivity;
     let mut to_visit = tiles;
     let mut clusters = Vec::new();
     let mut edge_clusters = Vec::new();
-    let edge = y_edge as i32;
 
     while let Some(x) = to_visit.pop() { // starting point for a new cluster
         let mut is_edge_cluster = false;
-        is_edge_cluster |= x.0 == edge; // mark cluster if x is an edge-tile
+        is_edge_cluster |= y_edge.is_touched(x.0, connectivity); // mark cluster if x is an edge-tile
 
         let mut cluster = IndexSet::new();
         cluster.insert(x);
 
-        let mut to_check: Vec<_> = pop_neighbors(x, &mut to_visit).collect();
+        let mut to_check: Vec<_> = pop_neighbors(x, &mut to_visit, connectivity).collect();
         while let Some(p) = to_check.pop() {
-            is_edge_cluster |= p.0 == edge; // mark cluster if p is an edge-tile
+            is_edge_cluster |= y_edge.is_touched(p.0, connectivity); // mark cluster if p is an edge-tile
             cluster.insert(p);
 
-            let new_neighbors = pop_neighbors(p, &mut to_visit);
+            let new_neighbors = pop_neighbors(p, &mut to_visit, connectivity);
             for n in new_neighbors {
                 to_check.push(n);
             }
@@ -181,7 +167,7 @@ mod tests {
             vec![(0, -3), (0, -4), (0, -5)],
         ].into_iter().map(|list| list.iter().cloned().collect()).collect();
 
-        let new_cluster = join_clusters(xs, ys, 4);
+        let new_cluster = join_clusters(xs, ys, &ClusterParams::new(4));
         assert_eq!(new_cluster.len(), 3);
     }
 
@@ -211,7 +197,7 @@ mod tests {
             vec![(0, 3640000), (1, 3640001), (0, 3639999), (0, 3639998), (0, 3640001)],
         ].into_iter().map(|list| list.iter().cloned().collect()).collect();
 
-        let new_cluster = join_clusters(xs, ys, 4);
+        let new_cluster = join_clusters(xs, ys, &ClusterParams::new(4));
         assert_eq!(new_cluster.len(), 16);
     }
 }
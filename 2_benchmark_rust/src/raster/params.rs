@@ -0,0 +1,208 @@
+/*!
+ * Parameters of the projection and clustering steps.
+ *
+ * A [`ClusterParams`](struct.ClusterParams.html) is passed to the `*_with` versions of the
+ * clustering functions, e.g. [`cluster_tiles_with`](../fn.cluster_tiles_with.html). The functions
+ * that only take a `min_cluster_size` use [`ClusterParams::new`](struct.ClusterParams.html#method.new).
+ *
+ * A [`Significance`](enum.Significance.html) defines the threshold of the projection relative to
+ * the tile counts of the data, see [`map_to_significant_tiles`](../fn.map_to_significant_tiles.html).
+ */
+
+use indexmap::IndexMap;
+
+use crate::{
+    Float,
+    nd::TileContent,
+    sorted::KeyOrder,
+};
+
+
+/// Which tiles are considered neighbors of a tile.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Connectivity {
+    /// Tiles sharing a side: 4 neighbors in 2D and 2·D in general.
+    Four,
+    /// Tiles sharing a side or a corner: 8 neighbors in 2D and 3^D-1 in general.
+    #[default]
+    Eight,
+    /// Tiles within Chebyshev distance k, which bridges gaps of k-1 tiles.
+    /// `Radius(1)` is the same as `Eight`. The radius is a `u16` so that it always fits the `i32`
+    /// coordinates of a tile; a tile has (2k+1)^D-1 neighbors anyway.
+    Radius(u16),
+}
+
+impl Connectivity {
+    /// The largest distance along one axis between two neighbors, i.e. the width of the band
+    /// along a border in which a tile can have neighbors on the other side.
+    pub fn reach(&self) -> i32 {
+        match self {
+            Connectivity::Four | Connectivity::Eight => 1,
+            Connectivity::Radius(k) => i32::from(*k),
+        }
+    }
+}
+
+
+/// How the points are grouped into tiles.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Projection {
+    /// Counts the points of each tile in a hash map.
+    #[default]
+    Hash,
+    /// Packs the tile of each point into a `u64` key, sorts the keys and counts the runs of equal
+    /// keys, see [`sorted`](../sorted/index.html).
+    Sort(KeyOrder),
+}
+
+
+/// The algorithm that groups neighboring tiles into clusters. All give the same clusters.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum ClusterEngine {
+    /// Grows one cluster at a time by removing the neighbors of its tiles from the set of tiles.
+    Bfs,
+    /// Unions the indices of neighboring tiles in a disjoint-set forest,
+    /// see [`union_find`](../union_find/index.html).
+    UnionFind,
+    /// Labels the tiles on a bitmap of their bounding box, see [`dense`](../dense/index.html).
+    /// Only for 2D tiles, other tiles use `Bfs`. The concurrent versions label every slice or
+    /// block with the engine of their parameters, except with `SeamMerge::Sequential`, which
+    /// labels the slices with `Bfs` unless the engine is `UnionFind`.
+    Dense,
+    /// `Dense` if the tiles fill enough of their bounding box, `Bfs` otherwise.
+    #[default]
+    Auto,
+}
+
+
+/// How the concurrent versions join the clusters across the seams between slices.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum SeamMerge {
+    /// One pass over the seams from right to left, carrying the clusters that cross whole slices.
+    /// With `ClusterEngine::UnionFind` the ids of all the clusters at the seams are unioned at once.
+    Sequential,
+    /// Merges adjacent slices pairwise and in parallel as a reduction tree, unioning the ids of
+    /// the clusters at the seam of every pair, see [`par::cluster_tiles_timed`](../par/fn.cluster_tiles_timed.html).
+    #[default]
+    Tree,
+}
+
+
+/// Which tiles are significant, as a rule that gives a count threshold for the tile counts of the
+/// data. A tile is significant if it has at least as many points as the threshold.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Significance {
+    /// At least this number of points, the same as the `threshold` of
+    /// [`map_to_tiles`](../fn.map_to_tiles.html).
+    Count(usize),
+    /// At least the p-th percentile, between 0 and 100, of the counts of the occupied tiles,
+    /// using the nearest rank.
+    Percentile(Float),
+    /// The `n` tiles with the most points. Tiles with the same count as the n-th one are kept too.
+    Top(usize),
+    /// At least `k` times the mean count of the occupied tiles.
+    TimesMean(Float),
+}
+
+impl Significance {
+    /// The count threshold given by this rule for the `counts` of the occupied tiles.
+    /// It is at least 1 except for `Count(0)`.
+    pub fn threshold<I: IntoIterator<Item = usize>>(&self, counts: I) -> usize {
+        match *self {
+            Significance::Count(threshold) => threshold,
+            Significance::Percentile(p) => {
+                let mut counts: Vec<usize> = counts.into_iter().collect();
+                if counts.is_empty() {
+                    return 1;
+                }
+                let rank = (p.clamp(0., 100.) / 100. * counts.len() as Float).ceil() as usize;
+                *counts.select_nth_unstable(rank.saturating_sub(1)).1
+            },
+            Significance::Top(n) => {
+                let mut counts: Vec<usize> = counts.into_iter().collect();
+                if n == 0 {
+                    return counts.iter().max().map_or(1, |max| max + 1);
+                }
+                if n >= counts.len() {
+                    return counts.iter().cloned().min().unwrap_or(1);
+                }
+                *counts.select_nth_unstable_by(n - 1, |a, b| b.cmp(a)).1
+            },
+            Significance::TimesMean(k) => {
+                let (sum, len) = counts.into_iter().fold((0, 0), |(sum, len), c| (sum + c, len + 1));
+                if len == 0 {
+                    return 1;
+                }
+                ((k * sum as Float / len as Float).ceil() as usize).max(1)
+            },
+        }
+    }
+}
+
+
+/// Parameters of the clustering step.
+///
+/// A cluster is kept if it has at least `min_cluster_size` tiles, `min_points` points and a total
+/// weight of `min_weight`. The number of points and the weight are only known to the clusterings
+/// that keep a value per tile, e.g. [`cluster_tile_counts_with`](../fn.cluster_tile_counts_with.html)
+/// and [`prime::cluster_tiles_with`](../prime/fn.cluster_tiles_with.html). The clusterings of bare
+/// tiles only check the number of tiles.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ClusterParams {
+    /// Minimum number of tiles in a cluster.
+    pub min_cluster_size: usize,
+    /// Minimum number of points in a cluster.
+    pub min_points: usize,
+    /// Minimum total weight of the points of a cluster.
+    pub min_weight: Float,
+    pub connectivity: Connectivity,
+    pub engine: ClusterEngine,
+    /// Only used by the concurrent versions.
+    pub merge: SeamMerge,
+}
+
+impl ClusterParams {
+    /// Clusters of at least `min_cluster_size` tiles using 8-connectivity.
+    pub fn new(min_cluster_size: usize) -> Self {
+        ClusterParams {
+            min_cluster_size,
+            min_points: 0,
+            min_weight: 0.,
+            connectivity: Connectivity::default(),
+            engine: ClusterEngine::default(),
+            merge: SeamMerge::default(),
+        }
+    }
+
+    /// Same parameters but with another connectivity.
+    pub fn with_connectivity(self, connectivity: Connectivity) -> Self {
+        ClusterParams { connectivity, ..self }
+    }
+
+    /// Same parameters but with another clustering algorithm.
+    pub fn with_engine(self, engine: ClusterEngine) -> Self {
+        ClusterParams { engine, ..self }
+    }
+
+    /// Same parameters but with another way of joining the clusters of the concurrent versions.
+    pub fn with_merge(self, merge: SeamMerge) -> Self {
+        ClusterParams { merge, ..self }
+    }
+
+    /// Same parameters but clusters also need at least `min_points` points.
+    pub fn with_min_points(self, min_points: usize) -> Self {
+        ClusterParams { min_points, ..self }
+    }
+
+    /// Same parameters but clusters also need a total weight of at least `min_weight`.
+    pub fn with_min_weight(self, min_weight: Float) -> Self {
+        ClusterParams { min_weight, ..self }
+    }
+
+    /// Whether `cluster` is large enough to be kept.
+    pub fn keeps<T, V: TileContent>(&self, cluster: &IndexMap<T, V>) -> bool {
+        cluster.len() >= self.min_cluster_size
+            && (self.min_points == 0 || cluster.values().map(|v| v.nr_points()).sum::<usize>() >= self.min_points)
+            && (self.min_weight <= 0. || cluster.values().map(|v| v.weight()).sum::<Float>() >= self.min_weight)
+    }
+}
@@ -6,86 +6,100 @@
 pub mod par;
 
 use indexmap::IndexMap;
-use hashbrown::HashMap;
 
 use crate::{
+    ClusterParams,
     Float,
     Point,
+    Projection,
     Tile,
+    Tiling,
+    WeightedPoint,
+    nd,
+    sorted,
 };
 
-type TileMap = IndexMap<Tile, Vec<Point>>;
+pub type TileMap = IndexMap<Tile, Vec<Point>>;
+pub type WeightedTileMap = IndexMap<Tile, Vec<WeightedPoint>>;
 
 
 /// Prime version of [map_to_tiles](../fn.map_to_tiles.html).
 pub fn map_to_tiles(
-    points: &Vec<Point>,
+    points: &[Point],
     precision: Float,
     threshold: usize,
 ) -> (TileMap, Float) {
-    let scalar = (10 as Float).powf(precision);
-    let mut tile_map = HashMap::new();
+    let tiling = Tiling::new(precision);
+    (map_to_tiles_with(points, &tiling, threshold), tiling.scalars[0])
+}
+
+
+/// Prime version of [map_to_tiles_with](../fn.map_to_tiles_with.html).
+pub fn map_to_tiles_with(
+    points: &[Point],
+    tiling: &Tiling,
+    threshold: usize,
+) -> TileMap {
+    nd::prime::map_to_tiles(points, tiling, threshold)
+}
 
-    for p in points {
-        let p_int = p.truncate(scalar);
 
-        let ps = tile_map.entry(p_int).or_insert(vec![]);
-        ps.push(*p);
+/// Prime version of [map_to_tiles_using](../fn.map_to_tiles_using.html).
+pub fn map_to_tiles_using(
+    points: &[Point],
+    tiling: &Tiling,
+    threshold: usize,
+    projection: Projection,
+) -> TileMap {
+    match projection {
+        Projection::Hash => map_to_tiles_with(points, tiling, threshold),
+        Projection::Sort(order) => sorted::map_to_sorted_tile_map(points, tiling, threshold, order),
     }
+}
 
-    let significant = tile_map
-        .into_iter()
-        .filter(|(_, ps)| ps.len() >= threshold )
-        .collect();
 
-    (significant, scalar)
+/// Prime version of [map_to_weighted_tiles](../fn.map_to_weighted_tiles.html).
+/// The points keep their weights.
+pub fn map_to_weighted_tiles(
+    points: &[WeightedPoint],
+    precision: Float,
+    threshold: Float,
+) -> (WeightedTileMap, Float) {
+    let tiling = Tiling::new(precision);
+    (map_to_weighted_tiles_with(points, &tiling, threshold), tiling.scalars[0])
 }
 
 
-/// Prime version of [cluster_tiles](../fn.cluster_tiles.html).
-pub fn cluster_tiles(tiles: TileMap, min_cluster_size: usize) -> Vec<TileMap> {
-    let mut to_visit = tiles;
-    let mut clusters = Vec::new();
+/// Prime version of [map_to_weighted_tiles_with](../fn.map_to_weighted_tiles_with.html).
+pub fn map_to_weighted_tiles_with(
+    points: &[WeightedPoint],
+    tiling: &Tiling,
+    threshold: Float,
+) -> WeightedTileMap {
+    nd::prime::map_to_weighted_tiles(points, tiling, threshold)
+}
 
-    while let Some((kx, vx)) = to_visit.pop() { // starting point for a new cluster
 
-        let mut cluster = IndexMap::new();
-        cluster.insert(kx, vx);
+/// Same as [cluster_tiles](fn.cluster_tiles.html) for the tiles of weighted points.
+pub fn cluster_weighted_tiles(tiles: WeightedTileMap, min_cluster_size: usize) -> Vec<WeightedTileMap> {
+    cluster_weighted_tiles_with(tiles, &ClusterParams::new(min_cluster_size))
+}
+
 
-        let mut to_check: IndexMap<_,_> = pop_neighbors(kx, &mut to_visit).collect();
-        while let Some((kc, vc)) = to_check.pop() {
-            cluster.insert(kc, vc);
+/// Same as [cluster_tiles_with](fn.cluster_tiles_with.html) for the tiles of weighted points.
+pub fn cluster_weighted_tiles_with(tiles: WeightedTileMap, params: &ClusterParams) -> Vec<WeightedTileMap> {
+    crate::dense::cluster_tile_map_with(tiles, params)
+}
 
-            let new_neighbors = pop_neighbors(kc, &mut to_visit);
-            for (kn, vn) in new_neighbors {
-                to_check.insert(kn, vn);
-            }
-        }
 
-        if cluster.len() >= min_cluster_size {
-            clusters.push(cluster);
-        }
-    }
-    clusters
+/// Prime version of [cluster_tiles](../fn.cluster_tiles.html).
+pub fn cluster_tiles(tiles: TileMap, min_cluster_size: usize) -> Vec<TileMap> {
+    cluster_tiles_with(tiles, &ClusterParams::new(min_cluster_size))
 }
 
 
-fn pop_neighbors<'a>((x, y): Tile, tiles: &'a mut TileMap) -> impl Iterator<Item = (Tile, Vec<Point>)>  + 'a {
-    let candidates = vec![
-        (x + 1, y    ),
-        (x - 1, y    ),
-        (x    , y + 1),
-        (x    , y - 1),
-        (x + 1, y - 1),
-        (x + 1, y + 1),
-        (x - 1, y - 1),
-        (x - 1, y + 1),
-    ];
-
-    // map neighbors to points if they exists
-    candidates.into_iter().filter_map( move |n|
-        if let Some(points) = tiles.remove(&n) {
-            Some((n, points))
-        } else {None}
-    )
+/// Prime version of [cluster_tiles_with](../fn.cluster_tiles_with.html).
+/// The points of the clusters are used for the minimum number of points and weight of `params`.
+pub fn cluster_tiles_with(tiles: TileMap, params: &ClusterParams) -> Vec<TileMap> {
+    crate::dense::cluster_tile_map_with(tiles, params)
 }
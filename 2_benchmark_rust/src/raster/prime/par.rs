@@ -1,40 +1,107 @@
 /*!
  * The concurrent version of RASTER'.
  *
- * This is very similar to regular RASTER [`par`](../../par/index.html).
+ * This is very similar to regular RASTER [`par`](../../par/index.html), and runs on the rayon thread
+ * pool it is called from in the same way.
  */
 
 use hashbrown::HashMap;
 use indexmap::IndexMap;
 use rayon::prelude::*;
-use std::thread;
+use std::time::{Duration, Instant};
 
 use crate::{
+    ClusterEngine,
+    ClusterParams,
     Float,
     Point,
+    SeamMerge,
     Tile,
+    Tiling,
+    WeightedPoint,
+    nd::{GridPoint, TileContent},
+    nd::prime::pop_neighbors,
+    par::blocks::{Block, block_index, block_splits, cluster_blocks_using, empty_blocks},
+    par::{PhaseTimes, Segment, batch_size, edge_tiles, empty_slices, equal_width_splits, extent_splits, join_at_seam, join_parts, merge_narrow_slices, merge_tree, quantile_splits, touches_edges, with_edge_tiles},
     prime::TileMap,
+    sorted::{self, KeyOrder},
+    union_find,
 };
 
+/// Clusters of tiles that hold a value each.
+type Clusters<V> = Vec<IndexMap<Tile, V>>;
+
+/// Slices of tiles that hold a value each, with the first and last column of each slice.
+type Slices<V> = Vec<(i32, IndexMap<Tile, V>, i32)>;
+
+/// Cf. `par::EdgeClusters`
+type EdgeClusters<V> = (Clusters<V>, Clusters<V>, Clusters<V>);
+
 
 /// Cf. [`par::map_to_tiles`](../../par/fn.map_to_tiles.html)
 pub fn map_to_tiles(
-    points: &Vec<Point>,
+    points: &[Point],
     precision: Float,
     threshold: usize,
     nr_threads: usize,
 ) -> (impl Iterator<Item=(Tile, Vec<Point>)>, Float) {
+    let tiling = Tiling::new(precision);
+    (map_to_tiles_with(points, &tiling, threshold, nr_threads), tiling.scalars[0])
+}
+
+
+/// Cf. [`par::map_to_tiles_with`](../../par/fn.map_to_tiles_with.html)
+pub fn map_to_tiles_with(
+    points: &[Point],
+    tiling: &Tiling,
+    threshold: usize,
+    nr_threads: usize,
+) -> impl Iterator<Item=(Tile, Vec<Point>)> {
+    accumulate_points(points, tiling, nr_threads)
+        .into_iter()
+        .filter(move |(_, count)| count.len() >= threshold )
+}
+
+
+/// Cf. [`par::map_to_weighted_tiles`](../../par/fn.map_to_weighted_tiles.html)
+/// The points keep their weights.
+pub fn map_to_weighted_tiles(
+    points: &[WeightedPoint],
+    precision: Float,
+    threshold: Float,
+    nr_threads: usize,
+) -> (impl Iterator<Item=(Tile, Vec<WeightedPoint>)>, Float) {
+    let tiling = Tiling::new(precision);
+    (map_to_weighted_tiles_with(points, &tiling, threshold, nr_threads), tiling.scalars[0])
+}
+
 
-    let scalar = (10 as Float).powf(precision);
-    let chunk_size = points.len() / nr_threads;
+/// Cf. [`par::map_to_weighted_tiles_with`](../../par/fn.map_to_weighted_tiles_with.html)
+/// A tile is significant if the sum of the weights of its points reaches `threshold`.
+pub fn map_to_weighted_tiles_with(
+    points: &[WeightedPoint],
+    tiling: &Tiling,
+    threshold: Float,
+    nr_threads: usize,
+) -> impl Iterator<Item=(Tile, Vec<WeightedPoint>)> {
+    accumulate_points(points, tiling, nr_threads)
+        .into_iter()
+        .filter(move |(_, ps)| ps.iter().map(|p| p.weight()).sum::<Float>() >= threshold )
+}
 
-    let accumulate_tiles = points
-        .par_chunks(chunk_size)
+
+/// The points of every tile, collected by batches of points on the rayon thread pool.
+fn accumulate_points<P>(points: &[P], tiling: &Tiling, nr_threads: usize) -> HashMap<Tile, Vec<P>>
+where
+    P: GridPoint<2, Tile = Tile> + Send + Sync,
+{
+    points
+        .par_chunks(batch_size(points.len(), nr_threads))
         .map(|data| {
             let mut all_points = HashMap::new();
 
             for &p in data {
-                let p_int = p.truncate(scalar);
+                let p_int = p.to_tile(tiling);
 
                 let count = all_points.entry(p_int).or_insert(vec![]);
                 count.push(p);
@@ -48,56 +115,96 @@ pub fn map_to_tiles(
                 collection.extend(associated);
             }
             acc
-        }).expect("Reduce on < 1 map. Is nr_threads set to zero?");
+        })
+        .unwrap_or_default()
+}
 
-    (
-        accumulate_tiles.into_iter()
-            .filter(move |(_, count)| count.len() >= threshold ),
-        scalar,
-    )
+
+/// Concurrent version of [`sorted::map_to_sorted_tile_map`](../../sorted/fn.map_to_sorted_tile_map.html).
+/// The keys are computed and sorted on the rayon thread pool.
+pub fn map_to_sorted_tiles_with(
+    points: &[Point],
+    tiling: &Tiling,
+    threshold: usize,
+    order: KeyOrder,
+) -> TileMap {
+    let mut keyed: Vec<(u64, usize)> = points
+        .par_iter()
+        .enumerate()
+        .map(|(i, p)| (sorted::pack(p.to_tile(tiling), order), i))
+        .collect();
+    keyed.par_sort_unstable();
+
+    let mut tiles = IndexMap::new();
+    for run in keyed.chunk_by(|a, b| a.0 == b.0) {
+        if run.len() >= threshold {
+            let run_points = run.iter().map(|(_, i)| points[*i]).collect();
+            tiles.insert(sorted::unpack(run[0].0, order), run_points);
+        }
+    }
+    tiles
 }
 
 
 #[inline]
 /// Cf. [`par::split_vertically`](../../par/fn.split_vertically.html)
-pub fn split_vertically(
-    all_tiles: impl Iterator<Item=(Tile, Vec<Point>)>,
+/// The tiles can hold any value, e.g. their points or their number of points.
+pub fn split_vertically<V>(
+    all_tiles: impl Iterator<Item=(Tile, V)>,
     min: i32,
     max: i32,
     scalar: Float,
     nr_slices: usize
-) -> Vec<(i32, TileMap, i32)>{
-    // Compute splitting coordinates depending on nr_slices.
-    if nr_slices < 2 {
-        return vec![(std::i32::MIN, all_tiles.collect(), std::i32::MAX)];
-    }
+) -> Slices<V> {
+    slice_at(all_tiles, &equal_width_splits(min, max, scalar, nr_slices))
+}
 
-    let step = ((max.abs() + min.abs()) as Float / nr_slices as Float) as i32;
-    let mut splits = Vec::new();
-    let mut split = min + step;
-    for _ in 1..nr_slices {
-        splits.push((split as Float * scalar) as i32); // scale and push
-        split += step;
-    }
 
-    // Initialize tile_slices with border values
-    let mut tile_slices: Vec<(i32, TileMap, i32)> = Vec::with_capacity(splits.len()+1);
-    for i in 0..tile_slices.capacity() {
-        let left_bound = *splits.get(i.wrapping_sub(1)).unwrap_or(&std::i32::MIN);
-        let right_bound = (*splits.get(i).unwrap_or(&std::i32::MIN)).wrapping_sub(1); // split-1 or wrap around to MAX
-        tile_slices.push((left_bound, IndexMap::new(), right_bound));
+/// Cf. [`par::split_by_extent`](../../par/fn.split_by_extent.html)
+pub fn split_by_extent<V>(all_tiles: impl Iterator<Item=(Tile, V)>, nr_slices: usize) -> Slices<V> {
+    let tiles: Vec<(Tile, V)> = all_tiles.collect();
+    let splits = extent_splits(tiles.iter().map(|(t, _)| t.0), nr_slices);
+    slice_at(tiles.into_iter(), &splits)
+}
+
+
+/// Cf. [`par::split_balanced`](../../par/fn.split_balanced.html)
+/// The tiles can hold any value, e.g. their points or their number of points.
+pub fn split_balanced<V>(
+    all_tiles: impl Iterator<Item=(Tile, V)>,
+    nr_slices: usize,
+) -> (Slices<V>, Vec<usize>) {
+    let tiles: Vec<(Tile, V)> = all_tiles.collect();
+    let splits = quantile_splits(tiles.iter().map(|(t, _)| t.0).collect(), nr_slices);
+    let tile_slices = slice_at(tiles.into_iter(), &splits);
+    let counts = tile_slices.iter().map(|(_, tiles, _)| tiles.len()).collect();
+    (tile_slices, counts)
+}
+
+
+/// Cf. [`par::blocks::split_into_blocks`](../../par/blocks/fn.split_into_blocks.html)
+/// The tiles can hold any value, e.g. their points or their number of points.
+pub fn split_into_blocks<V>(
+    all_tiles: impl Iterator<Item=(Tile, V)>,
+    nr_columns: usize,
+    nr_rows: usize,
+) -> Vec<Block<IndexMap<Tile, V>>> {
+    let tiles: Vec<(Tile, V)> = all_tiles.collect();
+    let (x_splits, y_splits) = block_splits(tiles.iter().map(|(t, _)| t), nr_columns, nr_rows);
+
+    let mut blocks: Vec<Block<IndexMap<Tile, V>>> = empty_blocks(&x_splits, &y_splits);
+    for (k, v) in tiles {
+        blocks[block_index(&k, &x_splits, &y_splits)].tiles.insert(k, v);
     }
-    // Split all_tiles into nr_threads sets
-    for (k,v) in all_tiles {
-        for i in 0..splits.len() {
-            if k.0 < splits[i] {
-                tile_slices[i].1.insert(k, v.clone());
-                break;
-            }
-        }
-        if k.0 >= *splits.last().unwrap() {
-            tile_slices[splits.len()].1.insert(k, v);
-        }
+    blocks
+}
+
+
+/// Cf. `par::slice_at`
+fn slice_at<V>(all_tiles: impl Iterator<Item=(Tile, V)>, splits: &[i32]) -> Slices<V> {
+    let mut tile_slices: Slices<V> = empty_slices(splits);
+    for (k, v) in all_tiles {
+        tile_slices[splits.partition_point(|s| *s <= k.0)].1.insert(k, v);
     }
     tile_slices
 }
@@ -105,26 +212,122 @@ pub fn split_vertically(
 
 /// Cf. [`par::cluster_tiles`](../../par/fn.cluster_tiles.html)
 pub fn cluster_tiles(slices: Vec<(i32, TileMap, i32)>, min_cluster_size: usize) -> Vec<TileMap> {
+    cluster_tiles_with(slices, &ClusterParams::new(min_cluster_size))
+}
+
+
+/// Cf. [`par::cluster_tiles_with`](../../par/fn.cluster_tiles_with.html)
+pub fn cluster_tiles_with(slices: Vec<(i32, TileMap, i32)>, params: &ClusterParams) -> Vec<TileMap> {
+    cluster_maps_timed(slices, params).0
+}
+
+
+/// Cf. [`par::cluster_tiles_timed`](../../par/fn.cluster_tiles_timed.html)
+pub fn cluster_tiles_timed(slices: Vec<(i32, TileMap, i32)>, params: &ClusterParams) -> (Vec<TileMap>, PhaseTimes) {
+    cluster_maps_timed(slices, params)
+}
+
+
+/// Cf. [`par::blocks::cluster_blocks`](../../par/blocks/fn.cluster_blocks.html)
+pub fn cluster_blocks(blocks: Vec<Block<TileMap>>, min_cluster_size: usize) -> Vec<TileMap> {
+    cluster_blocks_with(blocks, &ClusterParams::new(min_cluster_size))
+}
+
+
+/// Cf. [`par::blocks::cluster_blocks_with`](../../par/blocks/fn.cluster_blocks_with.html)
+/// The points of the clusters are used for the minimum number of points and weight of `params`.
+pub fn cluster_blocks_with(blocks: Vec<Block<TileMap>>, params: &ClusterParams) -> Vec<TileMap> {
+    cluster_blocks_timed(blocks, params).0
+}
+
+
+/// Cf. [`par::blocks::cluster_blocks_timed`](../../par/blocks/fn.cluster_blocks_timed.html)
+pub fn cluster_blocks_timed(blocks: Vec<Block<TileMap>>, params: &ClusterParams) -> (Vec<TileMap>, PhaseTimes) {
+    let unfiltered = ClusterParams { min_cluster_size: 1, min_points: 0, min_weight: Float::NEG_INFINITY, ..*params };
+    cluster_blocks_using(
+        blocks,
+        params.connectivity,
+        |tiles| crate::prime::cluster_tiles_with(tiles, &unfiltered),
+        |c| params.keeps(c),
+    )
+}
+
+
+/// Clusters slices of tiles that hold a value each, which is kept with its tile.
+/// The clusters are only filtered once they are joined across the borders of the slices,
+/// so that the number of points and weight of a cluster are the ones of the whole cluster.
+pub(crate) fn cluster_maps_with<V>(slices: Vec<(i32, IndexMap<Tile, V>, i32)>, params: &ClusterParams) -> Clusters<V>
+where
+    V: TileContent + Send,
+{
+    cluster_maps_timed(slices, params).0
+}
+
+
+/// Same as `cluster_maps_with` but also returns the time spent on each phase.
+fn cluster_maps_timed<V>(slices: Vec<(i32, IndexMap<Tile, V>, i32)>, params: &ClusterParams) -> (Clusters<V>, PhaseTimes)
+where
+    V: TileContent + Send,
+{
+    let slices = merge_narrow_slices(slices, params.connectivity.reach());
 
     if slices.len() < 2 {
-        if let Some((_, tiles, _)) = slices.into_iter().next() {
-            return crate::prime::cluster_tiles(tiles, min_cluster_size);
-        }
-        else {
-            return vec![];
-        }
+        let start = Instant::now();
+        let clusters = match slices.into_iter().next() {
+            Some((_, tiles, _)) => crate::dense::cluster_tile_map_with(tiles, params),
+            None => vec![],
+        };
+        return (clusters, PhaseTimes { local: start.elapsed(), merge: Duration::ZERO });
     }
-
-    let mut forks = Vec::new();
-    for (left, tiles, right) in slices {
-        forks.push(thread::spawn(move || {
-            cluster_slice(tiles, min_cluster_size, left, right)
-        }));
+    match (params.merge, params.engine) {
+        (SeamMerge::Tree, _) => merge_slices(slices, params),
+        (SeamMerge::Sequential, ClusterEngine::UnionFind) => union_slices(slices, params),
+        (SeamMerge::Sequential, _) => join_slices(slices, params),
     }
+}
+
+
+/// Cf. `par::merge_slices`
+fn merge_slices<V>(slices: Vec<(i32, IndexMap<Tile, V>, i32)>, params: &ClusterParams) -> (Clusters<V>, PhaseTimes)
+where
+    V: TileContent + Send,
+{
+    let reach = params.connectivity.reach();
+    let unfiltered = ClusterParams { min_cluster_size: 1, min_points: 0, min_weight: Float::NEG_INFINITY, ..*params };
+    let keep = |c: &IndexMap<Tile, V>| params.keeps(c);
+
+    let start = Instant::now();
+    let segments: Vec<Segment<IndexMap<Tile, V>>> = slices
+        .into_par_iter()
+        .map(|(left, tiles, right)| {
+            let clusters = crate::dense::cluster_tile_map_with(tiles, &unfiltered);
+            Segment::new(clusters, |c| edge_tiles(c.keys(), left, right, reach), keep)
+        })
+        .collect();
+    let local = start.elapsed();
+
+    let start = Instant::now();
+    let clusters = merge_tree(segments, params.connectivity, &keep).into_clusters(keep);
+    (clusters, PhaseTimes { local, merge: start.elapsed() })
+}
 
-    let (fst, snd) = forks.into_iter().map(|f| f.join().unwrap()).unzip();
-    let clusters_2d: Vec<Vec<TileMap>> = fst;
-    let edges: Vec<(Vec<TileMap>, Vec<TileMap>, Vec<TileMap>)> = snd;
+
+/// Cf. `par::join_slices`
+fn join_slices<V>(slices: Vec<(i32, IndexMap<Tile, V>, i32)>, params: &ClusterParams) -> (Clusters<V>, PhaseTimes)
+where
+    V: TileContent + Send,
+{
+    let mut seams: Vec<i32> = slices.iter().map(|(left, _, _)| *left).collect();
+    let start = Instant::now();
+    let (fst, snd) = slices
+        .into_par_iter()
+        .map(|(left, tiles, right)| cluster_slice(tiles, params, left, right))
+        .unzip();
+    let clusters_2d: Vec<Clusters<V>> = fst;
+    let edges: Vec<EdgeClusters<V>> = snd;
+    let local = start.elapsed();
+
+    let start = Instant::now();
     let mut left_edges = Vec::new();
     let mut left_right_edges = Vec::new();
     let mut right_edges = Vec::new();
@@ -143,11 +346,12 @@ pub fn cluster_tiles(slices: Vec<(i32, TileMap, i32)>, min_cluster_size: usize)
     );
 
     // traverse right_edges and left_edges backwards
-    assert_eq!(0, right_edges.pop().unwrap().len()); // The right most is touching infinity (i32::MAX),
-                                                     // which no cluster should be able to do.
-    assert_eq!(0, left_right_edges.pop().unwrap().len());
-
-    let mut trans_slices = Vec::new();
+    // The clusters at the right edge of the rightmost slice have no slice to join to their right,
+    // which only happens when the edge is not at i32::MAX. The ones that also touch the left edge
+    // of that slice are joined at its seam like the clusters that cross slices.
+    let outer_right = right_edges.pop().unwrap();
+    clusters.extend(outer_right.into_iter().filter(|c| params.keeps(c)));
+    let mut trans_slices = left_right_edges.pop().unwrap();
     while let Some(right) = right_edges.pop() {
         let mut left = left_edges.pop().unwrap();
         left.extend(trans_slices);
@@ -155,27 +359,56 @@ pub fn cluster_tiles(slices: Vec<(i32, TileMap, i32)>, min_cluster_size: usize)
         // Note that "right" and "left" switch place in the call to join_clusters.
         // This is because "right" is clusters to the right within a slice while the parameter
         // to join_clusters refers to the right side of a border between slices
-        let (joined_clusters, transient) = join_clusters(right, left, left_right, min_cluster_size);
+        let seam = seams.pop().unwrap();
+        let (joined_clusters, transient) = join_clusters(right, left, left_right, seam, params);
         trans_slices = transient;
         clusters.extend(joined_clusters);
     }
 
-    clusters.extend(trans_slices.into_iter().filter(|c| c.len() >= min_cluster_size));
+    clusters.extend(trans_slices.into_iter().filter(|c| params.keeps(c)));
 
-    assert_eq!(0, left_edges.pop().unwrap().len()); // The right most is touching infinity (i32::MIN),
-                                                    // which no cluster should be able to do.
+    // Likewise for the left edge of the leftmost slice when it is not at i32::MIN.
+    let outer_left = left_edges.pop().unwrap();
+    clusters.extend(outer_left.into_iter().filter(|c| params.keeps(c)));
 
-    clusters
+    (clusters, PhaseTimes { local, merge: start.elapsed() })
+}
+
+
+/// Cf. `par::union_slices`
+fn union_slices<V>(slices: Vec<(i32, IndexMap<Tile, V>, i32)>, params: &ClusterParams) -> (Clusters<V>, PhaseTimes)
+where
+    V: TileContent + Send,
+{
+    let connectivity = params.connectivity;
+    let reach = connectivity.reach();
+
+    let start = Instant::now();
+    let parts = slices
+        .into_par_iter()
+        .map(|(left, tiles, right)| {
+            with_edge_tiles(union_find::cluster_tile_map(tiles, connectivity), |t| {
+                touches_edges(t.0, left, right, reach) != (false, false)
+            })
+        })
+        .collect();
+    let local = start.elapsed();
+
+    let start = Instant::now();
+    let clusters = join_parts(parts, connectivity, |c| params.keeps(c));
+    (clusters, PhaseTimes { local, merge: start.elapsed() })
 }
 
 
 /// Cf. [`par::cluster_slice`]
-fn cluster_slice(
-    tiles: TileMap,
-    min_cluster_size: usize,
+fn cluster_slice<V: TileContent>(
+    tiles: IndexMap<Tile, V>,
+    params: &ClusterParams,
     left_edge: i32,
     right_edge: i32
-) -> (Vec<TileMap>, (Vec<TileMap>, Vec<TileMap>, Vec<TileMap>)) {
+) -> (Clusters<V>, EdgeClusters<V>) {
+    let connectivity = params.connectivity;
+    let reach = connectivity.reach();
     let mut to_visit = tiles;
     let mut clusters = Vec::new();
     let mut left_clusters = Vec::new();
@@ -183,21 +416,21 @@ fn cluster_slice(
     let mut left_right_clusters = Vec::new();
 
     while let Some((x, val)) = to_visit.pop() { // starting point for a new cluster
-        let mut is_left_edge_cluster = false;
-        let mut is_right_edge_cluster = false;
-        is_left_edge_cluster |= x.0 == left_edge; // mark cluster if x is an edge-tile
-        is_right_edge_cluster |= x.0 == right_edge; // mark cluster if x is an edge-tile
+        // mark cluster if x is an edge-tile
+        let (mut is_left_edge_cluster, mut is_right_edge_cluster) = touches_edges(x.0, left_edge, right_edge, reach);
 
         let mut cluster = IndexMap::new();
         cluster.insert(x, val);
 
-        let mut to_check: Vec<_> = pop_neighbors(x, &mut to_visit).collect();
+        let mut to_check: Vec<_> = pop_neighbors(x, &mut to_visit, connectivity).collect();
         while let Some((p, val)) = to_check.pop() {
-            is_left_edge_cluster |= p.0 == left_edge; // mark cluster if p is an edge-tile
-            is_right_edge_cluster |= p.0 == right_edge; // mark cluster if p is an edge-tile
+            // mark cluster if p is an edge-tile
+            let (is_left, is_right) = touches_edges(p.0, left_edge, right_edge, reach);
+            is_left_edge_cluster |= is_left;
+            is_right_edge_cluster |= is_right;
             cluster.insert(p, val);
 
-            let new_neighbors = pop_neighbors(p, &mut to_visit);
+            let new_neighbors = pop_neighbors(p, &mut to_visit, connectivity);
             to_check.extend(new_neighbors);
         }
 
@@ -210,7 +443,7 @@ fn cluster_slice(
         else if is_right_edge_cluster {
             right_clusters.push(cluster);
         }
-        else if cluster.len() >= min_cluster_size {
+        else if params.keeps(&cluster) {
             clusters.push(cluster);
         }
     }
@@ -218,106 +451,56 @@ fn cluster_slice(
 }
 
 
-/// Returns all neighbors to (x,y) in tiles and removes them from tiles.
-fn pop_neighbors<'a>((x, y): Tile, tiles: &'a mut TileMap) -> impl Iterator<Item = (Tile, Vec<Point>)>  + 'a {
-    let candidates = vec![
-        (x + 1, y    ),
-        (x - 1, y    ),
-        (x    , y + 1),
-        (x    , y - 1),
-        (x + 1, y - 1),
-        (x + 1, y + 1),
-        (x - 1, y - 1),
-        (x - 1, y + 1),
-    ];
-
-    candidates.into_iter().filter_map( move |n| tiles.remove(&n).map(|v| (n, v)) ) // set's remove returns a bool
-}
-
-
 /// Cf. [`par::join_clusters`]
-fn join_clusters(
-    left_clusters: Vec<TileMap>,
-    right_clusters: Vec<TileMap>,
-    left_right_clusters: Vec<TileMap>,
-    min_cluster_size: usize,
-) -> (Vec<TileMap>, Vec<TileMap>,) {
-    let mut clusters = Vec::new();
-    let mut new_left_right_clusters = Vec::new();
-    let mut left_right = left_right_clusters;
-    let mut left = left_clusters;
-    let mut right = right_clusters;
-    let mut is_left_and_right;
-
-    while let Some(start) = right.pop() {
-        is_left_and_right = false;
-        let mut to_visit = vec![(false, start)];
-        let mut cluster = IndexMap::new();
-
-        while let Some((go_right, visit)) = to_visit.pop() {
-            if go_right {
-                let (neighbors, leftovers) = right.into_iter().partition(|c| is_cluster_neighbors(c, &visit));
-                right = leftovers;
+fn join_clusters<V: TileContent>(
+    left_clusters: Clusters<V>,
+    right_clusters: Clusters<V>,
+    left_right_clusters: Clusters<V>,
+    seam: i32,
+    params: &ClusterParams,
+) -> (Clusters<V>, Clusters<V>,) {
+    join_at_seam(
+        left_clusters,
+        right_clusters,
+        left_right_clusters,
+        seam,
+        params.connectivity,
+        |c, columns| c.keys().filter(|t| columns.contains(&t.0)).cloned().collect(),
+        |c| params.keeps(c),
+    )
+}
 
-                for n in neighbors {
-                    to_visit.push((false, n));
-                }
-            } else {
-                let (neighbors, leftovers) = left.into_iter().partition(|c| is_cluster_neighbors(c, &visit));
-                left = leftovers;
 
-                for n in neighbors {
-                    to_visit.push((true, n));
-                }
-                // when going from right to left left_right_clusters is considered to be on the left side
-                let (neighbors, leftovers) = left_right.into_iter().partition(|c| is_cluster_neighbors(c, &visit));
-                left_right = leftovers; // remove old (unjoined) cluster
 
-                for n in neighbors {
-                    is_left_and_right = true; // assumed to happen quite rarely
-                    to_visit.push((true, n));
+/////////////////////////////////////////////////////////////////////////////////////
+/// Unit tests
+/////////////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prime::WeightedTileMap;
+
+    #[test]
+    fn weighted_projection() {
+        let points: Vec<WeightedPoint> = (0..3000)
+            .map(|i| WeightedPoint::new((i % 70) as Float * 0.013, (i / 70) as Float * 0.011, (i % 7) as Float * 0.25))
+            .collect();
+        let tiling = Tiling::new(1.5);
+        for &threshold in [0., 2., 6.5].iter() {
+            let expected = crate::prime::map_to_weighted_tiles_with(&points, &tiling, threshold);
+            for &nr_threads in [1, 3, 8].iter() {
+                let tiles: WeightedTileMap = map_to_weighted_tiles_with(&points, &tiling, threshold, nr_threads).collect();
+                assert_eq!(tiles.len(), expected.len());
+                for (tile, mut ps) in tiles {
+                    // the batches may change the order of the points of a tile
+                    let mut expected_ps = expected[&tile].clone();
+                    for ps in [&mut ps, &mut expected_ps].iter_mut() {
+                        ps.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+                    }
+                    assert_eq!(ps, expected_ps);
                 }
             }
-            cluster.extend(visit); // join the visited sub-cluster
-        }
-        if is_left_and_right {
-            new_left_right_clusters.push(cluster) // insert new (joined) cluster
-        }
-        else if cluster.len() >= min_cluster_size {
-            clusters.push(cluster);
-        }
-    }
-    for c in left {
-        if c.len() >= min_cluster_size {
-            clusters.push(c);
-        }
-    }
-    left_right.extend(new_left_right_clusters);
-    (clusters, left_right)
-}
-
-
-fn is_cluster_neighbors(c1: &TileMap, c2: &TileMap) -> bool {
-    for (tile, _) in c1.iter() {
-        if is_neighbors(*tile, c2) {
-            return true;
         }
+        assert_eq!(map_to_weighted_tiles(&points, 1.5, 6.5, 4).1, tiling.scalars[0]);
     }
-    false
-}
-
-
-fn is_neighbors((x, y): Tile, tiles: &TileMap) -> bool {
-    let candidates = [
-        (x + 1, y    ),
-        (x - 1, y    ),
-        (x    , y + 1),
-        (x    , y - 1),
-        (x + 1, y - 1),
-        (x + 1, y + 1),
-        (x - 1, y - 1),
-        (x - 1, y + 1),
-    ];
-
-    candidates.iter().any(|tile| tiles.contains_key(tile))
 }
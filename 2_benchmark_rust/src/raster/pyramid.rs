@@ -0,0 +1,246 @@
+/*!
+ * Tile counts at several resolutions from a single projection.
+ *
+ * The points are projected onto the finest grid only. A coarser grid is derived by summing the
+ * counts of the fine tiles it contains, which requires the grids to nest: every coarse tile must
+ * be made of whole fine tiles. That is the case for integer precision steps, e.g. 5, 4 and 3, or
+ * integer ratios of cell sizes, as long as the grids share their origin and rounding.
+ * Precisions 3 and 3.5 do not nest.
+ *
+ * Up to floating-point rounding of points on the border of a coarse tile, the counts are the same
+ * as projecting the points onto each grid with [`tile_counts_with`](../fn.tile_counts_with.html).
+ */
+
+use std::error::Error;
+use std::fmt;
+
+use crate::{
+    Float,
+    Point,
+    Rounding,
+    TileCounts,
+    TileSet,
+    Tiling,
+    tile_counts_with,
+};
+
+
+/// The reason a coarse grid cannot be derived from a fine one.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum NestingError {
+    /// A coarse tile is not a whole number of fine tiles along `axis`.
+    NotAMultiple { axis: usize, ratio: Float },
+    DifferentOrigin,
+    DifferentRounding,
+}
+
+impl fmt::Display for NestingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NestingError::NotAMultiple { axis, ratio } => write!(
+                f, "a coarse tile is {} fine tiles along axis {}, which is not a whole number", ratio, axis
+            ),
+            NestingError::DifferentOrigin => write!(f, "the grids do not have the same origin"),
+            NestingError::DifferentRounding => write!(f, "the grids do not have the same rounding"),
+        }
+    }
+}
+
+impl Error for NestingError {}
+
+
+/// Returns the number of tiles of `fine` along each axis of a tile of `coarse`,
+/// or why `coarse` cannot be derived from `fine`.
+pub fn nesting_factors(fine: &Tiling, coarse: &Tiling) -> Result<[i32; 2], NestingError> {
+    if fine.rounding != coarse.rounding {
+        return Err(NestingError::DifferentRounding);
+    }
+    if fine.origin != coarse.origin {
+        return Err(NestingError::DifferentOrigin);
+    }
+    let mut factors = [1; 2];
+    for (axis, factor) in factors.iter_mut().enumerate() {
+        let ratio = fine.scalars[axis] / coarse.scalars[axis];
+        // the scalars of precisions are powers of 10 which are rarely exact
+        let rounded = ratio.round();
+        if !ratio.is_finite() || rounded < 1. || rounded > i32::MAX as Float || (ratio - rounded).abs() > 1e-9 * rounded {
+            return Err(NestingError::NotAMultiple { axis, ratio });
+        }
+        *factor = rounded as i32;
+    }
+    Ok(factors)
+}
+
+
+/// Sums the counts of every `factors[0]` by `factors[1]` block of tiles into one coarse tile.
+/// `rounding` must be the rounding of the fine grid, see [`nesting_factors`](fn.nesting_factors.html).
+pub fn coarsen(counts: &TileCounts, factors: [i32; 2], rounding: Rounding) -> TileCounts {
+    let divide = |x: i32, factor: i32| match rounding {
+        Rounding::Floor => x.div_euclid(factor),
+        Rounding::Truncate => x / factor,
+    };
+
+    let mut coarse = TileCounts::with_capacity(counts.len());
+    for (&(x, y), count) in counts {
+        *coarse.entry((divide(x, factors[0]), divide(y, factors[1]))).or_insert(0) += count;
+    }
+    coarse
+}
+
+
+/// The counts of every tile of one grid of a [`Pyramid`](struct.Pyramid.html).
+#[derive(Debug, PartialEq, Clone)]
+pub struct Level {
+    pub tiling: Tiling,
+    pub counts: TileCounts,
+}
+
+impl Level {
+    /// The significant tiles of this level, ready for [`cluster_tiles`](../fn.cluster_tiles.html).
+    pub fn tiles(&self, threshold: usize) -> TileSet {
+        self.counts
+            .iter()
+            .filter(|(_, count)| **count >= threshold )
+            .map(|(tile, _)| *tile)
+            .collect()
+    }
+}
+
+
+/// Tile counts of several nested grids, ordered from the coarsest to the finest level like the
+/// levels of a [`ClusterTree`](../tree/struct.ClusterTree.html).
+#[derive(Debug, PartialEq, Clone)]
+pub struct Pyramid {
+    pub levels: Vec<Level>,
+}
+
+impl Pyramid {
+    /// Projects `points` onto the finest of `tilings` and derives the counts of the other ones.
+    /// Each level is derived from the coarsest finer level it nests in.
+    pub fn new(points: &[Point], tilings: &[Tiling]) -> Result<Self, NestingError> {
+        // from the finest to the coarsest, so that every level is derived from a finer one
+        let mut tilings = tilings.to_vec();
+        tilings.sort_by(|a, b| b.scalars[0].total_cmp(&a.scalars[0]));
+
+        let mut tilings = tilings.into_iter();
+        let mut pyramid = match tilings.next() {
+            Some(finest) => Self::from_points(points, finest),
+            None => return Ok(Pyramid { levels: Vec::new() }),
+        };
+        for tiling in tilings {
+            pyramid.insert(tiling)?;
+        }
+        Ok(pyramid)
+    }
+
+    /// A pyramid of the single level of `points` projected onto `tiling`.
+    pub fn from_points(points: &[Point], tiling: Tiling) -> Self {
+        let counts = tile_counts_with(points, &tiling);
+        Pyramid { levels: vec![Level { tiling, counts }] }
+    }
+
+    /// Derives the level of `tiling` from the coarsest level it nests in and inserts it in order.
+    /// Returns the new level, or why `tiling` cannot be derived from the finest level.
+    pub fn insert(&mut self, tiling: Tiling) -> Result<&Level, NestingError> {
+        let finest = self.levels.last().expect("a pyramid has at least one level");
+        nesting_factors(&finest.tiling, &tiling)?;
+        let (parent, factors) = self.levels
+            .iter()
+            .find_map(|l| nesting_factors(&l.tiling, &tiling).ok().map(|f| (l, f)))
+            .unwrap(); // at least the finest level
+        let counts = coarsen(&parent.counts, factors, tiling.rounding);
+
+        let position = self.levels.partition_point(|l| l.tiling.scalars[0] <= tiling.scalars[0]);
+        self.levels.insert(position, Level { tiling, counts });
+        Ok(&self.levels[position])
+    }
+
+    /// A pyramid of grids with tiles of side `10^-precision`, see [`new`](#method.new).
+    pub fn from_precisions(points: &[Point], precisions: &[Float]) -> Result<Self, NestingError> {
+        let tilings: Vec<_> = precisions.iter().map(|p| Tiling::new(*p)).collect();
+        Self::new(points, &tilings)
+    }
+}
+
+
+
+/////////////////////////////////////////////////////////////////////////////////////
+/// Unit tests
+/////////////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cluster_tiles, map_to_tiles_with, tile_counts};
+
+    fn points() -> Vec<Point> {
+        (0..500)
+            .map(|i| {
+                let i = i as Float;
+                Point::new((i * 0.37).sin() * 3. - 1., (i * 0.11).cos() * 2. + 0.5)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn factors() {
+        assert_eq!(nesting_factors(&Tiling::new(5.), &Tiling::new(3.)), Ok([100, 100]));
+        assert_eq!(nesting_factors(&Tiling::new(3.5), &Tiling::new(2.5)), Ok([10, 10]));
+        assert_eq!(
+            nesting_factors(&Tiling::from_cell_sizes([0.5, 0.25]), &Tiling::from_cell_sizes([1.5, 1.])),
+            Ok([3, 4]),
+        );
+        assert!(nesting_factors(&Tiling::new(3.5), &Tiling::new(3.)).is_err());
+        assert!(nesting_factors(&Tiling::new(3.), &Tiling::new(4.)).is_err());
+        assert_eq!(
+            nesting_factors(&Tiling::new(3.), &Tiling::new(2.).with_origin([0.1, 0.])),
+            Err(NestingError::DifferentOrigin),
+        );
+    }
+
+    #[test]
+    fn same_as_projection() {
+        let points = points();
+        for &rounding in [Rounding::Floor, Rounding::Truncate].iter() {
+            let tilings: Vec<_> = [0., 2., 1., 0.]
+                .iter()
+                .map(|p| Tiling::new(*p).with_rounding(rounding))
+                .chain(Some(Tiling::from_cell_sizes([0.5, 0.5]).with_rounding(rounding)))
+                .collect();
+            let pyramid = Pyramid::new(&points, &tilings).unwrap();
+            assert_eq!(pyramid.levels.len(), 5);
+            assert_eq!(pyramid.levels[0].tiling, tilings[0]);
+            assert_eq!(pyramid.levels[4].tiling, tilings[1]);
+            assert!(pyramid.levels.windows(2).all(|w| w[0].tiling.scalars[0] <= w[1].tiling.scalars[0]));
+
+            for level in pyramid.levels.iter() {
+                assert_eq!(level.counts, crate::tile_counts_with(&points, &level.tiling));
+                let tiles = level.tiles(3);
+                assert_eq!(tiles, map_to_tiles_with(&points, &level.tiling, 3));
+                assert!(!cluster_tiles(level.tiles(1), 1).is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn not_nested() {
+        let points = points();
+        match Pyramid::from_precisions(&points, &[1., 0.5]) {
+            Err(NestingError::NotAMultiple { axis: 0, ratio }) => assert!((ratio - 3.1623).abs() < 1e-4),
+            other => panic!("{:?}", other),
+        }
+        // a NaN precision is not a multiple of anything, and does not panic
+        match Pyramid::from_precisions(&points, &[1., Float::NAN]) {
+            Err(NestingError::NotAMultiple { axis: 0, ratio }) => assert!(ratio.is_nan()),
+            other => panic!("{:?}", other),
+        }
+        let mut pyramid = Pyramid::from_precisions(&points, &[1.]).unwrap();
+        assert_eq!(pyramid.levels[0].counts, tile_counts(&points, 1.).0);
+
+        // levels added one at a time, in any order
+        assert!(pyramid.insert(Tiling::new(0.5)).is_err());
+        assert!(pyramid.insert(Tiling::new(2.)).is_err());
+        assert_eq!(pyramid.insert(Tiling::new(-1.)).unwrap().tiling, Tiling::new(-1.));
+        assert_eq!(pyramid.insert(Tiling::new(0.)).unwrap().counts, tile_counts(&points, 0.).0);
+        assert_eq!(pyramid, Pyramid::from_precisions(&points, &[0., -1., 1.]).unwrap());
+    }
+}
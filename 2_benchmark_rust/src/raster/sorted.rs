@@ -0,0 +1,388 @@
+/*!
+ * Projection by sorting packed tile keys instead of hashing tiles.
+ *
+ * The tile of every point is packed into a `u64`, either row-major, i.e. by row `y` and then by
+ * column `x`, or along a Morton curve that interleaves the bits of both coordinates. The keys are
+ * radix-sorted and the runs of equal keys give the number of points of each tile, without hashing
+ * a tile per point. The tiles come out sorted by key, so that
+ * [`cluster_sorted_tiles`](fn.cluster_sorted_tiles.html) can walk them row by row and only compare
+ * each tile with the tiles of the few rows above it, without hashing or searching for neighbors.
+ *
+ * Select it with [`Projection::Sort`](../params/enum.Projection.html), e.g. in
+ * [`map_to_tiles_using`](../fn.map_to_tiles_using.html).
+ */
+
+use indexmap::IndexMap;
+
+use crate::{
+    ClusterParams,
+    Connectivity,
+    Point,
+    Tile,
+    TileCounts,
+    TileSet,
+    Tiling,
+    prime::TileMap,
+    union_find::DisjointSets,
+};
+
+
+/// How a tile is packed into a `u64` key, which sets the order of the sorted tiles.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum KeyOrder {
+    /// By row `y` and then by column `x`.
+    #[default]
+    RowMajor,
+    /// Along a Morton (Z-order) curve, which keeps tiles close in both directions close in the keys.
+    Morton,
+}
+
+
+/// Maps `i32::MIN..=i32::MAX` onto `0..=u32::MAX` in the same order.
+const BIAS: u32 = 0x8000_0000;
+
+
+/// Packs `tile` into a key, keys compare like the tiles in `order`.
+#[inline]
+pub fn pack(tile: Tile, order: KeyOrder) -> u64 {
+    let (x, y) = (tile.0 as u32 ^ BIAS, tile.1 as u32 ^ BIAS);
+    match order {
+        KeyOrder::RowMajor => (y as u64) << 32 | x as u64,
+        KeyOrder::Morton => spread(x) | spread(y) << 1,
+    }
+}
+
+
+/// The tile packed into `key`, see [`pack`](fn.pack.html).
+#[inline]
+pub fn unpack(key: u64, order: KeyOrder) -> Tile {
+    let (x, y) = match order {
+        KeyOrder::RowMajor => (key as u32, (key >> 32) as u32),
+        KeyOrder::Morton => (compact(key), compact(key >> 1)),
+    };
+    ((x ^ BIAS) as i32, (y ^ BIAS) as i32)
+}
+
+
+/// Moves the bits of `x` to the even bits of a `u64`.
+#[inline]
+fn spread(x: u32) -> u64 {
+    let mut x = x as u64;
+    x = (x | x << 16) & 0x0000_FFFF_0000_FFFF;
+    x = (x | x << 8) & 0x00FF_00FF_00FF_00FF;
+    x = (x | x << 4) & 0x0F0F_0F0F_0F0F_0F0F;
+    x = (x | x << 2) & 0x3333_3333_3333_3333;
+    x = (x | x << 1) & 0x5555_5555_5555_5555;
+    x
+}
+
+
+/// Gathers the even bits of `x`, the inverse of [`spread`](fn.spread.html).
+#[inline]
+fn compact(x: u64) -> u32 {
+    let mut x = x & 0x5555_5555_5555_5555;
+    x = (x | x >> 1) & 0x3333_3333_3333_3333;
+    x = (x | x >> 2) & 0x0F0F_0F0F_0F0F_0F0F;
+    x = (x | x >> 4) & 0x00FF_00FF_00FF_00FF;
+    x = (x | x >> 8) & 0x0000_FFFF_0000_FFFF;
+    x = (x | x >> 16) & 0x0000_0000_FFFF_FFFF;
+    x as u32
+}
+
+
+/// Sorts `keys` with a least significant digit radix sort over bytes.
+/// Bytes that are the same for every key, e.g. the high bytes of nearby tiles, are skipped.
+pub fn radix_sort(keys: &mut Vec<u64>) {
+    radix_sort_by_key(keys, |key| *key);
+}
+
+
+/// Sorts `items` by their `u64` key like [`radix_sort`](fn.radix_sort.html).
+/// The sort is stable, items with equal keys keep their order.
+pub fn radix_sort_by_key<T: Copy + Default>(items: &mut Vec<T>, key: impl Fn(&T) -> u64) {
+    let mut buffer = vec![T::default(); items.len()];
+    for shift in (0..64).step_by(8) {
+        let mut offsets = [0; 256];
+        for item in items.iter() {
+            offsets[(key(item) >> shift & 0xFF) as usize] += 1;
+        }
+        if offsets.contains(&items.len()) {
+            continue;
+        }
+        let mut start = 0;
+        for offset in offsets.iter_mut() {
+            let count = *offset;
+            *offset = start;
+            start += count;
+        }
+        for &item in items.iter() {
+            let digit = (key(&item) >> shift & 0xFF) as usize;
+            buffer[offsets[digit]] = item;
+            offsets[digit] += 1;
+        }
+        std::mem::swap(items, &mut buffer);
+    }
+}
+
+
+/// Tiles and their number of points, sorted by their keys.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct SortedCounts {
+    pub order: KeyOrder,
+    pub keys: Vec<u64>,
+    pub counts: Vec<usize>,
+}
+
+impl SortedCounts {
+    /// Sorts `keys` and counts the runs of equal keys.
+    pub fn from_keys(mut keys: Vec<u64>, order: KeyOrder) -> Self {
+        radix_sort(&mut keys);
+
+        let mut sorted = SortedCounts { order, keys: Vec::new(), counts: Vec::new() };
+        for key in keys {
+            match sorted.keys.last() {
+                Some(&last) if last == key => *sorted.counts.last_mut().unwrap() += 1,
+                _ => {
+                    sorted.keys.push(key);
+                    sorted.counts.push(1);
+                },
+            }
+        }
+        sorted
+    }
+
+    /// Merges the counts of several batches of points packed in the same order.
+    pub fn merge(batches: Vec<SortedCounts>) -> Self {
+        let order = batches.first().map_or(KeyOrder::default(), |b| b.order);
+        let mut runs: Vec<(u64, usize)> = batches
+            .into_iter()
+            .flat_map(|b| b.keys.into_iter().zip(b.counts))
+            .collect();
+        runs.sort_unstable_by_key(|(key, _)| *key);
+
+        let mut merged = SortedCounts { order, keys: Vec::new(), counts: Vec::new() };
+        for (key, count) in runs {
+            match merged.keys.last() {
+                Some(&last) if last == key => *merged.counts.last_mut().unwrap() += count,
+                _ => {
+                    merged.keys.push(key);
+                    merged.counts.push(count);
+                },
+            }
+        }
+        merged
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// The tiles in the order of their keys.
+    pub fn tiles(&self) -> impl Iterator<Item = Tile> + '_ {
+        self.keys.iter().map(move |key| unpack(*key, self.order))
+    }
+
+    /// The tiles with at least `threshold` points, still sorted.
+    pub fn significant(&self, threshold: usize) -> Self {
+        let (keys, counts) = self.keys
+            .iter()
+            .zip(self.counts.iter())
+            .filter(|(_, count)| **count >= threshold)
+            .unzip();
+        SortedCounts { order: self.order, keys, counts }
+    }
+
+    pub fn to_tile_set(&self) -> TileSet {
+        self.tiles().collect()
+    }
+
+    pub fn to_tile_counts(&self) -> TileCounts {
+        self.tiles().zip(self.counts.iter().cloned()).collect()
+    }
+}
+
+
+/// Sort version of [`tile_counts_with`](../fn.tile_counts_with.html).
+pub fn sorted_tile_counts(points: &[Point], tiling: &Tiling, order: KeyOrder) -> SortedCounts {
+    let keys = points.iter().map(|p| pack(p.to_tile(tiling), order)).collect();
+    SortedCounts::from_keys(keys, order)
+}
+
+
+/// Sort version of [`map_to_tile_counts_with`](../fn.map_to_tile_counts_with.html).
+pub fn map_to_sorted_tiles(points: &[Point], tiling: &Tiling, threshold: usize, order: KeyOrder) -> SortedCounts {
+    sorted_tile_counts(points, tiling, order).significant(threshold)
+}
+
+
+/// Sort version of [`prime::map_to_tiles_with`](../prime/fn.map_to_tiles_with.html).
+/// The tiles are inserted in the order of their keys and keep their points in input order.
+pub fn map_to_sorted_tile_map(points: &[Point], tiling: &Tiling, threshold: usize, order: KeyOrder) -> TileMap {
+    let mut keyed: Vec<(u64, usize)> = points
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (pack(p.to_tile(tiling), order), i))
+        .collect();
+    radix_sort_by_key(&mut keyed, |(key, _)| *key);
+
+    let mut tiles = IndexMap::new();
+    for run in keyed.chunk_by(|a, b| a.0 == b.0) {
+        if run.len() >= threshold {
+            let run_points = run.iter().map(|(_, i)| points[*i]).collect();
+            tiles.insert(unpack(run[0].0, order), run_points);
+        }
+    }
+    tiles
+}
+
+
+/// Clusters sorted tiles by walking them row by row. A tile is unioned with the tiles before it in
+/// its row and with the tiles of the rows above it within the reach of the connectivity, see
+/// [`union_find`](../union_find/index.html), so the engine of `params` is not used. Along each of
+/// these rows a window of candidate neighbors only moves forward, since the tiles of a row are
+/// sorted by `x`. Tiles packed in [`KeyOrder::Morton`](enum.KeyOrder.html) are first re-sorted
+/// into rows.
+pub fn cluster_sorted_tiles(tiles: &SortedCounts, params: &ClusterParams) -> Vec<TileSet> {
+    let tiles = row_major(tiles);
+    let reach = params.connectivity.reach() as i64;
+    // The largest distance in x to a neighbor in another row.
+    let reach_x = match params.connectivity {
+        Connectivity::Four => 0,
+        _ => reach,
+    };
+
+    let mut rows = Vec::new();
+    let mut start = 0;
+    for row in tiles.chunk_by(|a, b| a.1 == b.1) {
+        rows.push((row[0].1 as i64, start..start + row.len()));
+        start += row.len();
+    }
+
+    let mut sets = DisjointSets::new(tiles.len());
+    for (r, (y, row)) in rows.iter().enumerate() {
+        for i in row.start + 1..row.end {
+            if (tiles[i].0 as i64) - (tiles[i - 1].0 as i64) <= reach {
+                sets.union(i, i - 1);
+            }
+        }
+
+        for (_, above) in rows[..r].iter().rev().take_while(|(y_above, _)| y - y_above <= reach) {
+            let mut first = above.start;
+            for i in row.clone() {
+                let x = tiles[i].0 as i64;
+                while first < above.end && (tiles[first].0 as i64) < x - reach_x {
+                    first += 1;
+                }
+                let mut j = first;
+                while j < above.end && tiles[j].0 as i64 <= x + reach_x {
+                    sets.union(i, j);
+                    j += 1;
+                }
+            }
+        }
+    }
+
+    sets.groups()
+        .into_iter()
+        .filter(|group| group.len() >= params.min_cluster_size)
+        .map(|group| group.into_iter().map(|i| tiles[i]).collect())
+        .collect()
+}
+
+
+/// The tiles sorted by row `y` and then by column `x`.
+fn row_major(tiles: &SortedCounts) -> Vec<Tile> {
+    match tiles.order {
+        KeyOrder::RowMajor => tiles.tiles().collect(),
+        KeyOrder::Morton => {
+            let mut keys = tiles.tiles().map(|tile| pack(tile, KeyOrder::RowMajor)).collect();
+            radix_sort(&mut keys);
+            keys.into_iter().map(|key| unpack(key, KeyOrder::RowMajor)).collect()
+        },
+    }
+}
+
+
+
+/////////////////////////////////////////////////////////////////////////////////////
+/// Unit tests
+/////////////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Float, cluster_tiles_with, map_to_tile_counts_with, prime, tile_counts_with};
+
+    fn points() -> Vec<Point> {
+        (0..2000)
+            .map(|i| {
+                let i = i as Float;
+                Point::new((i * 0.37).sin() * (i % 7.) - 1., (i * 0.11).cos() * (i % 5.))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn packing() {
+        let tiles = [(0, 0), (-1, 0), (3, -7), (i32::MIN + 1, i32::MAX - 1), (-5, 12)];
+        for &order in [KeyOrder::RowMajor, KeyOrder::Morton].iter() {
+            for &tile in tiles.iter() {
+                assert_eq!(unpack(pack(tile, order), order), tile);
+            }
+        }
+        assert!(pack((5, -1), KeyOrder::RowMajor) < pack((-5, 0), KeyOrder::RowMajor));
+        assert!(pack((-1, 3), KeyOrder::RowMajor) < pack((0, 3), KeyOrder::RowMajor));
+        assert!(pack((1, 1), KeyOrder::Morton) < pack((2, 0), KeyOrder::Morton));
+
+        let mut keys: Vec<u64> = (0..1000u64).map(|i| i.wrapping_mul(0x9E37_79B9_7F4A_7C15)).collect();
+        let mut expected = keys.clone();
+        expected.sort_unstable();
+        radix_sort(&mut keys);
+        assert_eq!(keys, expected);
+
+        let mut pairs: Vec<(u64, usize)> = (0..1000).map(|i| (((i as u64 * 7919) % 13) << 40, i)).collect();
+        let mut expected = pairs.clone();
+        expected.sort_by_key(|(key, _)| *key);
+        radix_sort_by_key(&mut pairs, |(key, _)| *key);
+        assert_eq!(pairs, expected);
+    }
+
+    #[test]
+    fn same_as_hashing() {
+        let points = points();
+        let tiling = Tiling::new(0.5);
+        for &order in [KeyOrder::RowMajor, KeyOrder::Morton].iter() {
+            let sorted = sorted_tile_counts(&points, &tiling, order);
+            assert!(sorted.keys.windows(2).all(|w| w[0] < w[1]));
+            assert_eq!(sorted.to_tile_counts(), tile_counts_with(&points, &tiling));
+            assert_eq!(sorted.counts.iter().sum::<usize>(), points.len());
+
+            let significant = map_to_sorted_tiles(&points, &tiling, 3, order);
+            assert_eq!(significant.to_tile_counts(), map_to_tile_counts_with(&points, &tiling, 3));
+
+            let halves = points.split_at(700);
+            let merged = SortedCounts::merge(vec![
+                sorted_tile_counts(halves.0, &tiling, order),
+                sorted_tile_counts(halves.1, &tiling, order),
+            ]);
+            assert_eq!(merged, sorted);
+
+            let tile_map = map_to_sorted_tile_map(&points, &tiling, 3, order);
+            assert_eq!(tile_map, prime::map_to_tiles_with(&points, &tiling, 3));
+
+            for &connectivity in [Connectivity::Four, Connectivity::Eight, Connectivity::Radius(2)].iter() {
+                let params = ClusterParams::new(2).with_connectivity(connectivity);
+                let clusters = cluster_sorted_tiles(&significant, &params);
+                let expected = cluster_tiles_with(significant.to_tile_set(), &params);
+                assert!(!expected.is_empty());
+                assert_eq!(clusters.len(), expected.len());
+                for c in clusters.iter() {
+                    assert!(expected.contains(c));
+                }
+            }
+        }
+    }
+}
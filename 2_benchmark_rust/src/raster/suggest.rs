@@ -0,0 +1,258 @@
+/*!
+ * Suggestion of the parameters of RASTER from the data.
+ *
+ * The precision is the finest candidate at which at most half of the occupied tiles contain a
+ * single point. Finer tiles mostly split sparse tiles into tiles of a single point, which leaves
+ * nothing to tell dense and sparse regions apart.
+ *
+ * The threshold is the knee of the tile counts sorted in decreasing order, i.e. the point of the
+ * curve that is the furthest from the line between its first and last points, which separates the
+ * dense tiles from the sparse ones.
+ *
+ * The minimum cluster size keeps the clusters before the largest drop in the cluster sizes sorted
+ * in decreasing order, if the next cluster is at least twice as small. Otherwise every cluster is
+ * kept.
+ *
+ * The curves are returned with the suggestion so that the choice can be checked.
+ */
+
+use hashbrown::HashMap;
+
+use crate::{
+    Float,
+    Point,
+    TileCounts,
+    Tiling,
+    sweep::cluster_sweep,
+    tile_counts_with,
+};
+
+
+/// The tile counts of one candidate tiling.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TilingDiagnostics {
+    pub tiling: Tiling,
+    /// Number of tiles containing at least one point.
+    pub nr_tiles: usize,
+    pub mean_count: Float,
+    pub max_count: usize,
+    /// Fraction of the occupied tiles that contain a single point.
+    pub singleton_fraction: Float,
+    /// Number of tiles of each count, by increasing count.
+    pub histogram: Vec<(usize, usize)>,
+}
+
+
+/// Suggested parameters and the diagnostics behind them.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Suggestion {
+    /// Index of the suggested tiling among the candidates.
+    pub candidate: usize,
+    pub tiling: Tiling,
+    pub threshold: usize,
+    pub min_cluster_size: usize,
+    /// The tile counts of every candidate with finite cell sizes, in the order of the candidates.
+    pub candidates: Vec<TilingDiagnostics>,
+    /// Number of clusters of at least one tile for each threshold, with the suggested tiling.
+    pub clusters_by_threshold: Vec<(usize, usize)>,
+    /// Sizes of the clusters with the suggested tiling and threshold, in decreasing order.
+    pub cluster_sizes: Vec<usize>,
+}
+
+
+/// Suggests parameters among tiles of side `10^-precision` for each of `precisions`,
+/// see [`suggest_with`](fn.suggest_with.html).
+pub fn suggest(points: &[Point], precisions: &[Float]) -> Option<Suggestion> {
+    let candidates: Vec<_> = precisions.iter().map(|p| Tiling::new(*p)).collect();
+    suggest_with(points, &candidates)
+}
+
+
+/// Suggests a tiling among `candidates`, a threshold and a minimum cluster size.
+/// The candidates with a cell size that is not finite are skipped.
+/// Returns `None` without points or finite candidates.
+pub fn suggest_with(points: &[Point], candidates: &[Tiling]) -> Option<Suggestion> {
+    let finite: Vec<usize> = (0..candidates.len())
+        .filter(|&i| candidates[i].scalars.iter().all(|s| s.is_finite()))
+        .collect();
+    if points.is_empty() || finite.is_empty() {
+        return None;
+    }
+
+    let mut all_counts: Vec<TileCounts> = finite.iter().map(|&i| tile_counts_with(points, &candidates[i])).collect();
+    let diagnostics: Vec<_> = finite.iter().zip(all_counts.iter()).map(|(&i, c)| diagnose(&candidates[i], c)).collect();
+
+    // the finest candidate with few singletons, or the one with the fewest
+    let by_fraction = |a: &&TilingDiagnostics, b: &&TilingDiagnostics| {
+        a.singleton_fraction.total_cmp(&b.singleton_fraction)
+    };
+    let best = diagnostics
+        .iter()
+        .filter(|d| d.singleton_fraction <= 0.5)
+        .max_by(|a, b| a.tiling.scalars[0].total_cmp(&b.tiling.scalars[0]))
+        .or_else(|| diagnostics.iter().min_by(by_fraction))
+        .unwrap();
+    let position = diagnostics.iter().position(|d| std::ptr::eq(d, best)).unwrap();
+    let candidate = finite[position];
+    let counts = all_counts.swap_remove(position);
+
+    let mut sorted: Vec<usize> = counts.values().cloned().collect();
+    sorted.sort_unstable_by(|a, b| b.cmp(a));
+    let threshold = sorted[knee(&to_floats(&sorted))].max(1);
+
+    // one projection for every threshold up to twice the suggested one
+    let max_threshold = (2 * threshold).max(2);
+    let step = max_threshold.div_ceil(50);
+    let mut thresholds: Vec<usize> = (1..=max_threshold).step_by(step).collect();
+    if !thresholds.contains(&threshold) {
+        thresholds.push(threshold);
+        thresholds.sort_unstable();
+    }
+    let results = cluster_sweep(&counts, &thresholds, &[1]);
+    let clusters_by_threshold = results.iter().map(|r| (r.threshold, r.clusters.len())).collect();
+
+    let clusters = &results.iter().find(|r| r.threshold == threshold).unwrap().clusters;
+    let mut cluster_sizes: Vec<usize> = clusters.iter().map(|c| c.len()).collect();
+    cluster_sizes.sort_unstable_by(|a, b| b.cmp(a));
+    let min_cluster_size = largest_drop(&cluster_sizes)
+        .filter(|&i| cluster_sizes[i] >= 2 * cluster_sizes[i + 1])
+        .map_or(1, |i| cluster_sizes[i]);
+
+    Some(Suggestion {
+        candidate,
+        tiling: candidates[candidate],
+        threshold,
+        min_cluster_size,
+        candidates: diagnostics,
+        clusters_by_threshold,
+        cluster_sizes,
+    })
+}
+
+
+fn diagnose(tiling: &Tiling, counts: &TileCounts) -> TilingDiagnostics {
+    let mut histogram = HashMap::new();
+    for count in counts.values() {
+        *histogram.entry(*count).or_insert(0) += 1;
+    }
+    let mut histogram: Vec<(usize, usize)> = histogram.into_iter().collect();
+    histogram.sort_unstable();
+
+    let total: usize = counts.values().sum();
+    let singletons = histogram.first().filter(|(count, _)| *count == 1).map_or(0, |(_, n)| *n);
+    TilingDiagnostics {
+        tiling: *tiling,
+        nr_tiles: counts.len(),
+        mean_count: total as Float / counts.len().max(1) as Float,
+        max_count: counts.values().cloned().max().unwrap_or(0),
+        singleton_fraction: singletons as Float / counts.len().max(1) as Float,
+        histogram,
+    }
+}
+
+
+fn to_floats(xs: &[usize]) -> Vec<Float> {
+    xs.iter().map(|x| *x as Float).collect()
+}
+
+
+/// Index `i` of the largest ratio `xs[i] / xs[i + 1]` of decreasing positive numbers.
+fn largest_drop(xs: &[usize]) -> Option<usize> {
+    (0..xs.len().saturating_sub(1))
+        .max_by(|&i, &j| {
+            let ri = xs[i] as Float / xs[i + 1] as Float;
+            let rj = xs[j] as Float / xs[j + 1] as Float;
+            ri.total_cmp(&rj)
+        })
+}
+
+
+/// Index of the point of the curve `(i, ys[i])` that is the furthest from the line between its
+/// first and last points, after scaling both axes to `0..=1`. The first point if the curve is flat.
+fn knee(ys: &[Float]) -> usize {
+    if ys.len() < 3 {
+        return 0;
+    }
+    let last = ys.len() - 1;
+    let (y0, y1) = (ys[0], ys[last]);
+    let range = (y1 - y0).abs();
+    if range == 0. {
+        return 0;
+    }
+
+    let mut best = (0, 0.);
+    for (i, y) in ys.iter().enumerate() {
+        let x = i as Float / last as Float;
+        let chord = (y - y0) / (y1 - y0); // the chord goes from (0, 0) to (1, 1)
+        let distance = (chord - x).abs();
+        if distance > best.1 {
+            best = (i, distance);
+        }
+    }
+    best.0
+}
+
+
+
+/////////////////////////////////////////////////////////////////////////////////////
+/// Unit tests
+/////////////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn knees() {
+        assert_eq!(knee(&[]), 0);
+        assert_eq!(knee(&[3., 1.]), 0);
+        assert_eq!(knee(&[2., 2., 2.]), 0);
+        assert_eq!(knee(&[100., 90., 80., 5., 4., 3., 2., 1.]), 3);
+        assert_eq!(knee(&[10., 80., 95., 98., 99., 100.]), 1);
+
+        assert_eq!(largest_drop(&[]), None);
+        assert_eq!(largest_drop(&[5]), None);
+        assert_eq!(largest_drop(&[40, 35, 30, 3, 2, 1]), Some(2));
+    }
+
+    #[test]
+    fn dense_blobs_and_noise() {
+        // 20 blobs of 400 points on a 0.01 grid and 500 noise points
+        let mut points = Vec::new();
+        for b in 0..20 {
+            let (cx, cy) = ((b % 5) as Float * 2. + 0.013, (b / 5) as Float * 2. + 0.013);
+            for i in 0..20 {
+                for j in 0..20 {
+                    points.push(Point::new(cx + i as Float * 0.005, cy + j as Float * 0.005));
+                }
+            }
+        }
+        for i in 0..500 {
+            let i = i as Float;
+            points.push(Point::new((i * 0.731).fract() * 10., (i * 0.377).fract() * 8.));
+        }
+
+        let precisions = [0., 0.5, 1., 1.5, 2., 2.5, 3., 3.5];
+        let suggestion = suggest(&points, &precisions).unwrap();
+        assert_eq!(suggestion.candidates.len(), precisions.len());
+        assert_eq!(suggestion.tiling, Tiling::new(precisions[suggestion.candidate]));
+
+        assert_eq!(suggestion.candidate, 4); // the blobs have 4 points per tile at precision 2
+        let finite = suggest(&points, &[Float::NAN, 2.]).unwrap();
+        assert_eq!(finite.candidate, 1);
+        assert_eq!(finite.tiling, Tiling::new(2.));
+        assert_eq!(finite.candidates.len(), 1);
+        assert!(suggest(&points, &[Float::NAN, Float::INFINITY]).is_none());
+        let d = &suggestion.candidates[suggestion.candidate];
+        assert_eq!(d.histogram.iter().map(|(_, n)| n).sum::<usize>(), d.nr_tiles);
+        assert!(suggestion.threshold > 1);
+        assert!(suggestion.clusters_by_threshold.iter().any(|(t, _)| *t == suggestion.threshold));
+
+        // the suggestion finds the blobs
+        let (tiles, _) = crate::map_to_tiles(&points, precisions[suggestion.candidate], suggestion.threshold);
+        let clusters = crate::cluster_tiles(tiles, suggestion.min_cluster_size);
+        assert_eq!(clusters.len(), 20);
+
+        assert_eq!(suggest(&[], &precisions), None);
+        assert_eq!(suggest(&points, &[]), None);
+    }
+}
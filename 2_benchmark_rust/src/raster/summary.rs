@@ -0,0 +1,207 @@
+/*!
+ * Summary statistics of clusters in original coordinates.
+ *
+ * A cluster of RASTER' keeps its points, so its summary is exact. A cluster of RASTER only keeps
+ * its tiles, so its summary is computed from the number of points of each tile, see
+ * [`map_to_tile_counts`](../fn.map_to_tile_counts.html) and
+ * [`cluster_tile_counts`](../fn.cluster_tile_counts.html), and every point is assumed to lie at the
+ * center of its tile.
+ */
+
+use crate::{
+    Float,
+    Tile,
+    TileCounts,
+    TileSet,
+    Tiling,
+    prime::TileMap,
+};
+
+
+/// Size, extent and density of a cluster.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ClusterSummary {
+    pub nr_tiles: usize,
+    pub nr_points: usize,
+    /// Lower left corner of the bounding box.
+    pub min: (Float, Float),
+    /// Upper right corner of the bounding box.
+    pub max: (Float, Float),
+    pub centroid: (Float, Float),
+    /// Total area of the tiles of the cluster.
+    pub area: Float,
+    /// Number of points per unit of area.
+    pub density: Float,
+}
+
+impl ClusterSummary {
+    /// Summary of a cluster of [`cluster_tiles`](../fn.cluster_tiles.html), using the number of
+    /// points of each tile in `counts` and the `scalar` returned by the projection.
+    /// The bounding box covers the tiles of the cluster.
+    pub fn from_tiles(cluster: &TileSet, counts: &TileCounts, scalar: Float) -> Self {
+        Self::from_tiles_with(cluster, counts, &Tiling::from_scalars([scalar; 2]))
+    }
+
+    /// Same as [`from_tiles`](#method.from_tiles) but on the grid described by `tiling`.
+    /// Tiles missing from `counts` have no points.
+    pub fn from_tiles_with(cluster: &TileSet, counts: &TileCounts, tiling: &Tiling) -> Self {
+        let tiles = cluster.iter().map(|tile| (tile, counts.get(tile).copied().unwrap_or(0)));
+        Self::from_tile_iter(tiles, cluster.len(), tiling)
+    }
+
+    /// Summary of a cluster of [`cluster_tile_counts`](../fn.cluster_tile_counts.html) and the
+    /// `scalar` returned by the projection.
+    pub fn from_counts(cluster: &TileCounts, scalar: Float) -> Self {
+        Self::from_counts_with(cluster, &Tiling::from_scalars([scalar; 2]))
+    }
+
+    /// Same as [`from_counts`](#method.from_counts) but on the grid described by `tiling`.
+    pub fn from_counts_with(cluster: &TileCounts, tiling: &Tiling) -> Self {
+        Self::from_tile_iter(cluster.iter().map(|(tile, count)| (tile, *count)), cluster.len(), tiling)
+    }
+
+    fn from_tile_iter<'a>(
+        tiles: impl Iterator<Item = (&'a Tile, usize)>,
+        nr_tiles: usize,
+        tiling: &Tiling,
+    ) -> Self {
+        let mut summary = Self::empty(nr_tiles);
+        let mut sum = (0., 0.);
+
+        for (tile, count) in tiles {
+            let ((x0, x1), (y0, y1)) = summary.add_tile(tile, tiling);
+            summary.nr_points += count;
+            sum.0 += (x0 + x1) / 2. * count as Float;
+            sum.1 += (y0 + y1) / 2. * count as Float;
+        }
+        summary.finish(sum)
+    }
+
+    /// Summary of a cluster of [`prime::cluster_tiles`](../prime/fn.cluster_tiles.html) using the
+    /// retained points and the `scalar` returned by the projection.
+    /// The bounding box covers the points of the cluster.
+    pub fn from_points(cluster: &TileMap, scalar: Float) -> Self {
+        Self::from_points_with(cluster, &Tiling::from_scalars([scalar; 2]))
+    }
+
+    /// Same as [`from_points`](#method.from_points) but on the grid described by `tiling`.
+    pub fn from_points_with(cluster: &TileMap, tiling: &Tiling) -> Self {
+        let mut summary = Self::empty(cluster.len());
+        let mut sum = (0., 0.);
+
+        for (tile, points) in cluster {
+            summary.add_tile(tile, tiling);
+            summary.nr_points += points.len();
+        }
+        // the tiles only served for the area, the bounding box covers the points
+        summary.min = (Float::INFINITY, Float::INFINITY);
+        summary.max = (Float::NEG_INFINITY, Float::NEG_INFINITY);
+        for p in cluster.values().flatten() {
+            summary.min = (summary.min.0.min(p.0), summary.min.1.min(p.1));
+            summary.max = (summary.max.0.max(p.0), summary.max.1.max(p.1));
+            sum.0 += p.0;
+            sum.1 += p.1;
+        }
+        summary.finish(sum)
+    }
+
+    fn empty(nr_tiles: usize) -> Self {
+        ClusterSummary {
+            nr_tiles,
+            nr_points: 0,
+            min: (Float::INFINITY, Float::INFINITY),
+            max: (Float::NEG_INFINITY, Float::NEG_INFINITY),
+            centroid: (Float::NAN, Float::NAN),
+            area: 0.,
+            density: 0.,
+        }
+    }
+
+    /// Extends the bounding box and the area with `tile` and returns its bounds.
+    fn add_tile(&mut self, tile: &Tile, tiling: &Tiling) -> ((Float, Float), (Float, Float)) {
+        let (x0, x1) = tiling.tile_bounds(0, tile.0);
+        let (y0, y1) = tiling.tile_bounds(1, tile.1);
+        self.min = (self.min.0.min(x0), self.min.1.min(y0));
+        self.max = (self.max.0.max(x1), self.max.1.max(y1));
+        self.area += (x1 - x0) * (y1 - y0);
+        ((x0, x1), (y0, y1))
+    }
+
+    /// Computes the centroid from the sum of the coordinates of the points, and the density.
+    fn finish(mut self, sum: (Float, Float)) -> Self {
+        if self.nr_points > 0 {
+            let n = self.nr_points as Float;
+            self.centroid = (sum.0 / n, sum.1 / n);
+        }
+        if self.area > 0. {
+            self.density = self.nr_points as Float / self.area;
+        }
+        self
+    }
+}
+
+
+
+/////////////////////////////////////////////////////////////////////////////////////
+/// Unit tests
+/////////////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Point, Rounding, cluster_tile_counts, cluster_tiles, map_to_tile_counts, prime};
+
+    fn points() -> Vec<Point> {
+        vec![
+            Point::new(0.25, 0.25), Point::new(0.75, 0.75), Point::new(0.25, 0.75), // tile (0, 0)
+            Point::new(1.25, 0.25), Point::new(1.75, 0.25), Point::new(1.75, 0.75), // tile (1, 0)
+            Point::new(5.5, 5.5),
+        ]
+    }
+
+    #[test]
+    fn from_tiles() {
+        let points = points();
+        let (counts, scalar) = map_to_tile_counts(&points, 0., 2);
+        assert_eq!(counts.len(), 2);
+
+        let clusters = cluster_tiles(counts.keys().cloned().collect(), 1);
+        let summary = ClusterSummary::from_tiles(&clusters[0], &counts, scalar);
+        let counted = cluster_tile_counts(counts.clone(), 1);
+        assert_eq!(ClusterSummary::from_counts(&counted[0], scalar), summary);
+        assert_eq!(summary, ClusterSummary {
+            nr_tiles: 2,
+            nr_points: 6,
+            min: (0., 0.),
+            max: (2., 1.),
+            centroid: (1., 0.5),
+            area: 2.,
+            density: 3.,
+        });
+
+        let summary = ClusterSummary::from_tiles_with(
+            &clusters[0], &TileCounts::new(), &Tiling::new(0.).with_rounding(Rounding::Truncate),
+        );
+        assert_eq!(summary.nr_points, 0);
+        assert_eq!((summary.min, summary.max), ((-1., -1.), (2., 1.)));
+        assert_eq!((summary.area, summary.density), (6., 0.));
+        assert!(summary.centroid.0.is_nan());
+    }
+
+    #[test]
+    fn from_points() {
+        let points = points();
+        let (tiles, scalar) = prime::map_to_tiles(&points, 0., 2);
+        let clusters = prime::cluster_tiles(tiles, 1);
+
+        let summary = ClusterSummary::from_points(&clusters[0], scalar);
+        assert_eq!(summary, ClusterSummary {
+            nr_tiles: 2,
+            nr_points: 6,
+            min: (0.25, 0.25),
+            max: (1.75, 0.75),
+            centroid: (1., 0.5),
+            area: 2.,
+            density: 3.,
+        });
+    }
+}
@@ -0,0 +1,110 @@
+/*!
+ * Parameter sweeps from a single projection.
+ *
+ * The points are projected once with [`tile_counts`](../fn.tile_counts.html), which keeps every
+ * tile and its number of points. Each threshold then only filters these counts, and each minimum
+ * cluster size only filters the clusters of a threshold, so a sweep costs one projection and one
+ * clustering per threshold.
+ */
+
+use crate::{
+    ClusterParams,
+    TileCounts,
+    TileSet,
+    cluster_tiles_with,
+};
+
+
+/// The clusters of one combination of parameters.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SweepResult {
+    pub threshold: usize,
+    pub min_cluster_size: usize,
+    pub clusters: Vec<TileSet>,
+}
+
+
+/// Clusters `counts` for every combination of `thresholds` and `min_cluster_sizes`, in that order.
+/// Same as [`map_to_tiles`](../fn.map_to_tiles.html) followed by
+/// [`cluster_tiles`](../fn.cluster_tiles.html) for each combination.
+pub fn cluster_sweep(
+    counts: &TileCounts,
+    thresholds: &[usize],
+    min_cluster_sizes: &[usize],
+) -> Vec<SweepResult> {
+    cluster_sweep_with(counts, thresholds, min_cluster_sizes, &ClusterParams::new(1))
+}
+
+
+/// Same as [`cluster_sweep`](fn.cluster_sweep.html) but with the other parameters of the
+/// clustering step. The `min_cluster_size` of `params` is replaced by each of `min_cluster_sizes`.
+pub fn cluster_sweep_with(
+    counts: &TileCounts,
+    thresholds: &[usize],
+    min_cluster_sizes: &[usize],
+    params: &ClusterParams,
+) -> Vec<SweepResult> {
+    let mut results = Vec::with_capacity(thresholds.len() * min_cluster_sizes.len());
+    let all_sizes = ClusterParams { min_cluster_size: 0, ..*params };
+
+    for &threshold in thresholds {
+        let tiles: TileSet = counts
+            .iter()
+            .filter(|(_, count)| **count >= threshold )
+            .map(|(tile, _)| *tile)
+            .collect();
+        let clusters = cluster_tiles_with(tiles, &all_sizes);
+
+        for &min_cluster_size in min_cluster_sizes {
+            results.push(SweepResult {
+                threshold,
+                min_cluster_size,
+                clusters: clusters.iter().filter(|c| c.len() >= min_cluster_size).cloned().collect(),
+            });
+        }
+    }
+    results
+}
+
+
+
+/////////////////////////////////////////////////////////////////////////////////////
+/// Unit tests
+/////////////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Connectivity, Point, cluster_tiles, map_to_tiles, tile_counts};
+
+    #[test]
+    fn same_as_separate_runs() {
+        let points: Vec<_> = (0..200)
+            .map(|i| {
+                let i = i as f64;
+                Point::new((i * 0.37).sin() * (i % 7.), (i * 0.11).cos() * (i % 5.))
+            })
+            .collect();
+        let (counts, _) = tile_counts(&points, 0.5);
+        let thresholds = [1, 2, 3];
+        let min_sizes = [1, 2, 4];
+
+        let results = cluster_sweep(&counts, &thresholds, &min_sizes);
+        assert_eq!(results.len(), 9);
+        for result in results.iter() {
+            let (tiles, _) = map_to_tiles(&points, 0.5, result.threshold);
+            let mut expected = cluster_tiles(tiles, result.min_cluster_size);
+            let mut clusters = result.clusters.clone();
+            assert_eq!(clusters.len(), expected.len());
+            for c in clusters.drain(..) {
+                let i = expected.iter().position(|e| *e == c).unwrap();
+                expected.swap_remove(i);
+            }
+        }
+        assert_eq!((results[5].threshold, results[5].min_cluster_size), (2, 4));
+
+        let params = ClusterParams::new(100).with_connectivity(Connectivity::Four);
+        let four = cluster_sweep_with(&counts, &[1], &[1], &params);
+        let (tiles, _) = map_to_tiles(&points, 0.5, 1);
+        assert_eq!(four[0].clusters.len(), cluster_tiles_with(tiles, &ClusterParams { min_cluster_size: 1, ..params }).len());
+    }
+}
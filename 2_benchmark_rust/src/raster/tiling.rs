@@ -0,0 +1,355 @@
+/*!
+ * Description of the grid that points are projected onto.
+ *
+ * A [`Tiling`](struct.Tiling.html) is passed to the `*_with` versions of the projection functions,
+ * e.g. [`map_to_tiles_with`](../fn.map_to_tiles_with.html). The functions that only take a
+ * `precision` use [`Tiling::new`](struct.Tiling.html#method.new), i.e. square tiles whose side is a
+ * power of ten. Other tilings have an independent tile size per axis, given either as a precision
+ * or as a cell size.
+ *
+ * Tile coordinates are `i32`, so large coordinates at a high precision cannot be represented.
+ * The regular projection saturates silently, while the checked projection
+ * ([`Tiling::check`](struct.Tiling.html#method.check),
+ * [`try_map_to_tiles`](../fn.try_map_to_tiles.html)) returns a
+ * [`ProjectionError`](enum.ProjectionError.html).
+ */
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{
+    Float,
+    Point,
+    Tile,
+    nd::GridPoint,
+};
+
+
+/// Smallest tile coordinate of a checked projection. `i32::MIN` is reserved as the left sentinel
+/// of the slices in [`par`](../par/index.html).
+pub const MIN_COORDINATE: i32 = i32::MIN + 1;
+/// Largest tile coordinate of a checked projection. `i32::MAX` is reserved as the right sentinel
+/// of the slices in [`par`](../par/index.html).
+pub const MAX_COORDINATE: i32 = i32::MAX - 1;
+
+
+/// The reason a point could not be projected onto a tile.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ProjectionError {
+    /// The coordinate is NaN or infinite.
+    NotFinite(Float),
+    /// The scaled coordinate is outside `MIN_COORDINATE..=MAX_COORDINATE`.
+    OutOfRange { coordinate: Float, scalar: Float },
+}
+
+impl fmt::Display for ProjectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProjectionError::NotFinite(x) =>
+                write!(f, "coordinate {} cannot be projected onto a tile", x),
+            ProjectionError::OutOfRange { coordinate, scalar } =>
+                write!(f, "coordinate {} scaled by {} does not fit in a tile coordinate, lower the precision",
+                    coordinate, scalar),
+        }
+    }
+}
+
+impl Error for ProjectionError {}
+
+
+/// How a scaled coordinate is turned into a tile coordinate.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Rounding {
+    /// Rounds towards negative infinity, so every tile covers the same area.
+    #[default]
+    Floor,
+    /// Rounds towards zero, which is how the published benchmarks were computed.
+    /// Tile 0 covers twice the width of every other tile on each axis.
+    Truncate,
+}
+
+/// The grid of tiles: the scaling factor of each axis, the origin of the grid and how scaled
+/// coordinates are rounded. A tile is `1 / scalars[i]` wide along axis `i` and tile 0 starts at
+/// `origin[i]`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Tiling<const D: usize = 2> {
+    pub scalars: [Float; D],
+    pub origin: [Float; D],
+    pub rounding: Rounding,
+}
+
+impl<const D: usize> Tiling<D> {
+    /// Tiles of side `10^-precision` along every axis using floor rounding.
+    pub fn new(precision: Float) -> Self {
+        Self::from_precisions([precision; D])
+    }
+
+    /// Tiles of side `10^-precisions[i]` along axis `i`.
+    pub fn from_precisions(precisions: [Float; D]) -> Self {
+        let mut scalars = [0.; D];
+        for (s, p) in scalars.iter_mut().zip(precisions.iter()) {
+            *s = (10 as Float).powf(*p);
+        }
+        Self::from_scalars(scalars)
+    }
+
+    /// Tiles of side `1 / scalars[i]` along axis `i`, e.g. with the scalar returned by
+    /// [`map_to_tiles`](../fn.map_to_tiles.html).
+    pub fn from_scalars(scalars: [Float; D]) -> Self {
+        Tiling {
+            scalars,
+            origin: [0.; D],
+            rounding: Rounding::default(),
+        }
+    }
+
+    /// Tiles of side `cell_sizes[i]` along axis `i`, e.g. 25 for 25 m tiles in projected meters.
+    pub fn from_cell_sizes(cell_sizes: [Float; D]) -> Self {
+        let mut scalars = [0.; D];
+        for (s, c) in scalars.iter_mut().zip(cell_sizes.iter()) {
+            *s = 1. / c;
+        }
+        Self::from_scalars(scalars)
+    }
+
+    /// Same tiling but anchored at `origin` instead of 0.
+    pub fn with_origin(self, origin: [Float; D]) -> Self {
+        Tiling { origin, ..self }
+    }
+
+    /// Same tiling but with the origin moved by `shift[i]` tiles along axis `i`,
+    /// e.g. `[0.5, 0.5]` for a grid whose corners are at the centers of the original tiles.
+    pub fn shifted(self, shift: [Float; D]) -> Self {
+        let mut origin = self.origin;
+        for (axis, o) in origin.iter_mut().enumerate() {
+            *o += shift[axis] / self.scalars[axis];
+        }
+        Tiling { origin, ..self }
+    }
+
+    /// Same tiling but with another rounding, e.g. `Rounding::Truncate` for compatibility.
+    pub fn with_rounding(self, rounding: Rounding) -> Self {
+        Tiling { rounding, ..self }
+    }
+
+    /// The side of a tile along each axis.
+    pub fn cell_sizes(&self) -> [Float; D] {
+        let mut cell_sizes = [0.; D];
+        for (c, s) in cell_sizes.iter_mut().zip(self.scalars.iter()) {
+            *c = 1. / s;
+        }
+        cell_sizes
+    }
+
+    /// Maps a single coordinate along `axis` to its tile coordinate.
+    #[inline]
+    pub fn to_coordinate(&self, axis: usize, x: Float) -> i32 {
+        let scaled = (x - self.origin[axis]) * self.scalars[axis];
+        match self.rounding {
+            Rounding::Floor => scaled.floor() as i32,
+            Rounding::Truncate => scaled as i32,
+        }
+    }
+
+    /// Same as [`to_coordinate`](#method.to_coordinate) but fails instead of saturating.
+    #[inline]
+    pub fn checked_coordinate(&self, axis: usize, x: Float) -> Result<i32, ProjectionError> {
+        if !x.is_finite() {
+            return Err(ProjectionError::NotFinite(x));
+        }
+        let scalar = self.scalars[axis];
+        let scaled = match self.rounding {
+            Rounding::Floor => ((x - self.origin[axis]) * scalar).floor(),
+            Rounding::Truncate => ((x - self.origin[axis]) * scalar).trunc(),
+        };
+        if scaled < MIN_COORDINATE as Float || scaled > MAX_COORDINATE as Float {
+            Err(ProjectionError::OutOfRange { coordinate: x, scalar })
+        } else {
+            Ok(scaled as i32)
+        }
+    }
+
+    /// The range of original coordinates along `axis` that is projected onto tile `coordinate`.
+    /// With truncation, tile 0 is twice as wide as the others.
+    pub fn tile_bounds(&self, axis: usize, coordinate: i32) -> (Float, Float) {
+        let c = coordinate as Float;
+        let (low, high) = match self.rounding {
+            Rounding::Truncate if coordinate == 0 => (-1., 1.),
+            Rounding::Truncate if coordinate < 0 => (c - 1., c),
+            _ => (c, c + 1.),
+        };
+        let scalar = self.scalars[axis];
+        (self.origin[axis] + low / scalar, self.origin[axis] + high / scalar)
+    }
+
+    /// Checks that every point can be projected, e.g. before calling one of the unchecked
+    /// projection functions.
+    pub fn check<P: GridPoint<D>>(&self, points: &[P]) -> Result<(), ProjectionError> {
+        for p in points {
+            p.checked_to_tile(self)?;
+        }
+        Ok(())
+    }
+}
+
+impl Tiling {
+    /// Maps a point to the tile containing it.
+    #[inline]
+    pub fn project(&self, p: &Point) -> Tile {
+        p.to_tile(self)
+    }
+}
+
+
+/// The reason a tiling specification could not be parsed.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseTilingError(String);
+
+impl fmt::Display for ParseTilingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid tiling '{}', expected P, P1,P2,.. or cell=S, cell=S1,S2,..", self.0)
+    }
+}
+
+impl Error for ParseTilingError {}
+
+/// Parses a precision for every axis (`3.5`), a precision per axis (`3.5,3`),
+/// a cell size for every axis (`cell=0.002`) or a cell size per axis (`cell=0.002,0.001`).
+impl<const D: usize> FromStr for Tiling<D> {
+    type Err = ParseTilingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseTilingError(s.to_string());
+        let (is_cell_size, list) = match s.trim().strip_prefix("cell=") {
+            Some(list) => (true, list),
+            None => (false, s.trim()),
+        };
+        let values = list
+            .split(',')
+            .map(|v| v.trim().parse::<Float>().map_err(|_| err()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut per_axis = [0.; D];
+        match values.len() {
+            1 => per_axis = [values[0]; D],
+            n if n == D => per_axis.copy_from_slice(&values),
+            _ => return Err(err()),
+        }
+        if is_cell_size {
+            if per_axis.iter().any(|c| !(c.is_finite() && *c > 0.)) {
+                return Err(err());
+            }
+            Ok(Tiling::from_cell_sizes(per_axis))
+        } else {
+            if per_axis.iter().any(|p| !p.is_finite()) {
+                return Err(err());
+            }
+            Ok(Tiling::from_precisions(per_axis))
+        }
+    }
+}
+
+
+
+/////////////////////////////////////////////////////////////////////////////////////
+/// Unit tests
+/////////////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn floor_around_origin() {
+        let tiling = Tiling::new(1.);
+        assert_eq!(tiling.project(&Point::new(0.05, 0.05)), (0, 0));
+        assert_eq!(tiling.project(&Point::new(-0.05, 0.05)), (-1, 0));
+        assert_eq!(tiling.project(&Point::new(-0.05, -0.05)), (-1, -1));
+        assert_eq!(tiling.project(&Point::new(-0.1, 0.1)), (-1, 1));
+    }
+
+    #[test]
+    fn truncate_around_origin() {
+        let tiling = Tiling::new(1.).with_rounding(Rounding::Truncate);
+        assert_eq!(tiling.project(&Point::new(0.05, 0.05)), (0, 0));
+        assert_eq!(tiling.project(&Point::new(-0.05, 0.05)), (0, 0));
+        assert_eq!(tiling.project(&Point::new(-0.05, -0.05)), (0, 0));
+        assert_eq!(tiling.project(&Point::new(-0.15, 0.15)), (-1, 1));
+    }
+
+    #[test]
+    fn checked_precision() {
+        let points = vec![Point::new(-180., -90.), Point::new(180., 90.)];
+        assert_eq!(Tiling::new(7.).check(&points), Ok(()));
+
+        let tiling = Tiling::new(8.);
+        assert_eq!(
+            tiling.check(&points),
+            Err(ProjectionError::OutOfRange { coordinate: -180., scalar: tiling.scalars[0] })
+        );
+        // The unchecked projection saturates
+        assert_eq!(tiling.project(&points[1]), (i32::MAX, i32::MAX));
+    }
+
+    #[test]
+    fn checked_not_finite() {
+        let tiling = Tiling::new(1.);
+        assert!(tiling.check(&[Point::new(0., Float::NAN)]).is_err());
+        assert_eq!(
+            tiling.checked_coordinate(0, Float::INFINITY),
+            Err(ProjectionError::NotFinite(Float::INFINITY))
+        );
+        assert_eq!(tiling.checked_coordinate(1, -0.05), Ok(-1));
+    }
+
+    #[test]
+    fn per_axis() {
+        let tiling = Tiling::from_precisions([1., 0.]);
+        assert_eq!(tiling.project(&Point::new(0.25, 2.5)), (2, 2));
+        assert_eq!(tiling.project(&Point::new(-0.25, -2.5)), (-3, -3));
+
+        let tiling = Tiling::from_cell_sizes([25., 50.]);
+        assert_eq!(tiling.project(&Point::new(30., 30.)), (1, 0));
+        assert_eq!(tiling.project(&Point::new(-30., 130.)), (-2, 2));
+        assert_eq!(Tiling::from_cell_sizes([0.5, 4.]).cell_sizes(), [0.5, 4.]);
+    }
+
+    #[test]
+    fn origin() {
+        let tiling = Tiling::new(0.).with_origin([0.5, -0.5]);
+        assert_eq!(tiling.project(&Point::new(0.4, 0.4)), (-1, 0));
+        assert_eq!(tiling.project(&Point::new(0.6, -0.6)), (0, -1));
+        assert_eq!(Tiling::new(0.).shifted([0.5, -0.5]), tiling);
+
+        let tiling = Tiling::from_cell_sizes([2., 4.]).shifted([0.5, 0.5]);
+        assert_eq!(tiling.origin, [1., 2.]);
+        assert_eq!(tiling.checked_coordinate(1, 1.9), Ok(-1));
+    }
+
+    #[test]
+    fn bounds() {
+        let tiling = Tiling::<2>::from_cell_sizes([0.5, 4.]);
+        assert_eq!(tiling.tile_bounds(0, 3), (1.5, 2.));
+        assert_eq!(tiling.tile_bounds(1, -1), (-4., 0.));
+        assert_eq!(tiling.with_origin([1., 1.]).tile_bounds(1, -1), (-3., 1.));
+
+        let tiling = tiling.with_rounding(Rounding::Truncate);
+        assert_eq!(tiling.tile_bounds(0, 3), (1.5, 2.));
+        assert_eq!(tiling.tile_bounds(0, 0), (-0.5, 0.5));
+        assert_eq!(tiling.tile_bounds(1, -1), (-8., -4.));
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("3.5".parse(), Ok(Tiling::<2>::new(3.5)));
+        assert_eq!("3, 2".parse(), Ok(Tiling::from_precisions([3., 2.])));
+        assert_eq!("cell=25".parse(), Ok(Tiling::<3>::from_cell_sizes([25.; 3])));
+        assert_eq!("cell=0.5,2".parse(), Ok(Tiling::from_cell_sizes([0.5, 2.])));
+        assert!("3,2,1".parse::<Tiling>().is_err());
+        assert!("cell=0".parse::<Tiling>().is_err());
+        assert!("cell=".parse::<Tiling>().is_err());
+        assert!("p3".parse::<Tiling>().is_err());
+        assert!("nan".parse::<Tiling>().is_err());
+        assert!("3,inf".parse::<Tiling>().is_err());
+    }
+}
@@ -0,0 +1,231 @@
+/*!
+ * Hierarchy of clusters over several resolutions.
+ *
+ * The points are clustered at a series of precisions with
+ * [`map_to_tiles_with`](../fn.map_to_tiles_with.html) and
+ * [`cluster_tiles_with`](../fn.cluster_tiles_with.html). Each cluster of a level is linked to the
+ * cluster of the next coarser level that contains it, which gives a forest that can be cut at any
+ * level or traversed from coarse to fine clusters.
+ *
+ * A fine tile is contained in the coarse tile holding its center, so the grids do not need to
+ * nest. A fine cluster is contained in the coarse cluster holding most of its tiles, and it has no
+ * parent if none of its tiles are in a coarse cluster.
+ */
+
+use hashbrown::HashMap;
+
+use crate::{
+    ClusterId,
+    ClusterParams,
+    Point,
+    TileSet,
+    Tiling,
+    cluster_tiles_with,
+    map_to_tiles_with,
+    nd,
+};
+
+
+/// The clusters of one resolution and their links to the adjacent levels.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TreeLevel {
+    pub tiling: Tiling,
+    pub clusters: Vec<TileSet>,
+    /// The cluster of the previous, coarser, level containing each cluster.
+    pub parents: Vec<Option<ClusterId>>,
+    /// The clusters of the next, finer, level contained in each cluster.
+    pub children: Vec<Vec<ClusterId>>,
+}
+
+
+/// Clusters at several resolutions, ordered from the coarsest to the finest level like the levels
+/// of a [`Pyramid`](../pyramid/struct.Pyramid.html).
+#[derive(Debug, PartialEq, Clone)]
+pub struct ClusterTree {
+    pub levels: Vec<TreeLevel>,
+}
+
+impl ClusterTree {
+    /// Clusters `points` on each of `tilings` and links the clusters of adjacent levels.
+    pub fn new(points: &[Point], tilings: &[Tiling], threshold: usize, params: &ClusterParams) -> Self {
+        let levels = tilings
+            .iter()
+            .map(|tiling| {
+                let tiles = map_to_tiles_with(points, tiling, threshold);
+                (*tiling, cluster_tiles_with(tiles, params))
+            })
+            .collect();
+        Self::from_levels(levels)
+    }
+
+    /// Links clusters that were computed separately, e.g. with another threshold per level.
+    /// The levels are sorted from the coarsest to the finest tiling.
+    pub fn from_levels(mut levels: Vec<(Tiling, Vec<TileSet>)>) -> Self {
+        levels.sort_by(|(a, _), (b, _)| a.scalars[0].total_cmp(&b.scalars[0]));
+
+        let mut tree: Vec<TreeLevel> = Vec::with_capacity(levels.len());
+        for (tiling, clusters) in levels {
+            let parents = match tree.last_mut() {
+                None => vec![None; clusters.len()],
+                Some(coarse) => {
+                    let parents = find_parents(&clusters, &tiling, &coarse.clusters, &coarse.tiling);
+                    for (child, parent) in parents.iter().enumerate() {
+                        if let Some(parent) = parent {
+                            coarse.children[*parent].push(child);
+                        }
+                    }
+                    parents
+                },
+            };
+            tree.push(TreeLevel {
+                tiling,
+                children: vec![vec![]; clusters.len()],
+                clusters,
+                parents,
+            });
+        }
+        ClusterTree { levels: tree }
+    }
+
+    /// The clusters of `level` that have no parent.
+    pub fn roots(&self, level: usize) -> Vec<ClusterId> {
+        self.levels[level].parents
+            .iter()
+            .enumerate()
+            .filter(|(_, parent)| parent.is_none())
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    /// The cluster of the coarser level `ancestor_level` that contains cluster `id` of `level`,
+    /// following the parents.
+    pub fn ancestor(&self, level: usize, id: ClusterId, ancestor_level: usize) -> Option<ClusterId> {
+        let mut id = id;
+        for l in (ancestor_level + 1..=level).rev() {
+            id = self.levels[l].parents[id]?;
+        }
+        Some(id)
+    }
+
+    /// The clusters of the finer level `descendant_level` contained in cluster `id` of `level`.
+    pub fn descendants(&self, level: usize, id: ClusterId, descendant_level: usize) -> Vec<ClusterId> {
+        let mut ids = vec![id];
+        for l in level..descendant_level {
+            ids = ids.iter().flat_map(|id| self.levels[l].children[*id].iter().cloned()).collect();
+        }
+        ids
+    }
+}
+
+
+/// The coarse cluster containing most tiles of each fine cluster.
+fn find_parents(
+    fine: &[TileSet],
+    fine_tiling: &Tiling,
+    coarse: &[TileSet],
+    coarse_tiling: &Tiling,
+) -> Vec<Option<ClusterId>> {
+    let cluster_of = nd::cluster_lookup(coarse);
+
+    fine.iter()
+        .map(|cluster| {
+            let mut votes: HashMap<ClusterId, usize> = HashMap::new();
+            for &(x, y) in cluster {
+                let (x0, x1) = fine_tiling.tile_bounds(0, x);
+                let (y0, y1) = fine_tiling.tile_bounds(1, y);
+                let center = Point::new((x0 + x1) / 2., (y0 + y1) / 2.);
+                if let Some(id) = cluster_of.get(&coarse_tiling.project(&center)) {
+                    *votes.entry(*id).or_insert(0) += 1;
+                }
+            }
+            // ties go to the lowest id
+            votes.into_iter().max_by_key(|(id, count)| (*count, std::cmp::Reverse(*id))).map(|(id, _)| id)
+        })
+        .collect()
+}
+
+
+
+/////////////////////////////////////////////////////////////////////////////////////
+/// Unit tests
+/////////////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two dense blobs close to each other and a third one further away.
+    fn points() -> Vec<Point> {
+        let mut points = Vec::new();
+        for &(cx, cy) in [(1.21, 1.21), (1.76, 1.21), (6.51, 6.51)].iter() {
+            for i in 0..5 {
+                for j in 0..5 {
+                    points.push(Point::new(cx + i as f64 * 0.02, cy + j as f64 * 0.02));
+                }
+            }
+        }
+        points
+    }
+
+    #[test]
+    fn two_levels() {
+        let points = points();
+        let tilings = [Tiling::new(1.), Tiling::new(0.)];
+        let tree = ClusterTree::new(&points, &tilings, 2, &ClusterParams::new(1));
+        assert_eq!(tree.levels.len(), 2);
+        assert_eq!(tree.levels[0].tiling, Tiling::new(0.));
+
+        let coarse = &tree.levels[0];
+        let fine = &tree.levels[1];
+        assert_eq!(coarse.clusters.len(), 2);
+        assert_eq!(fine.clusters.len(), 3);
+
+        let big = coarse.clusters.iter().position(|c| c.contains(&(1, 1))).unwrap();
+        let far = 1 - big;
+        assert_eq!(coarse.parents, vec![None, None]);
+        assert_eq!(tree.roots(0), vec![0, 1]);
+        assert_eq!(coarse.children[big].len(), 2);
+        assert_eq!(coarse.children[far].len(), 1);
+
+        for (id, cluster) in fine.clusters.iter().enumerate() {
+            let parent = if cluster.contains(&(65, 65)) { far } else { big };
+            assert_eq!(fine.parents[id], Some(parent));
+            assert!(coarse.children[parent].contains(&id));
+        }
+    }
+
+    #[test]
+    fn traversal() {
+        let points = points();
+        let tilings = [Tiling::new(1.), Tiling::new(0.), Tiling::from_cell_sizes([0.5, 0.5])];
+        let tree = ClusterTree::new(&points, &tilings, 2, &ClusterParams::new(1));
+        assert_eq!(tree.levels.len(), 3);
+        let scalars: Vec<_> = tree.levels.iter().map(|l| l.tiling.scalars[0]).collect();
+        assert_eq!(scalars, vec![1., 2., 10.]);
+
+        for id in 0..tree.levels[2].clusters.len() {
+            let ancestor = tree.ancestor(2, id, 0).unwrap();
+            assert!(tree.descendants(0, ancestor, 2).contains(&id));
+            assert_eq!(tree.ancestor(2, id, 2), Some(id));
+        }
+        let total: usize = (0..tree.levels[0].clusters.len())
+            .map(|id| tree.descendants(0, id, 2).len())
+            .sum();
+        assert_eq!(total, tree.levels[2].clusters.len());
+
+        // a coarse level without clusters leaves the finer clusters without parents
+        let tree = ClusterTree::from_levels(vec![
+            (Tiling::new(1.), tree.levels[2].clusters.clone()),
+            (Tiling::new(0.), vec![]),
+        ]);
+        assert_eq!(tree.roots(1).len(), tree.levels[1].clusters.len());
+        assert_eq!(tree.ancestor(1, 0, 0), None);
+
+        // a NaN scalar sorts as the finest level instead of panicking
+        let tree = ClusterTree::from_levels(vec![
+            (Tiling::new(f64::NAN), vec![]),
+            (Tiling::new(0.), tree.levels[0].clusters.clone()),
+        ]);
+        assert_eq!(tree.levels[0].tiling, Tiling::new(0.));
+        assert!(tree.levels[1].clusters.is_empty());
+    }
+}
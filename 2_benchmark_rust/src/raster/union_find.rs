@@ -0,0 +1,161 @@
+/*!
+ * Clustering of significant tiles with a disjoint-set forest.
+ *
+ * Instead of growing one cluster at a time by removing neighbors from the set of tiles, every tile
+ * gets the index it has in its set and the indices of neighboring tiles are unioned. The clusters
+ * are the same as with the breadth-first search of [`cluster_tiles`](../fn.cluster_tiles.html),
+ * they are ordered by the first of their tiles in the input. Select it with
+ * [`ClusterEngine::UnionFind`](../params/enum.ClusterEngine.html).
+ *
+ * The concurrent versions also join the clusters touching the borders of the slices by unioning
+ * their ids across the seams, see [`par::cluster_tiles_with`](../par/fn.cluster_tiles_with.html).
+ */
+
+use hashbrown::HashMap;
+use indexmap::{IndexMap, IndexSet};
+
+use crate::{
+    Connectivity,
+    nd::GridTile,
+};
+
+
+/// A forest of disjoint sets over the ids `0..len`, with path halving and union by size.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DisjointSets {
+    parents: Vec<usize>,
+    sizes: Vec<usize>,
+}
+
+impl DisjointSets {
+    /// `len` sets of one id each.
+    pub fn new(len: usize) -> Self {
+        DisjointSets {
+            parents: (0..len).collect(),
+            sizes: vec![1; len],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.parents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parents.is_empty()
+    }
+
+    /// Adds a set of one new id and returns the id.
+    pub fn push(&mut self) -> usize {
+        self.parents.push(self.parents.len());
+        self.sizes.push(1);
+        self.parents.len() - 1
+    }
+
+    /// The representative of the set containing `x`.
+    pub fn find(&mut self, mut x: usize) -> usize {
+        while self.parents[x] != x {
+            self.parents[x] = self.parents[self.parents[x]];
+            x = self.parents[x];
+        }
+        x
+    }
+
+    /// Merges the sets containing `a` and `b`. Returns false if they were already the same set.
+    pub fn union(&mut self, a: usize, b: usize) -> bool {
+        let (mut ra, mut rb) = (self.find(a), self.find(b));
+        if ra == rb {
+            return false;
+        }
+        if self.sizes[ra] < self.sizes[rb] {
+            std::mem::swap(&mut ra, &mut rb);
+        }
+        self.parents[rb] = ra;
+        self.sizes[ra] += self.sizes[rb];
+        true
+    }
+
+    /// The ids of every set, each set in increasing order and the sets ordered by their first id.
+    pub fn groups(&mut self) -> Vec<Vec<usize>> {
+        let mut group_of = HashMap::new();
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        for x in 0..self.len() {
+            let root = self.find(x);
+            let group = *group_of.entry(root).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[group].push(x);
+        }
+        groups
+    }
+}
+
+
+/// The sets of neighboring `tiles`, as groups of indices into `tiles`.
+fn components<T: GridTile>(
+    tiles: &[T],
+    index_of: impl Fn(&T) -> Option<usize>,
+    connectivity: Connectivity,
+) -> Vec<Vec<usize>> {
+    let mut sets = DisjointSets::new(tiles.len());
+    for (i, tile) in tiles.iter().enumerate() {
+        for n in tile.neighbors(connectivity) {
+            if let Some(j) = index_of(&n) {
+                // every pair of neighbors is seen from both sides
+                if j < i {
+                    sets.union(i, j);
+                }
+            }
+        }
+    }
+    sets.groups()
+}
+
+
+/// Union-find version of [`nd::cluster_tiles_with`](../nd/fn.cluster_tiles_with.html), keeping
+/// every cluster regardless of its size.
+pub fn cluster_tiles<T: GridTile>(tiles: IndexSet<T>, connectivity: Connectivity) -> Vec<IndexSet<T>> {
+    let tiles: Vec<T> = tiles.into_iter().collect();
+    let index: HashMap<T, usize> = tiles.iter().enumerate().map(|(i, t)| (*t, i)).collect();
+
+    components(&tiles, |t| index.get(t).copied(), connectivity)
+        .into_iter()
+        .map(|group| group.into_iter().map(|i| tiles[i]).collect())
+        .collect()
+}
+
+
+/// Union-find version of [`nd::prime::cluster_tiles_with`](../nd/prime/fn.cluster_tiles_with.html),
+/// keeping every cluster regardless of its size. The values are kept with their tiles.
+pub fn cluster_tile_map<T: GridTile, V>(tiles: IndexMap<T, V>, connectivity: Connectivity) -> Vec<IndexMap<T, V>> {
+    let keys: Vec<T> = tiles.keys().cloned().collect();
+    let groups = components(&keys, |t| tiles.get_index_of(t), connectivity);
+
+    let mut entries: Vec<Option<(T, V)>> = tiles.into_iter().map(Some).collect();
+    groups
+        .into_iter()
+        .map(|group| group.into_iter().map(|i| entries[i].take().unwrap()).collect())
+        .collect()
+}
+
+
+
+/////////////////////////////////////////////////////////////////////////////////////
+/// Unit tests
+/////////////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disjoint_sets() {
+        let mut sets = DisjointSets::new(6);
+        assert!(sets.union(4, 1));
+        assert!(sets.union(1, 3));
+        assert!(!sets.union(3, 4));
+        assert!(sets.union(5, 2));
+        assert_eq!(sets.find(3), sets.find(4));
+        assert_ne!(sets.find(0), sets.find(1));
+        assert_eq!(sets.groups(), vec![vec![0], vec![1, 3, 4], vec![2, 5]]);
+    }
+}